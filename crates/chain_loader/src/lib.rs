@@ -0,0 +1,193 @@
+use std::{cmp, time::Duration};
+
+use anyhow::{anyhow, Result};
+use db_handler::{ChainProgress, DBHandler, InnerBlock};
+use futures::{stream, StreamExt};
+use metrics::gauge;
+use networking::rpc_handler::RpcHandler;
+use params::network::Network;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Interval between two checks of the chain tip once all confirmed blocks are loaded.
+pub const FOLLOW_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Inclusive `(start, end)` batches covering `start..=end`.
+pub fn batches(start: u64, end: u64, batch: u64) -> Vec<(u64, u64)> {
+    (start..=end)
+        .step_by(batch as usize)
+        .map(|from| (from, cmp::min(from + batch - 1, end)))
+        .collect()
+}
+
+pub async fn check_genesis<N: Network>(rpc_handler: &RpcHandler) -> Result<()> {
+    let genesis_block = rpc_handler
+        .get_latest_block()
+        .await?
+        .data
+        .genesis_block_identifier;
+    if !genesis_block
+        .hash
+        .eq_ignore_ascii_case(N::GENESIS_BLOCK_HASH)
+    {
+        return Err(anyhow!("Genesis block doesn't match"));
+    }
+    Ok(())
+}
+
+/// Blocks of `start..=end`, reusing the ones already in db.
+async fn fetch_blocks(
+    db_handler: &(dyn DBHandler + Send + Sync),
+    rpc_handler: &RpcHandler,
+    start: u64,
+    end: u64,
+) -> Result<(Vec<InnerBlock>, bool)> {
+    if let Ok(blocks) = db_handler.get_blocks(start as i64, end as i64).await {
+        return Ok((blocks, true));
+    }
+    let blocks = rpc_handler
+        .get_blocks(start, end)
+        .await?
+        .data
+        .blocks
+        .into_iter()
+        .map(|item| item.block.to_inner())
+        .collect();
+    Ok((blocks, false))
+}
+
+/// Drop the last `REORG_DEPTH` loaded blocks, they are loaded again from the canonical chain.
+pub async fn rollback<N: Network>(
+    db_handler: &(dyn DBHandler + Send + Sync),
+    progress: &ChainProgress,
+) -> Result<()> {
+    let sequence = cmp::max(progress.sequence - N::REORG_DEPTH, 1);
+    warn!(
+        "Chain reorg below block {}, rolling back to {}",
+        progress.sequence, sequence
+    );
+    db_handler
+        .remove_blocks(sequence + 1, progress.sequence)
+        .await?;
+    let block = db_handler
+        .get_blocks(sequence, sequence)
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow!("Block {} missing", sequence))?;
+    db_handler
+        .update_chain_progress(ChainProgress {
+            hash: block.hash,
+            sequence,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Load blocks from the current progress up to `target`, fetching batches in parallel
+/// and saving them in chain order. Returns false if a reorg was rolled back.
+pub async fn load_blocks<N: Network>(
+    db_handler: &(dyn DBHandler + Send + Sync),
+    rpc_handler: &RpcHandler,
+    target: u64,
+    parallelism: usize,
+) -> Result<bool> {
+    let mut progress = db_handler.get_chain_progress().await?;
+    let start = progress.as_ref().map_or(1, |p| p.sequence as u64 + 1);
+    if start > target {
+        return Ok(true);
+    }
+    let mut fetched = stream::iter(batches(start, target, N::PRIMARY_BATCH))
+        .map(|(start, end)| fetch_blocks(db_handler, rpc_handler, start, end))
+        .buffered(cmp::max(parallelism, 1));
+    while let Some(result) = fetched.next().await {
+        let (mut blocks, mut saved) = result?;
+        let parent = progress.as_ref().map(|p| p.hash.as_str());
+        if saved && InnerBlock::broken_link(&blocks, parent).is_some() {
+            // stale local blocks, try the node instead
+            let (start, end) = match (blocks.first(), blocks.last()) {
+                (Some(first), Some(last)) => (first.sequence as u64, last.sequence as u64),
+                _ => continue,
+            };
+            blocks = rpc_handler
+                .get_blocks(start, end)
+                .await?
+                .data
+                .blocks
+                .into_iter()
+                .map(|item| item.block.to_inner())
+                .collect();
+            saved = false;
+        }
+        if InnerBlock::broken_link(&blocks, parent).is_some() {
+            match &progress {
+                Some(progress) => rollback::<N>(db_handler, progress).await?,
+                None => return Err(anyhow!("Rpc blocks don't form a chain")),
+            }
+            return Ok(false);
+        }
+        let last = match blocks.last() {
+            Some(block) => ChainProgress {
+                hash: block.hash.clone(),
+                sequence: block.sequence,
+            },
+            None => return Err(anyhow!("Empty blocks batch from {}", start)),
+        };
+        if !saved {
+            db_handler.save_blocks(blocks).await?;
+        }
+        db_handler.update_chain_progress(last.clone()).await?;
+        gauge!("chain_loader_progress").set(last.sequence as f64);
+        if last.sequence % 10000 == 0 {
+            info!("Loaded blocks up to {}", last.sequence);
+        }
+        progress = Some(last);
+    }
+    Ok(true)
+}
+
+/// Backfill `wallet.blocks` and keep following the tip, `REORG_DEPTH` blocks behind it.
+pub async fn run_chain_loader<N: Network>(
+    db_handler: Box<dyn DBHandler + Send + Sync>,
+    rpc_handler: RpcHandler,
+    parallelism: usize,
+) -> Result<()> {
+    check_genesis::<N>(&rpc_handler).await?;
+    loop {
+        let target = match rpc_handler.get_latest_block().await {
+            Ok(latest) => {
+                let head = latest
+                    .data
+                    .current_block_identifier
+                    .index
+                    .parse::<i64>()
+                    .unwrap_or(0);
+                gauge!("chain_loader_head").set(head as f64);
+                cmp::max(head - N::REORG_DEPTH, 0) as u64
+            }
+            Err(e) => {
+                error!("Failed to get latest block, {}", e);
+                sleep(FOLLOW_INTERVAL).await;
+                continue;
+            }
+        };
+        match load_blocks::<N>(db_handler.as_ref(), &rpc_handler, target, parallelism).await {
+            // reorg rolled back, load again right away
+            Ok(false) => continue,
+            Ok(true) => {}
+            Err(e) => error!("Failed to load blocks up to {}, {}", target, e),
+        }
+        sleep(FOLLOW_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::batches;
+
+    #[test]
+    fn batches_should_cover_range() {
+        assert_eq!(batches(1, 250, 100), vec![(1, 100), (101, 200), (201, 250)]);
+        assert_eq!(batches(1, 201, 100), vec![(1, 100), (101, 200), (201, 201)]);
+        assert!(batches(5, 4, 100).is_empty());
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+use chain_loader::run_chain_loader;
+use db_handler::load_db;
+use networking::rpc_handler::RpcHandler;
+use params::{mainnet::Mainnet, network::Network, testnet::Testnet};
+use utils::{
+    handle_signals, initialize_logger, initialize_logger_filter, initialize_metrics, ChainLoader,
+    EnvFilter, Parser,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let ChainLoader {
+        dbconfig,
+        node,
+        network,
+        parallelism,
+        metrics,
+        verbosity,
+    } = ChainLoader::parse();
+    initialize_logger(verbosity);
+    initialize_logger_filter(EnvFilter::from_default_env());
+    handle_signals().await?;
+    initialize_metrics(metrics)?;
+    let db_handler = load_db(dbconfig).unwrap();
+    let rpc_handler = RpcHandler::new(node);
+    rpc_handler.initialize_health_checker().await;
+    match network {
+        Mainnet::ID => run_chain_loader::<Mainnet>(db_handler, rpc_handler, parallelism).await?,
+        Testnet::ID => run_chain_loader::<Testnet>(db_handler, rpc_handler, parallelism).await?,
+        _ => panic!("Invalid network used"),
+    }
+    Ok(())
+}
@@ -2,7 +2,11 @@ mod config;
 mod pg_handler;
 mod redis_handler;
 
-use std::{path::Path, str::FromStr};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::Path,
+    str::FromStr,
+};
 
 use anyhow::anyhow;
 pub use config::DbConfig;
@@ -17,6 +21,9 @@ use serde::{Deserialize, Serialize};
 use sqlx::{postgres::PgConnectOptions, ConnectOptions, FromRow, PgPool};
 use substring::Substring;
 
+/// Seconds a scanner instance holds a scan session without renewing its lease.
+pub const SCAN_LEASE_SECS: i64 = 120;
+
 #[async_trait::async_trait]
 pub trait DBHandler {
     //// DB type: postgres and redis for now
@@ -25,7 +32,7 @@ pub trait DBHandler {
     async fn save_account(&self, account: Account, worker_id: u32) -> Result<String, OreoError>;
     /// Get account name from db
     async fn get_account(&self, address: String) -> Result<Account, OreoError>;
-    /// Remove account from db
+    /// Remove account from db, revoking its login sessions
     async fn remove_account(&self, address: String) -> Result<String, OreoError>;
     /// Update account need_scan status
     async fn update_scan_status(
@@ -39,6 +46,81 @@ pub trait DBHandler {
     async fn save_blocks(&self, blocks: Vec<InnerBlock>) -> Result<(), OreoError>;
     /// Get compact blocks for dservice
     async fn get_blocks(&self, start: i64, end: i64) -> Result<Vec<InnerBlock>, OreoError>;
+    /// Remove compact blocks which are not on the canonical chain anymore
+    async fn remove_blocks(&self, start: i64, end: i64) -> Result<(), OreoError>;
+    /// Get accounts count of each node wallet
+    async fn get_worker_loads(&self) -> Result<HashMap<u32, i64>, OreoError>;
+    /// Get accounts assigned to a node wallet
+    async fn get_worker_accounts(&self, worker_id: u32) -> Result<Vec<Account>, OreoError>;
+    /// Move account to another node wallet
+    async fn update_account_worker(
+        &self,
+        address: String,
+        worker_id: u32,
+    ) -> Result<String, OreoError>;
+    /// Save a new login session
+    async fn save_session(&self, session: Session) -> Result<(), OreoError>;
+    /// Get login session by id
+    async fn get_session(&self, sid: String) -> Result<Session, OreoError>;
+    /// Revoke one login session
+    async fn revoke_session(&self, sid: String) -> Result<(), OreoError>;
+    /// Revoke all login sessions of an account
+    async fn revoke_sessions(&self, address: String) -> Result<(), OreoError>;
+    /// Create or replace the scan session of an account
+    async fn save_scan_session(&self, session: ScanSession) -> Result<(), OreoError>;
+    /// Get all unfinished scan sessions
+    async fn get_scan_sessions(&self) -> Result<Vec<ScanSession>, OreoError>;
+    /// Lease up to `limit` scan sessions held by no live scanner instance to `owner`
+    async fn claim_scan_sessions(
+        &self,
+        owner: String,
+        limit: i64,
+    ) -> Result<Vec<ScanSession>, OreoError>;
+    /// Extend the leases of `owner` on the sessions of `addresses`, returns the ones it still holds
+    async fn renew_scan_leases(
+        &self,
+        owner: String,
+        addresses: Vec<String>,
+    ) -> Result<Vec<String>, OreoError>;
+    /// Revoke the lease on the session of `address`, returns the live scanner still holding it
+    async fn revoke_scan_lease(&self, address: String) -> Result<Option<String>, OreoError>;
+    /// Give up the leases of `owner` on the revoked sessions of `addresses`
+    async fn release_scan_leases(
+        &self,
+        owner: String,
+        addresses: Vec<String>,
+    ) -> Result<(), OreoError>;
+    /// Move the scanned watermark of a scan session, with the block ranges scanned above it
+    async fn update_scan_watermark(
+        &self,
+        address: String,
+        sequence: i64,
+        ranges: Vec<(i64, i64)>,
+    ) -> Result<(), OreoError>;
+    /// Take or extend the lease of `owner` on the scanner job `name`, returns whether it holds it
+    async fn claim_scanner_lease(
+        &self,
+        name: String,
+        owner: String,
+        seconds: i64,
+    ) -> Result<bool, OreoError>;
+    /// Save a block matched during a scan session
+    async fn save_scan_result(&self, result: ScanResult) -> Result<(), OreoError>;
+    /// Get blocks matched so far in a scan session
+    async fn get_scan_results(&self, address: String) -> Result<Vec<ScanResult>, OreoError>;
+    /// Remove a finished scan session with its results
+    async fn remove_scan_session(&self, address: String) -> Result<(), OreoError>;
+    /// Save notes decrypted by workers
+    async fn save_scan_notes(&self, notes: Vec<ScanNote>) -> Result<(), OreoError>;
+    /// Get notes of an account decrypted from `start` on
+    async fn get_scan_notes(&self, address: String, start: i64)
+        -> Result<Vec<ScanNote>, OreoError>;
+    /// Remove notes of an account decrypted from blocks above `sequence`
+    async fn remove_scan_notes(&self, address: String, sequence: i64) -> Result<(), OreoError>;
+    /// Get the last block up to which chain loader saved all blocks
+    async fn get_chain_progress(&self) -> Result<Option<ChainProgress>, OreoError>;
+    /// Update the last block up to which chain loader saved all blocks
+    async fn update_chain_progress(&self, progress: ChainProgress) -> Result<(), OreoError>;
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, FromRow)]
@@ -54,6 +136,71 @@ pub struct Account {
     pub vk: String,
     pub address: String,
     pub need_scan: bool,
+    /// Index of the rpc node whose wallet holds this account
+    #[serde(default)]
+    #[sqlx(default)]
+    pub worker_id: i32,
+    /// Notes the scanner matches for this account
+    #[serde(default)]
+    #[sqlx(default)]
+    pub scan_options: Json<ScanOptions>,
+}
+
+/// Whether a scan matches received notes, sent notes or both.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ScanMode {
+    #[default]
+    Full,
+    IncomingOnly,
+    OutgoingOnly,
+}
+
+/// Notes matched by a scan, every non zero value note by default.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanOptions {
+    #[serde(default)]
+    pub mode: ScanMode,
+    /// Smallest value of a matched note by hex asset id
+    #[serde(default)]
+    pub min_values: BTreeMap<String, u64>,
+    /// Hex asset ids of the notes to match, any asset if unset
+    #[serde(default)]
+    pub assets: Option<BTreeSet<String>>,
+    /// Also match zero value notes carrying a memo, which are messages between accounts
+    #[serde(default)]
+    pub messages: bool,
+}
+
+impl ScanOptions {
+    pub fn incoming(&self) -> bool {
+        self.mode != ScanMode::OutgoingOnly
+    }
+
+    pub fn outgoing(&self) -> bool {
+        self.mode != ScanMode::IncomingOnly
+    }
+
+    /// Whether a note of `value` in asset `asset_id` is worth reporting, zero value notes
+    /// are only reported as messages.
+    pub fn matches(&self, asset_id: &str, value: u64, memo: &[u8]) -> bool {
+        if value == 0 {
+            return self.messages && memo.iter().any(|byte| *byte != 0);
+        }
+        if let Some(assets) = &self.assets {
+            if !assets
+                .iter()
+                .any(|asset| asset.eq_ignore_ascii_case(asset_id))
+            {
+                return false;
+            }
+        }
+        self.min_values
+            .iter()
+            .find(|(asset, _)| asset.eq_ignore_ascii_case(asset_id))
+            .map_or(true, |(_, min)| value >= *min)
+    }
 }
 
 pub fn address_to_name(address: &str) -> String {
@@ -72,6 +219,96 @@ pub struct InnerBlock {
     pub hash: String,
     pub sequence: i64,
     pub transactions: Json<Vec<DBTransaction>>,
+    // unknown for blocks saved before it was tracked
+    #[serde(default)]
+    #[sqlx(default)]
+    pub previous_block_hash: Option<String>,
+}
+
+impl InnerBlock {
+    /// Sequence of the first block which doesn't extend the one before it, `parent` is the
+    /// hash of the block before the first one if known. Blocks must be sorted by sequence.
+    pub fn broken_link(blocks: &[InnerBlock], parent: Option<&str>) -> Option<i64> {
+        let mut parent = parent.map(|hash| (hash.to_string(), None));
+        for block in blocks {
+            if let Some((hash, sequence)) = parent {
+                if sequence.is_some_and(|sequence: i64| block.sequence != sequence + 1) {
+                    return Some(block.sequence);
+                }
+                if let Some(previous) = &block.previous_block_hash {
+                    if !previous.eq_ignore_ascii_case(&hash) {
+                        return Some(block.sequence);
+                    }
+                }
+            }
+            parent = Some((block.hash.clone(), Some(block.sequence)));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct Session {
+    pub sid: String,
+    pub address: String,
+    pub expires_at: i64,
+    pub revoked: bool,
+}
+
+/// A scan requested by the server, `end_*` is unset until the scan is scheduled.
+/// Blocks up to `scanned_sequence` are scanned and their matches saved as `ScanResult`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ScanSession {
+    pub address: String,
+    pub in_vk: String,
+    pub out_vk: String,
+    pub start_hash: String,
+    pub start_sequence: i64,
+    pub end_hash: Option<String>,
+    pub end_sequence: Option<i64>,
+    pub scanned_sequence: i64,
+    #[serde(default)]
+    #[sqlx(default)]
+    pub scan_options: Json<ScanOptions>,
+    // scanner instance leasing the session, unset for a single scanner
+    #[serde(default)]
+    #[sqlx(default)]
+    pub owner: Option<String>,
+    // inclusive ranges of blocks scanned above `scanned_sequence`
+    #[serde(default)]
+    #[sqlx(default)]
+    pub completed_ranges: Json<Vec<(i64, i64)>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ScanResult {
+    pub address: String,
+    pub hash: String,
+    pub sequence: i64,
+    pub transactions: Json<Vec<String>>,
+}
+
+/// A note received or sent by an account, as decrypted by a worker.
+/// `memo` and `asset_id` are hex encoded, `direction` is either `received` or `sent`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanNote {
+    pub address: String,
+    pub tx_hash: String,
+    pub note_index: i32,
+    pub direction: String,
+    pub block_hash: String,
+    pub sequence: i64,
+    pub value: i64,
+    pub asset_id: String,
+    pub memo: String,
+    pub sender: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ChainProgress {
+    pub hash: String,
+    pub sequence: i64,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
@@ -115,3 +352,46 @@ impl DbConfig {
 pub fn load_db(filename: impl AsRef<Path>) -> anyhow::Result<Box<dyn DBHandler + Send + Sync>> {
     DbConfig::load(filename)?.build()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::{ScanMode, ScanOptions};
+
+    const IRON: &str = "51f33a2f14f92735e562dc658a5639279ddca3d5079a6d1242b2a588a9cbf44c";
+
+    #[test]
+    fn default_scan_options_should_skip_zero_value() {
+        let options = ScanOptions::default();
+        assert!(options.incoming() && options.outgoing());
+        assert!(options.matches(IRON, 1, &[]));
+        assert!(!options.matches(IRON, 0, b"hello"));
+    }
+
+    #[test]
+    fn scan_options_should_filter_dust_and_assets() {
+        let options = ScanOptions {
+            mode: ScanMode::IncomingOnly,
+            min_values: BTreeMap::from([(IRON.to_uppercase(), 100)]),
+            assets: Some(BTreeSet::from([IRON.to_string()])),
+            messages: false,
+        };
+        assert!(!options.outgoing());
+        assert!(!options.matches(IRON, 99, &[]));
+        assert!(options.matches(IRON, 100, &[]));
+        assert!(!options.matches("00", 1000, &[]));
+    }
+
+    #[test]
+    fn messages_should_need_a_memo() {
+        let options = ScanOptions {
+            min_values: BTreeMap::from([(IRON.to_string(), 100)]),
+            messages: true,
+            ..Default::default()
+        };
+        assert!(options.matches(IRON, 0, b"hello"));
+        assert!(!options.matches(IRON, 0, &[0; 32]));
+        assert!(!options.matches(IRON, 1, b"hello"));
+    }
+}
@@ -1,7 +1,15 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use oreo_errors::OreoError;
-use sqlx::{PgPool, Row};
+use sqlx::{types::Json, PgPool, Row};
 
-use crate::{BonusAddress, DBTransaction, InnerBlock};
+use crate::{
+    BonusAddress, ChainProgress, DBTransaction, InnerBlock, ScanNote, ScanResult, ScanSession,
+    Session, SCAN_LEASE_SECS,
+};
 
 use super::{Account, DBHandler};
 
@@ -17,7 +25,7 @@ impl PgHandler {
 
     pub async fn insert(&self, account: Account) -> Result<String, sqlx::Error> {
         let result = sqlx::query(
-            "INSERT INTO wallet.account (name, create_head, create_hash, head, hash, in_vk, out_vk, vk, address) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING name"
+            "INSERT INTO wallet.account (name, create_head, create_hash, head, hash, in_vk, out_vk, vk, address, worker_id, scan_options) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING name"
         )
         .bind(account.name.clone())
         .bind(account.create_head.clone())
@@ -28,6 +36,8 @@ impl PgHandler {
         .bind(account.out_vk.clone())
         .bind(account.vk.clone())
         .bind(account.address.clone())
+        .bind(account.worker_id)
+        .bind(account.scan_options.clone())
         .fetch_one(&self.pool)
         .await?.get(0);
         Ok(result)
@@ -66,11 +76,18 @@ impl PgHandler {
     }
 
     pub async fn delete(&self, address: String) -> Result<String, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
         let result = sqlx::query("DELETE FROM wallet.account WHERE address = $1 RETURNING name")
-            .bind(address)
-            .fetch_one(&self.pool)
+            .bind(address.clone())
+            .fetch_one(&mut *transaction)
             .await?
             .get(0);
+        // tokens issued before the removal must not let a new import of the address in
+        sqlx::query("UPDATE wallet.sessions SET revoked = true WHERE address = $1")
+            .bind(address)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
         Ok(result)
     }
 
@@ -93,13 +110,338 @@ impl PgHandler {
         Ok(result)
     }
 
+    pub async fn get_worker_counts(&self) -> Result<Vec<(i32, i64)>, sqlx::Error> {
+        let result =
+            sqlx::query_as("SELECT worker_id, COUNT(*) FROM wallet.account GROUP BY worker_id")
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(result)
+    }
+
+    pub async fn get_many_by_worker(&self, worker_id: i32) -> Result<Vec<Account>, sqlx::Error> {
+        let result = sqlx::query_as("SELECT * FROM wallet.account WHERE worker_id = $1")
+            .bind(worker_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    pub async fn set_worker(&self, address: String, worker_id: i32) -> Result<String, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE wallet.account SET worker_id = $1 WHERE address = $2 RETURNING name",
+        )
+        .bind(worker_id)
+        .bind(address)
+        .fetch_one(&self.pool)
+        .await?
+        .get(0);
+        Ok(result)
+    }
+
+    pub async fn insert_session(&self, session: Session) -> Result<String, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO wallet.sessions (sid, address, expires_at, revoked) VALUES ($1, $2, $3, $4) RETURNING sid",
+        )
+        .bind(session.sid)
+        .bind(session.address)
+        .bind(session.expires_at)
+        .bind(session.revoked)
+        .fetch_one(&self.pool)
+        .await?
+        .get(0);
+        Ok(result)
+    }
+
+    pub async fn get_one_session(&self, sid: String) -> Result<Session, sqlx::Error> {
+        let result = sqlx::query_as("SELECT * FROM wallet.sessions WHERE sid = $1")
+            .bind(sid)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    pub async fn set_session_revoked(&self, sid: String) -> Result<String, sqlx::Error> {
+        let result =
+            sqlx::query("UPDATE wallet.sessions SET revoked = true WHERE sid = $1 RETURNING sid")
+                .bind(sid)
+                .fetch_one(&self.pool)
+                .await?
+                .get(0);
+        Ok(result)
+    }
+
+    pub async fn set_sessions_revoked(&self, address: String) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE wallet.sessions SET revoked = true WHERE address = $1")
+            .bind(address)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_expired_sessions(&self, now: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM wallet.sessions WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn upsert_scan_session(&self, session: ScanSession) -> Result<(), sqlx::Error> {
+        // a session saved without owner is left to whichever instance claims it first,
+        // while rescheduling it keeps the lease already held
+        sqlx::query(
+            "INSERT INTO wallet.scan_sessions (address, in_vk, out_vk, start_hash, start_sequence, end_hash, end_sequence, scanned_sequence, scan_options, owner, lease_expires, completed_ranges) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, CASE WHEN $10 IS NULL THEN 0 ELSE EXTRACT(EPOCH FROM now())::BIGINT + $11 END, $12) \
+            ON CONFLICT (address) DO UPDATE SET in_vk = $2, out_vk = $3, start_hash = $4, start_sequence = $5, end_hash = $6, end_sequence = $7, scanned_sequence = $8, scan_options = $9, \
+            owner = COALESCE($10, wallet.scan_sessions.owner), \
+            lease_expires = CASE WHEN $10 IS NULL THEN wallet.scan_sessions.lease_expires ELSE EXTRACT(EPOCH FROM now())::BIGINT + $11 END, \
+            completed_ranges = $12, revoked = false",
+        )
+        .bind(session.address)
+        .bind(session.in_vk)
+        .bind(session.out_vk)
+        .bind(session.start_hash)
+        .bind(session.start_sequence)
+        .bind(session.end_hash)
+        .bind(session.end_sequence)
+        .bind(session.scanned_sequence)
+        .bind(session.scan_options)
+        .bind(session.owner)
+        .bind(SCAN_LEASE_SECS)
+        .bind(session.completed_ranges)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_many_scan_sessions(&self) -> Result<Vec<ScanSession>, sqlx::Error> {
+        let result = sqlx::query_as("SELECT * FROM wallet.scan_sessions")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    pub async fn lease_scan_sessions(
+        &self,
+        owner: String,
+        limit: i64,
+    ) -> Result<Vec<ScanSession>, sqlx::Error> {
+        let result = sqlx::query_as(
+            "UPDATE wallet.scan_sessions SET owner = $1, lease_expires = EXTRACT(EPOCH FROM now())::BIGINT + $2 \
+            WHERE address IN (SELECT address FROM wallet.scan_sessions \
+            WHERE NOT revoked AND (owner IS NULL OR lease_expires < EXTRACT(EPOCH FROM now())::BIGINT) \
+            ORDER BY start_sequence LIMIT $3 FOR UPDATE SKIP LOCKED) RETURNING *",
+        )
+        .bind(owner)
+        .bind(SCAN_LEASE_SECS)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn extend_scan_leases(
+        &self,
+        owner: String,
+        addresses: Vec<String>,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let result: Vec<(String,)> = sqlx::query_as(
+            "UPDATE wallet.scan_sessions SET lease_expires = EXTRACT(EPOCH FROM now())::BIGINT + $2 \
+            WHERE owner = $1 AND NOT revoked AND address = ANY($3) RETURNING address",
+        )
+        .bind(owner)
+        .bind(SCAN_LEASE_SECS)
+        .bind(addresses)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result.into_iter().map(|(address,)| address).collect())
+    }
+
+    pub async fn set_scan_lease_revoked(
+        &self,
+        address: String,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let result: Option<(Option<String>,)> = sqlx::query_as(
+            "UPDATE wallet.scan_sessions SET revoked = true WHERE address = $1 \
+            RETURNING CASE WHEN lease_expires >= EXTRACT(EPOCH FROM now())::BIGINT THEN owner END",
+        )
+        .bind(address)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(result.and_then(|(owner,)| owner))
+    }
+
+    pub async fn drop_revoked_leases(
+        &self,
+        owner: String,
+        addresses: Vec<String>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE wallet.scan_sessions SET owner = NULL, lease_expires = 0 \
+            WHERE owner = $1 AND revoked AND address = ANY($2)",
+        )
+        .bind(owner)
+        .bind(addresses)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_scan_watermark(
+        &self,
+        address: String,
+        sequence: i64,
+        ranges: Vec<(i64, i64)>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE wallet.scan_sessions SET scanned_sequence = $1, completed_ranges = $2 WHERE address = $3",
+        )
+        .bind(sequence)
+        .bind(Json(ranges))
+        .bind(address)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn take_scanner_lease(
+        &self,
+        name: String,
+        owner: String,
+        seconds: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let result: Option<(String,)> = sqlx::query_as(
+            "INSERT INTO wallet.scanner_leases (name, owner, lease_expires) VALUES ($1, $2, EXTRACT(EPOCH FROM now())::BIGINT + $3) \
+            ON CONFLICT (name) DO UPDATE SET owner = $2, lease_expires = EXTRACT(EPOCH FROM now())::BIGINT + $3 \
+            WHERE wallet.scanner_leases.owner = $2 OR wallet.scanner_leases.lease_expires < EXTRACT(EPOCH FROM now())::BIGINT \
+            RETURNING name",
+        )
+        .bind(name)
+        .bind(owner)
+        .bind(seconds)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(result.is_some())
+    }
+
+    pub async fn upsert_scan_result(&self, result: ScanResult) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO wallet.scan_results (address, hash, sequence, transactions) VALUES ($1, $2, $3, $4) \
+            ON CONFLICT (address, hash) DO UPDATE SET sequence = $3, transactions = $4",
+        )
+        .bind(result.address)
+        .bind(result.hash)
+        .bind(result.sequence)
+        .bind(result.transactions)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_many_scan_results(
+        &self,
+        address: String,
+    ) -> Result<Vec<ScanResult>, sqlx::Error> {
+        let result = sqlx::query_as("SELECT * FROM wallet.scan_results WHERE address = $1")
+            .bind(address)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(result)
+    }
+
+    pub async fn delete_scan_session(&self, address: String) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM wallet.scan_results WHERE address = $1")
+            .bind(address.clone())
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("DELETE FROM wallet.scan_sessions WHERE address = $1")
+            .bind(address)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await
+    }
+
+    pub async fn upsert_scan_notes(&self, notes: Vec<ScanNote>) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        for note in notes {
+            sqlx::query(
+                "INSERT INTO wallet.scan_notes (address, tx_hash, note_index, direction, block_hash, sequence, value, asset_id, memo, sender) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+                ON CONFLICT (address, tx_hash, note_index, direction) DO UPDATE SET block_hash = $5, sequence = $6, value = $7, asset_id = $8, memo = $9, sender = $10",
+            )
+            .bind(note.address)
+            .bind(note.tx_hash)
+            .bind(note.note_index)
+            .bind(note.direction)
+            .bind(note.block_hash)
+            .bind(note.sequence)
+            .bind(note.value)
+            .bind(note.asset_id)
+            .bind(note.memo)
+            .bind(note.sender)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await
+    }
+
+    pub async fn get_many_scan_notes(
+        &self,
+        address: String,
+        start: i64,
+    ) -> Result<Vec<ScanNote>, sqlx::Error> {
+        let result = sqlx::query_as(
+            "SELECT * FROM wallet.scan_notes WHERE address = $1 AND sequence >= $2 ORDER BY sequence, tx_hash, note_index",
+        )
+        .bind(address)
+        .bind(start)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(result)
+    }
+
+    pub async fn delete_scan_notes(
+        &self,
+        address: String,
+        sequence: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM wallet.scan_notes WHERE address = $1 AND sequence > $2")
+            .bind(address)
+            .bind(sequence)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_one_chain_progress(&self) -> Result<Option<ChainProgress>, sqlx::Error> {
+        let result =
+            sqlx::query_as("SELECT hash, sequence FROM wallet.chain_progress WHERE id = 0")
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(result)
+    }
+
+    pub async fn upsert_chain_progress(&self, progress: ChainProgress) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO wallet.chain_progress (id, hash, sequence) VALUES (0, $1, $2) \
+            ON CONFLICT (id) DO UPDATE SET hash = $1, sequence = $2",
+        )
+        .bind(progress.hash)
+        .bind(progress.sequence)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
     pub async fn insert_compact_block(&self, block: InnerBlock) -> Result<i64, sqlx::Error> {
         let result = sqlx::query(
-            "INSERT INTO wallet.blocks (hash, sequence, transactions) VALUES ($1, $2, $3) RETURNING sequence"
+            "INSERT INTO wallet.blocks (hash, sequence, transactions, previous_block_hash) VALUES ($1, $2, $3, $4) \
+            ON CONFLICT (sequence) DO UPDATE SET hash = $1, transactions = $3, previous_block_hash = $4 RETURNING sequence"
         )
         .bind(block.hash.clone())
         .bind(block.sequence)
         .bind(block.transactions)
+        .bind(block.previous_block_hash)
         .fetch_one(&self.pool)
         .await?.get(0);
         Ok(result)
@@ -110,15 +452,25 @@ impl PgHandler {
         start: i64,
         end: i64,
     ) -> Result<Vec<InnerBlock>, sqlx::Error> {
-        let result =
-            sqlx::query_as("SELECT * FROM wallet.blocks WHERE sequence >= $1 AND sequence <= $2")
-                .bind(start)
-                .bind(end)
-                .fetch_all(&self.pool)
-                .await?;
+        let result = sqlx::query_as(
+            "SELECT * FROM wallet.blocks WHERE sequence >= $1 AND sequence <= $2 ORDER BY sequence",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await?;
         Ok(result)
     }
 
+    pub async fn delete_compact_blocks(&self, start: i64, end: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM wallet.blocks WHERE sequence >= $1 AND sequence <= $2")
+            .bind(start)
+            .bind(end)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_compact_transactions(
         &self,
         block_hash: String,
@@ -165,7 +517,12 @@ impl DBHandler for PgHandler {
         "Postgres".to_string()
     }
 
-    async fn save_account(&self, account: Account, _worker_id: u32) -> Result<String, OreoError> {
+    async fn save_account(
+        &self,
+        mut account: Account,
+        worker_id: u32,
+    ) -> Result<String, OreoError> {
+        account.worker_id = worker_id as i32;
         let _ = self.insert_first_seen(account.address.clone()).await;
         let old_account = self.get_one(account.address.clone()).await;
         match old_account {
@@ -219,7 +576,9 @@ impl DBHandler for PgHandler {
     async fn save_blocks(&self, blocks: Vec<InnerBlock>) -> Result<(), OreoError> {
         let transaction = self.pool.begin().await.unwrap();
         for block in blocks {
-            let _ = self.insert_compact_block(block).await;
+            self.insert_compact_block(block)
+                .await
+                .map_err(|_| OreoError::DBError)?;
         }
         transaction.rollback().await.unwrap();
         Ok(())
@@ -235,6 +594,200 @@ impl DBHandler for PgHandler {
             false => Err(OreoError::DBError),
         }
     }
+
+    async fn remove_blocks(&self, start: i64, end: i64) -> Result<(), OreoError> {
+        self.delete_compact_blocks(start, end)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn get_worker_loads(&self) -> Result<HashMap<u32, i64>, OreoError> {
+        let counts = self
+            .get_worker_counts()
+            .await
+            .map_err(|_| OreoError::DBError)?;
+        Ok(counts
+            .into_iter()
+            .map(|(worker_id, count)| (worker_id as u32, count))
+            .collect())
+    }
+
+    async fn get_worker_accounts(&self, worker_id: u32) -> Result<Vec<Account>, OreoError> {
+        self.get_many_by_worker(worker_id as i32)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn update_account_worker(
+        &self,
+        address: String,
+        worker_id: u32,
+    ) -> Result<String, OreoError> {
+        self.set_worker(address.clone(), worker_id as i32)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => OreoError::NoImported(address),
+                _ => OreoError::DBError,
+            })
+    }
+
+    async fn save_session(&self, session: Session) -> Result<(), OreoError> {
+        // Expired sessions can never be used again, drop them while we are here
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let _ = self.delete_expired_sessions(now).await;
+        self.insert_session(session)
+            .await
+            .map(|_| ())
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn get_session(&self, sid: String) -> Result<Session, OreoError> {
+        self.get_one_session(sid).await.map_err(|e| match e {
+            sqlx::Error::RowNotFound => OreoError::Unauthorized,
+            _ => OreoError::DBError,
+        })
+    }
+
+    async fn revoke_session(&self, sid: String) -> Result<(), OreoError> {
+        self.set_session_revoked(sid)
+            .await
+            .map(|_| ())
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => OreoError::Unauthorized,
+                _ => OreoError::DBError,
+            })
+    }
+
+    async fn revoke_sessions(&self, address: String) -> Result<(), OreoError> {
+        self.set_sessions_revoked(address)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn save_scan_session(&self, session: ScanSession) -> Result<(), OreoError> {
+        self.upsert_scan_session(session)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn get_scan_sessions(&self) -> Result<Vec<ScanSession>, OreoError> {
+        self.get_many_scan_sessions()
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn claim_scan_sessions(
+        &self,
+        owner: String,
+        limit: i64,
+    ) -> Result<Vec<ScanSession>, OreoError> {
+        self.lease_scan_sessions(owner, limit)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn renew_scan_leases(
+        &self,
+        owner: String,
+        addresses: Vec<String>,
+    ) -> Result<Vec<String>, OreoError> {
+        self.extend_scan_leases(owner, addresses)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn revoke_scan_lease(&self, address: String) -> Result<Option<String>, OreoError> {
+        self.set_scan_lease_revoked(address)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn release_scan_leases(
+        &self,
+        owner: String,
+        addresses: Vec<String>,
+    ) -> Result<(), OreoError> {
+        self.drop_revoked_leases(owner, addresses)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn update_scan_watermark(
+        &self,
+        address: String,
+        sequence: i64,
+        ranges: Vec<(i64, i64)>,
+    ) -> Result<(), OreoError> {
+        self.set_scan_watermark(address, sequence, ranges)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn claim_scanner_lease(
+        &self,
+        name: String,
+        owner: String,
+        seconds: i64,
+    ) -> Result<bool, OreoError> {
+        self.take_scanner_lease(name, owner, seconds)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn save_scan_result(&self, result: ScanResult) -> Result<(), OreoError> {
+        self.upsert_scan_result(result)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn get_scan_results(&self, address: String) -> Result<Vec<ScanResult>, OreoError> {
+        self.get_many_scan_results(address)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn remove_scan_session(&self, address: String) -> Result<(), OreoError> {
+        self.delete_scan_session(address)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn save_scan_notes(&self, notes: Vec<ScanNote>) -> Result<(), OreoError> {
+        self.upsert_scan_notes(notes)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn get_scan_notes(
+        &self,
+        address: String,
+        start: i64,
+    ) -> Result<Vec<ScanNote>, OreoError> {
+        self.get_many_scan_notes(address, start)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn remove_scan_notes(&self, address: String, sequence: i64) -> Result<(), OreoError> {
+        self.delete_scan_notes(address, sequence)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn get_chain_progress(&self) -> Result<Option<ChainProgress>, OreoError> {
+        self.get_one_chain_progress()
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
+
+    async fn update_chain_progress(&self, progress: ChainProgress) -> Result<(), OreoError> {
+        self.upsert_chain_progress(progress)
+            .await
+            .map_err(|_| OreoError::DBError)
+    }
 }
 
 unsafe impl Send for PgHandler {}
@@ -248,7 +801,10 @@ mod tests {
     use sqlx::types::Json;
     use sqlx_db_tester::TestPg;
 
-    use crate::{address_to_name, Account, DBHandler, DBTransaction, InnerBlock};
+    use crate::{
+        address_to_name, Account, DBHandler, DBTransaction, InnerBlock, ScanNote, ScanResult,
+        ScanSession, Session,
+    };
 
     use super::PgHandler;
 
@@ -276,6 +832,8 @@ mod tests {
             vk: VK.to_string(),
             address: ADDRESS.to_string(),
             need_scan: false,
+            worker_id: 0,
+            scan_options: Json::default(),
         }
     }
 
@@ -283,6 +841,7 @@ mod tests {
         InnerBlock {
             hash: "dd6653ad5ec58e6174586d8a54e6c60731520d0c3b41c2e3266a05965cad0dae".to_string(),
             sequence: 10,
+            previous_block_hash: None,
             transactions: Json(vec![DBTransaction {
                 hash: "dd6653ad5ec58e6174586d8a54e6c60731520d0c3b41c2e3266a05965cad0da1".to_string(),
                 serialized_notes: vec!["dd6653ad5ec58e6174586d8a54e6c60731520d0c3b41c2e3266a05965cad0daedd6653ad5ec58e6174586d8a54e6c60731520d0c3b41c2e3266a05965cad0dae1".to_string()],
@@ -333,6 +892,277 @@ mod tests {
         assert!(should_error.is_err());
     }
 
+    #[tokio::test]
+    async fn remove_account_should_revoke_sessions_pg() {
+        let tdb = get_tdb();
+        let pool = tdb.get_pool().await;
+        let pg_handler = PgHandler::new(pool);
+        let account = get_test_account();
+        let _ = pg_handler.save_account(account.clone(), 0).await.unwrap();
+        let session = Session {
+            sid: "0123456789abcdef0123456789abcdef".to_string(),
+            address: ADDRESS.to_string(),
+            expires_at: i64::MAX,
+            revoked: false,
+        };
+        pg_handler.save_session(session.clone()).await.unwrap();
+        let _ = pg_handler
+            .remove_account(ADDRESS.to_string())
+            .await
+            .unwrap();
+        // importing the address again must not bring its old tokens back
+        let _ = pg_handler.save_account(account, 0).await.unwrap();
+        let saved = pg_handler.get_session(session.sid).await.unwrap();
+        assert!(saved.revoked);
+    }
+
+    #[tokio::test]
+    async fn worker_loads_should_work_pg() {
+        let tdb = get_tdb();
+        let pool = tdb.get_pool().await;
+        let pg_handler = PgHandler::new(pool);
+        let account = get_test_account();
+        let _ = pg_handler.save_account(account, 1).await.unwrap();
+        let loads = pg_handler.get_worker_loads().await.unwrap();
+        assert_eq!(loads.get(&1), Some(&1));
+        let _ = pg_handler
+            .update_account_worker(ADDRESS.to_string(), 2)
+            .await
+            .unwrap();
+        let accounts = pg_handler.get_worker_accounts(2).await.unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].worker_id, 2);
+    }
+
+    #[tokio::test]
+    async fn revoke_session_should_work_pg() {
+        let tdb = get_tdb();
+        let pool = tdb.get_pool().await;
+        let pg_handler = PgHandler::new(pool);
+        let session = Session {
+            sid: "0123456789abcdef0123456789abcdef".to_string(),
+            address: ADDRESS.to_string(),
+            expires_at: i64::MAX,
+            revoked: false,
+        };
+        pg_handler.save_session(session.clone()).await.unwrap();
+        let saved = pg_handler.get_session(session.sid.clone()).await.unwrap();
+        assert_eq!(saved, session);
+        pg_handler
+            .revoke_sessions(ADDRESS.to_string())
+            .await
+            .unwrap();
+        let saved = pg_handler.get_session(session.sid.clone()).await.unwrap();
+        assert!(saved.revoked);
+    }
+
+    #[tokio::test]
+    async fn scan_session_should_work_pg() {
+        let tdb = get_tdb();
+        let pool = tdb.get_pool().await;
+        let pg_handler = PgHandler::new(pool);
+        let block = get_test_block();
+        let session = ScanSession {
+            address: ADDRESS.to_string(),
+            in_vk: IN_VK.to_string(),
+            out_vk: OUT_VK.to_string(),
+            start_hash: block.hash.clone(),
+            start_sequence: block.sequence,
+            end_hash: None,
+            end_sequence: None,
+            scanned_sequence: block.sequence - 1,
+            scan_options: Json::default(),
+            owner: None,
+            completed_ranges: Json::default(),
+        };
+        pg_handler.save_scan_session(session.clone()).await.unwrap();
+        pg_handler
+            .update_scan_watermark(
+                ADDRESS.to_string(),
+                block.sequence,
+                vec![(block.sequence + 2, block.sequence + 4)],
+            )
+            .await
+            .unwrap();
+        pg_handler
+            .save_scan_result(ScanResult {
+                address: ADDRESS.to_string(),
+                hash: block.hash.clone(),
+                sequence: block.sequence,
+                transactions: Json(vec![block.transactions[0].hash.clone()]),
+            })
+            .await
+            .unwrap();
+        let sessions = pg_handler.get_scan_sessions().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].scanned_sequence, block.sequence);
+        assert_eq!(
+            sessions[0].completed_ranges.0,
+            vec![(block.sequence + 2, block.sequence + 4)]
+        );
+        let results = pg_handler
+            .get_scan_results(ADDRESS.to_string())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        pg_handler
+            .remove_scan_session(ADDRESS.to_string())
+            .await
+            .unwrap();
+        assert!(pg_handler.get_scan_sessions().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn scan_leases_should_work_pg() {
+        let tdb = get_tdb();
+        let pool = tdb.get_pool().await;
+        let pg_handler = PgHandler::new(pool);
+        let block = get_test_block();
+        let session = ScanSession {
+            address: ADDRESS.to_string(),
+            in_vk: IN_VK.to_string(),
+            out_vk: OUT_VK.to_string(),
+            start_hash: block.hash.clone(),
+            start_sequence: block.sequence,
+            end_hash: None,
+            end_sequence: None,
+            scanned_sequence: block.sequence - 1,
+            scan_options: Json::default(),
+            owner: Some("a".to_string()),
+            completed_ranges: Json::default(),
+        };
+        pg_handler.save_scan_session(session).await.unwrap();
+        let claimed = pg_handler
+            .claim_scan_sessions("b".to_string(), 10)
+            .await
+            .unwrap();
+        assert!(claimed.is_empty());
+        assert_eq!(
+            pg_handler
+                .renew_scan_leases("a".to_string(), vec![ADDRESS.to_string()])
+                .await
+                .unwrap(),
+            vec![ADDRESS.to_string()]
+        );
+        sqlx::query("UPDATE wallet.scan_sessions SET lease_expires = 0")
+            .execute(&pg_handler.pool)
+            .await
+            .unwrap();
+        let claimed = pg_handler
+            .claim_scan_sessions("b".to_string(), 10)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].owner, Some("b".to_string()));
+        assert!(pg_handler
+            .renew_scan_leases("a".to_string(), vec![ADDRESS.to_string()])
+            .await
+            .unwrap()
+            .is_empty());
+        // rescheduling without owner keeps the lease
+        pg_handler
+            .save_scan_session(ScanSession {
+                owner: None,
+                ..claimed[0].clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            pg_handler.get_scan_sessions().await.unwrap()[0].owner,
+            Some("b".to_string())
+        );
+        // a revoked session is neither renewed nor claimed until its holder releases it
+        assert_eq!(
+            pg_handler
+                .revoke_scan_lease(ADDRESS.to_string())
+                .await
+                .unwrap(),
+            Some("b".to_string())
+        );
+        assert!(pg_handler
+            .renew_scan_leases("b".to_string(), vec![ADDRESS.to_string()])
+            .await
+            .unwrap()
+            .is_empty());
+        pg_handler
+            .release_scan_leases("b".to_string(), vec![ADDRESS.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            pg_handler
+                .revoke_scan_lease(ADDRESS.to_string())
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(pg_handler
+            .claim_scan_sessions("a".to_string(), 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn scanner_lease_should_work_pg() {
+        let tdb = get_tdb();
+        let pool = tdb.get_pool().await;
+        let pg_handler = PgHandler::new(pool);
+        let claim = |owner: &str| {
+            pg_handler.claim_scanner_lease("reconciler".to_string(), owner.to_string(), 60)
+        };
+        assert!(claim("a").await.unwrap());
+        assert!(claim("a").await.unwrap());
+        assert!(!claim("b").await.unwrap());
+        sqlx::query("UPDATE wallet.scanner_leases SET lease_expires = 0")
+            .execute(&pg_handler.pool)
+            .await
+            .unwrap();
+        assert!(claim("b").await.unwrap());
+        assert!(!claim("a").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn scan_notes_should_work_pg() {
+        let tdb = get_tdb();
+        let pool = tdb.get_pool().await;
+        let pg_handler = PgHandler::new(pool);
+        let block = get_test_block();
+        let note = ScanNote {
+            address: ADDRESS.to_string(),
+            tx_hash: block.transactions[0].hash.clone(),
+            note_index: 0,
+            direction: "received".to_string(),
+            block_hash: block.hash.clone(),
+            sequence: block.sequence,
+            value: 100,
+            asset_id: block.hash.clone(),
+            memo: block.hash.clone(),
+            sender: ADDRESS.to_string(),
+        };
+        let sent = ScanNote {
+            direction: "sent".to_string(),
+            ..note.clone()
+        };
+        pg_handler
+            .save_scan_notes(vec![note.clone(), sent.clone(), note.clone()])
+            .await
+            .unwrap();
+        let notes = pg_handler
+            .get_scan_notes(ADDRESS.to_string(), block.sequence)
+            .await
+            .unwrap();
+        assert_eq!(notes.len(), 2);
+        pg_handler
+            .remove_scan_notes(ADDRESS.to_string(), block.sequence - 1)
+            .await
+            .unwrap();
+        let notes = pg_handler
+            .get_scan_notes(ADDRESS.to_string(), 0)
+            .await
+            .unwrap();
+        assert!(notes.is_empty());
+    }
+
     #[tokio::test]
     async fn save_blocks_should_work_pg() {
         let tdb = get_tdb();
@@ -6,7 +6,9 @@ use std::collections::HashMap;
 use substring::Substring;
 use tracing::info;
 
-use crate::{Account, DBHandler, InnerBlock};
+use crate::{
+    Account, ChainProgress, DBHandler, InnerBlock, ScanNote, ScanResult, ScanSession, Session,
+};
 
 pub const REDIS_ACCOUNT_KEY: &str = "IRONACCOUNT";
 pub const REDIS_ACCOUNT_KEY_V1: &str = "IRONACCOUNTV1";
@@ -64,7 +66,12 @@ impl DBHandler for RedisClient {
         "Redis".to_string()
     }
 
-    async fn save_account(&self, account: Account, _worker_id: u32) -> Result<String, OreoError> {
+    async fn save_account(
+        &self,
+        mut account: Account,
+        worker_id: u32,
+    ) -> Result<String, OreoError> {
+        account.worker_id = worker_id as i32;
         let address = account.address.clone();
         match self.hget(&self.db_name, &address).await {
             Ok(_) => {
@@ -143,6 +150,134 @@ impl DBHandler for RedisClient {
     async fn get_blocks(&self, _start: i64, _end: i64) -> Result<Vec<InnerBlock>, OreoError> {
         unimplemented!("Redis is deprecated for such feature!")
     }
+
+    async fn remove_blocks(&self, _start: i64, _end: i64) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn get_worker_loads(&self) -> Result<HashMap<u32, i64>, OreoError> {
+        // imports ask for loads, fail so they fall back instead of panicking
+        Err(OreoError::DBError)
+    }
+
+    async fn get_worker_accounts(&self, _worker_id: u32) -> Result<Vec<Account>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn update_account_worker(
+        &self,
+        _address: String,
+        _worker_id: u32,
+    ) -> Result<String, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn save_session(&self, _session: Session) -> Result<(), OreoError> {
+        // features added on top of redis need postgres, fail the request instead of the handler
+        Err(OreoError::DBError)
+    }
+
+    async fn get_session(&self, _sid: String) -> Result<Session, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn revoke_session(&self, _sid: String) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn revoke_sessions(&self, _address: String) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn save_scan_session(&self, _session: ScanSession) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn get_scan_sessions(&self) -> Result<Vec<ScanSession>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn claim_scan_sessions(
+        &self,
+        _owner: String,
+        _limit: i64,
+    ) -> Result<Vec<ScanSession>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn renew_scan_leases(
+        &self,
+        _owner: String,
+        _addresses: Vec<String>,
+    ) -> Result<Vec<String>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn revoke_scan_lease(&self, _address: String) -> Result<Option<String>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn release_scan_leases(
+        &self,
+        _owner: String,
+        _addresses: Vec<String>,
+    ) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn update_scan_watermark(
+        &self,
+        _address: String,
+        _sequence: i64,
+        _ranges: Vec<(i64, i64)>,
+    ) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn claim_scanner_lease(
+        &self,
+        _name: String,
+        _owner: String,
+        _seconds: i64,
+    ) -> Result<bool, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn save_scan_result(&self, _result: ScanResult) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn get_scan_results(&self, _address: String) -> Result<Vec<ScanResult>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn remove_scan_session(&self, _address: String) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn save_scan_notes(&self, _notes: Vec<ScanNote>) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn get_scan_notes(
+        &self,
+        _address: String,
+        _start: i64,
+    ) -> Result<Vec<ScanNote>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn remove_scan_notes(&self, _address: String, _sequence: i64) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn get_chain_progress(&self) -> Result<Option<ChainProgress>, OreoError> {
+        Err(OreoError::DBError)
+    }
+
+    async fn update_chain_progress(&self, _progress: ChainProgress) -> Result<(), OreoError> {
+        Err(OreoError::DBError)
+    }
 }
 
 pub fn address_to_name(address: &str) -> String {
@@ -169,6 +304,7 @@ mod tests {
     use crate::load_db;
     use crate::Account;
     use crate::DBHandler;
+    use crate::Json;
     use params::{mainnet::Mainnet, network::Network};
 
     const VK: &str = "4ae4eb9606ba57b3b17a444100a9ac6453cd67e6fe4c860e63a2e18b1200978ab5ecce68e8639d5016cbe73b0ea9a3c8e906fc881af2e9ccfa7a7b63fb73d555";
@@ -188,6 +324,8 @@ mod tests {
             vk: VK.to_string(),
             address: ADDRESS.to_string(),
             need_scan: false,
+            worker_id: 0,
+            scan_options: Json::default(),
         }
     }
 
@@ -1,11 +1,20 @@
-use std::collections::HashSet;
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::collections::{HashMap, HashSet};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::anyhow;
+use db_handler::ScanOptions;
 use futures::{SinkExt, StreamExt};
-use ironfish_rust::{IncomingViewKey, MerkleNote, OutgoingViewKey};
-use networking::socket_message::codec::{
-    DMessage, DMessageCodec, DRequest, DResponse, RegisterWorker, SingleRequest,
+use ironfish_rust::{IncomingViewKey, MerkleNote, Note, OutgoingViewKey};
+use metrics::{counter, histogram};
+use networking::socket_message::{
+    codec::{
+        DBatchRequest, DBatchResponse, DMessage, DRequest, DResponse, DecryptedNote, NoteBytes,
+        NoteDirection, RegisterKeys, RegisterWorker, ReleaseKeys, SingleRequest, ViewKeys,
+    },
+    handshake::TransportConfig,
 };
 use rayon::prelude::*;
 use rayon::{iter::IntoParallelIterator, ThreadPool};
@@ -14,10 +23,132 @@ use tokio::{
     io::split,
     net::TcpStream,
     sync::{mpsc, oneshot},
+    task::spawn_blocking,
     time::sleep,
 };
 use tokio_util::codec::{FramedRead, FramedWrite};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+use zeroize::Zeroizing;
+
+/// Whether a note selected by `options` is received or spent by the account.
+pub fn note_matches(
+    note: &MerkleNote,
+    in_vk: &IncomingViewKey,
+    out_vk: &OutgoingViewKey,
+    decrypt_for_spender: bool,
+    options: &ScanOptions,
+) -> bool {
+    if options.incoming() {
+        if let Ok(received_note) = note.decrypt_note_for_owner(in_vk) {
+            if options.matches(
+                &asset_id(&received_note),
+                received_note.value(),
+                &received_note.memo().0,
+            ) {
+                return true;
+            }
+        }
+    }
+    if decrypt_for_spender && options.outgoing() {
+        if let Ok(spend_note) = note.decrypt_note_for_spender(out_vk) {
+            if options.matches(
+                &asset_id(&spend_note),
+                spend_note.value(),
+                &spend_note.memo().0,
+            ) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn asset_id(note: &Note) -> String {
+    hex::encode(note.asset_id().as_bytes())
+}
+
+/// Details of the notes selected by `options` received or spent by the account, `index` is
+/// the position of the note in transaction `tx_hash`.
+pub fn decrypt_note(
+    note: &MerkleNote,
+    index: usize,
+    tx_hash: &str,
+    in_vk: &IncomingViewKey,
+    out_vk: &OutgoingViewKey,
+    decrypt_for_spender: bool,
+    options: &ScanOptions,
+) -> Vec<DecryptedNote> {
+    let mut decrypted = vec![];
+    if options.incoming() {
+        if let Ok(received_note) = note.decrypt_note_for_owner(in_vk) {
+            decrypted.push((NoteDirection::Received, received_note));
+        }
+    }
+    if decrypt_for_spender && options.outgoing() {
+        if let Ok(spend_note) = note.decrypt_note_for_spender(out_vk) {
+            decrypted.push((NoteDirection::Sent, spend_note));
+        }
+    }
+    decrypted
+        .into_iter()
+        .map(|(direction, note)| (direction, asset_id(&note), note))
+        .filter(|(_, asset_id, note)| options.matches(asset_id, note.value(), &note.memo().0))
+        .map(|(direction, asset_id, note)| DecryptedNote {
+            tx_hash: tx_hash.to_string(),
+            index: index as u32,
+            direction,
+            value: note.value(),
+            asset_id,
+            memo: hex::encode(note.memo().0),
+            sender: note.sender().hex_public_address(),
+        })
+        .collect()
+}
+
+/// Notes of a transaction which match the account, `None` if none does. Details are
+/// only decrypted if `with_notes` is set.
+fn match_transaction(
+    notes: &[(usize, MerkleNote)],
+    tx_hash: &str,
+    in_vk: &IncomingViewKey,
+    out_vk: &OutgoingViewKey,
+    decrypt_for_spender: bool,
+    options: &ScanOptions,
+    with_notes: bool,
+) -> Option<Vec<DecryptedNote>> {
+    match with_notes {
+        true => {
+            let decrypted: Vec<DecryptedNote> = notes
+                .iter()
+                .flat_map(|(index, note)| {
+                    decrypt_note(
+                        note,
+                        *index,
+                        tx_hash,
+                        in_vk,
+                        out_vk,
+                        decrypt_for_spender,
+                        options,
+                    )
+                })
+                .collect();
+            (!decrypted.is_empty()).then_some(decrypted)
+        }
+        false => notes
+            .iter()
+            .any(|(_, note)| note_matches(note, in_vk, out_vk, decrypt_for_spender, options))
+            .then(Vec::new),
+    }
+}
+
+/// Parsed notes of a transaction with their position, invalid ones are dropped.
+fn read_notes(serialized_note: &[NoteBytes]) -> Vec<(usize, MerkleNote)> {
+    serialized_note
+        .iter()
+        .enumerate()
+        .filter_map(|(index, note)| Some((index, MerkleNote::read(&note.0[..]).ok()?)))
+        .collect()
+}
 
 pub async fn decrypt(worker_pool: Arc<ThreadPool>, request: DRequest) -> DResponse {
     let DRequest {
@@ -27,74 +158,149 @@ pub async fn decrypt(worker_pool: Arc<ThreadPool>, request: DRequest) -> DRespon
         outgoing_view_key,
         decrypt_for_spender,
         data,
+        with_notes,
+        options,
     } = request;
-    let mut target_hash = vec![];
     let in_vk = IncomingViewKey::from_hex(&incoming_view_key);
     let out_vk = OutgoingViewKey::from_hex(&outgoing_view_key);
     if in_vk.is_err() || out_vk.is_err() {
         return DResponse {
             id,
-            data: target_hash,
+            data: vec![],
             address,
+            notes: vec![],
         };
     }
     let in_vk = in_vk.unwrap();
     let out_vk = out_vk.unwrap();
-    target_hash = worker_pool.install(move || {
-        let decrypted: HashSet<Option<String>> = data
+    let start = Instant::now();
+    let notes: usize = data.iter().map(|tx| tx.serialized_note.len()).sum();
+    let (target_hash, decrypted_notes) = worker_pool.install(move || {
+        let decrypted: Vec<(String, Vec<DecryptedNote>)> = data
             .into_par_iter()
-            .map(|data| {
+            .filter_map(|data| {
                 let SingleRequest {
                     serialized_note,
                     tx_hash,
                 } = data;
-                for note in serialized_note {
-                    let raw = hex::decode(note);
-                    match raw {
-                        Ok(raw) => {
-                            let note_enc = MerkleNote::read(&raw[..]);
-                            if let Ok(note_enc) = note_enc {
-                                if let Ok(received_note) = note_enc.decrypt_note_for_owner(&in_vk) {
-                                    if received_note.value() != 0 {
-                                        return Some(tx_hash);
-                                    }
-                                }
-
-                                if decrypt_for_spender {
-                                    if let Ok(spend_note) =
-                                        note_enc.decrypt_note_for_spender(&out_vk)
-                                    {
-                                        if spend_note.value() != 0 {
-                                            return Some(tx_hash);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        Err(_) => {}
-                    }
-                }
-                return None;
+                let notes = read_notes(&serialized_note);
+                match_transaction(
+                    &notes,
+                    &tx_hash,
+                    &in_vk,
+                    &out_vk,
+                    decrypt_for_spender,
+                    &options,
+                    with_notes,
+                )
+                .map(|decrypted| (tx_hash, decrypted))
             })
             .collect();
-        decrypted.into_iter().flatten().collect()
+        let hashes: HashSet<String> = decrypted.iter().map(|(hash, _)| hash.clone()).collect();
+        let notes = decrypted.into_iter().flat_map(|(_, notes)| notes).collect();
+        (hashes.into_iter().collect(), notes)
     });
+    counter!("dworker_tasks_total").increment(1);
+    counter!("dworker_notes_decrypted_total").increment(notes as u64);
+    histogram!("dworker_task_duration_seconds").record(start.elapsed().as_secs_f64());
     DResponse {
         id,
         data: target_hash,
         address,
+        notes: decrypted_notes,
+    }
+}
+
+/// Try the view keys of every account against every note of the block, so the notes are
+/// shipped and parsed once however many accounts are scanned.
+/// `accounts` are the registered keys and scan options of the accounts of the request.
+pub fn decrypt_batch(
+    worker_pool: Arc<ThreadPool>,
+    request: DBatchRequest,
+    accounts: Vec<(Zeroizing<ViewKeys>, ScanOptions)>,
+) -> DBatchResponse {
+    let DBatchRequest {
+        id,
+        decrypt_for_spender,
+        data,
+        with_notes,
+        ..
+    } = request;
+    let keys: Vec<(String, IncomingViewKey, OutgoingViewKey, ScanOptions)> = accounts
+        .into_iter()
+        .filter_map(|(keys, options)| {
+            match (
+                IncomingViewKey::from_hex(&keys.incoming_view_key),
+                OutgoingViewKey::from_hex(&keys.outgoing_view_key),
+            ) {
+                (Ok(in_vk), Ok(out_vk)) => Some((keys.address.clone(), in_vk, out_vk, options)),
+                _ => None,
+            }
+        })
+        .collect();
+    let start = Instant::now();
+    let notes: usize = data.iter().map(|tx| tx.serialized_note.len()).sum();
+    let trials = notes * keys.len();
+    let (matched, decrypted_notes) = worker_pool.install(move || {
+        let matched: Vec<(usize, String, Vec<DecryptedNote>)> = data
+            .into_par_iter()
+            .flat_map_iter(|data| {
+                let SingleRequest {
+                    serialized_note,
+                    tx_hash,
+                } = data;
+                let notes = read_notes(&serialized_note);
+                keys.iter()
+                    .enumerate()
+                    .filter_map(|(index, (_, in_vk, out_vk, options))| {
+                        match_transaction(
+                            &notes,
+                            &tx_hash,
+                            in_vk,
+                            out_vk,
+                            decrypt_for_spender,
+                            options,
+                            with_notes,
+                        )
+                        .map(|decrypted| (index, tx_hash.clone(), decrypted))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut result: HashMap<String, Vec<String>> = HashMap::new();
+        let mut notes: HashMap<String, Vec<DecryptedNote>> = HashMap::new();
+        for (index, tx_hash, decrypted) in matched {
+            let address = &keys[index].0;
+            result.entry(address.clone()).or_default().push(tx_hash);
+            if !decrypted.is_empty() {
+                notes.entry(address.clone()).or_default().extend(decrypted);
+            }
+        }
+        (result, notes)
+    });
+    counter!("dworker_tasks_total").increment(1);
+    counter!("dworker_notes_decrypted_total").increment(trials as u64);
+    histogram!("dworker_task_duration_seconds").record(start.elapsed().as_secs_f64());
+    DBatchResponse {
+        id,
+        data: matched,
+        notes: decrypted_notes,
     }
 }
 
 pub async fn handle_connection(
     worker_pool: Arc<ThreadPool>,
-    stream: TcpStream,
+    mut stream: TcpStream,
     worker_name: String,
+    capacity: usize,
+    transport: &TransportConfig,
+    legacy_protocol: bool,
 ) -> anyhow::Result<()> {
     debug!("connected to scheduler");
+    let (reader, writer) = transport.connect(&mut stream, legacy_protocol).await?;
     let (r, w) = split(stream);
-    let mut socket_w_handler = FramedWrite::new(w, DMessageCodec::default());
-    let mut socket_r_handler = FramedRead::new(r, DMessageCodec::default());
+    let mut socket_w_handler = FramedWrite::new(w, writer);
+    let mut socket_r_handler = FramedRead::new(r, reader);
     let (tx, mut rx) = mpsc::channel::<DMessage>(1024);
 
     // send to scheduler loop
@@ -110,6 +316,15 @@ pub async fn handle_connection(
                         return Err(anyhow!("failed to send DResponse message"));
                     }
                 }
+                DMessage::DBatchResponse(response) => {
+                    if let Err(e) = socket_w_handler
+                        .send(DMessage::DBatchResponse(response))
+                        .await
+                    {
+                        error!("failed to send DBatchResponse message, {:?}", e);
+                        return Err(anyhow!("failed to send DBatchResponse message"));
+                    }
+                }
                 DMessage::RegisterWorker(register) => {
                     if let Err(e) = socket_w_handler
                         .send(DMessage::RegisterWorker(register))
@@ -134,6 +349,8 @@ pub async fn handle_connection(
     let (router, handler) = oneshot::channel();
     let read_channel_handler = tokio::spawn(async move {
         let _ = router.send(());
+        // registered keys by handle, zeroized when released or the connection ends
+        let mut view_keys: HashMap<u64, (Zeroizing<ViewKeys>, ScanOptions)> = HashMap::new();
         while let Some(Ok(message)) = socket_r_handler.next().await {
             match message {
                 DMessage::DRequest(request) => {
@@ -144,6 +361,51 @@ pub async fn handle_connection(
                         return Err(anyhow!("failed to send response to write channel"));
                     }
                 }
+                DMessage::RegisterKeys(RegisterKeys {
+                    handle,
+                    keys,
+                    options,
+                }) => {
+                    debug!("keys of {} registered as {}", keys.address, handle);
+                    let _ = view_keys.insert(handle, (Zeroizing::new(keys), options));
+                }
+                DMessage::ReleaseKeys(ReleaseKeys { handles }) => {
+                    for handle in handles {
+                        let _ = view_keys.remove(&handle);
+                    }
+                }
+                DMessage::DBatchRequest(request) => {
+                    debug!("new batch task from scheduler: {}", request.id.clone());
+                    let accounts: Vec<(Zeroizing<ViewKeys>, ScanOptions)> = request
+                        .accounts
+                        .iter()
+                        .filter_map(|account| match view_keys.get(&account.handle) {
+                            Some(keys) => Some(keys.clone()),
+                            None => {
+                                warn!("no keys registered for {}", account.address);
+                                None
+                            }
+                        })
+                        .collect();
+                    // tasks run side by side, the scheduler keeps at most `capacity` in flight
+                    let worker_pool = worker_pool.clone();
+                    let task_tx = task_tx.clone();
+                    tokio::spawn(async move {
+                        let response =
+                            spawn_blocking(move || decrypt_batch(worker_pool, request, accounts))
+                                .await;
+                        match response {
+                            Ok(response) => {
+                                if let Err(e) =
+                                    task_tx.send(DMessage::DBatchResponse(response)).await
+                                {
+                                    error!("failed to send response to write channel, {}", e);
+                                }
+                            }
+                            Err(e) => error!("decryption task failed, {}", e),
+                        }
+                    });
+                }
                 _ => {
                     error!("invalid message");
                     return Err(anyhow!("invalid message"));
@@ -162,6 +424,7 @@ pub async fn handle_connection(
             let _ = heart_beat_tx
                 .send(DMessage::RegisterWorker(RegisterWorker {
                     name: worker_name.clone(),
+                    capacity,
                 }))
                 .await
                 .unwrap();
@@ -184,34 +447,48 @@ pub async fn handle_connection(
     }
 }
 
-pub async fn reconnect_tcp(addr: SocketAddr) -> anyhow::Result<TcpStream> {
+pub async fn reconnect_tcp(addr: &str) -> anyhow::Result<TcpStream> {
     match timeout(Duration::from_secs(3), TcpStream::connect(addr)).await {
         Ok(Ok(stream)) => Ok(stream),
         _ => Err(anyhow!("failed to connect to dservice")),
     }
 }
 
-pub async fn start_worker(addr: SocketAddr, name: String) -> anyhow::Result<()> {
+pub async fn start_worker(
+    addr: String,
+    name: String,
+    capacity: Option<usize>,
+    transport: TransportConfig,
+    legacy_protocol: bool,
+) -> anyhow::Result<()> {
     let thread_pool = rayon::ThreadPoolBuilder::new()
         .num_threads(num_cpus::get())
         .build()
         .unwrap();
+    let capacity = capacity.unwrap_or(thread_pool.current_num_threads());
     let worker = Arc::new(thread_pool);
     let (router, handler) = oneshot::channel();
     tokio::spawn(async move {
         let _ = router.send(());
         loop {
             let stream = loop {
-                if let Ok(stream) = reconnect_tcp(addr.clone()).await {
+                if let Ok(stream) = reconnect_tcp(&addr).await {
                     break stream;
                 }
                 error!("can't connect to dservice, reconnect after 30 seconds");
                 sleep(Duration::from_secs(30)).await;
             };
 
-            if handle_connection(worker.clone(), stream, name.clone())
-                .await
-                .is_err()
+            if handle_connection(
+                worker.clone(),
+                stream,
+                name.clone(),
+                capacity,
+                &transport,
+                legacy_protocol,
+            )
+            .await
+            .is_err()
             {
                 error!("failed to init networking");
                 break;
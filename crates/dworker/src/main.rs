@@ -1,9 +1,11 @@
 use anyhow::Result;
 use dworker::start_worker;
+use networking::socket_message::handshake::TransportConfig;
 use rand::Rng;
 use tracing::info;
 use utils::{
-    handle_signals, initialize_logger, initialize_logger_filter, EnvFilter, Parser, Worker,
+    handle_signals, initialize_logger, initialize_logger_filter, initialize_metrics, EnvFilter,
+    Parser, Worker,
 };
 
 #[tokio::main]
@@ -12,6 +14,7 @@ async fn main() -> Result<()> {
     initialize_logger(args.verbosity);
     initialize_logger_filter(EnvFilter::from_default_env());
     handle_signals().await?;
+    initialize_metrics(args.metrics)?;
     if args.name.is_none() {
         args.name = Some(
             format!(
@@ -26,6 +29,19 @@ async fn main() -> Result<()> {
         "Start connecting to scheduler: {:?} with name {:?}",
         args.address, args.name
     );
-    start_worker(args.address, args.name.unwrap()).await?;
+    let transport = TransportConfig::new(
+        args.max_frame_size,
+        args.noise_key,
+        args.scanner_key.into_iter().collect(),
+        args.insecure_transport,
+    )?;
+    start_worker(
+        args.address,
+        args.name.unwrap(),
+        args.capacity,
+        transport,
+        args.legacy_protocol,
+    )
+    .await?;
     Ok(())
 }
@@ -1,3 +1,6 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use db_handler::ScanOptions;
 use serde::{Deserialize, Serialize};
 
 use crate::rpc_abi::BlockInfo;
@@ -16,9 +19,97 @@ pub struct ScanRequest {
     pub out_vk: String,
     pub address: String,
     pub head: Option<BlockInfo>,
+    #[serde(default)]
+    pub options: ScanOptions,
+    // scan without persisting anything or updating the node, see `DryRunResponse`
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SuccessResponse {
     pub success: bool,
 }
+
+/// Seconds a signed `ScannerQuery` stays valid.
+pub const SCANNER_QUERY_MAX_AGE: u64 = 60;
+
+/// Query or command for the scanner status api, `address` selects the account where one is
+/// needed. Signed like `ScanRequest`, the timestamp keeps it from being replayed later.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScannerQuery {
+    pub address: Option<String>,
+    pub timestamp: u64,
+}
+
+impl ScannerQuery {
+    pub fn new(address: Option<String>) -> Self {
+        Self {
+            address,
+            timestamp: unix_now(),
+        }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        unix_now().abs_diff(self.timestamp) <= SCANNER_QUERY_MAX_AGE
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// Progress of a scan session, `end_sequence` and the task counts are only known once
+/// the session is scheduled.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanProgress {
+    pub address: String,
+    // "queued" or "scanning"
+    pub status: String,
+    pub start_sequence: u64,
+    pub end_sequence: Option<u64>,
+    pub scanned_sequence: u64,
+    pub total_task: u64,
+    pub remaining_task: u64,
+    pub eta_seconds: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScanSessionsResponse {
+    pub sessions: Vec<ScanProgress>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScanStatusResponse {
+    pub address: String,
+    pub progress: Option<ScanProgress>,
+}
+
+/// A dry run scan of an account, `result` holds the matched blocks once it is finished.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DryRunResponse {
+    pub address: String,
+    pub progress: Option<ScanProgress>,
+    pub result: Option<ScanResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerStatus {
+    // authenticated key of the worker, or its connection on insecure transport
+    pub id: String,
+    pub name: String,
+    pub capacity: usize,
+    pub in_flight: usize,
+    pub quarantined: bool,
+    pub tasks_per_minute: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WorkersResponse {
+    pub workers: Vec<WorkerStatus>,
+}
@@ -9,19 +9,19 @@ pub mod web_abi;
 
 use db_handler::{DBTransaction, InnerBlock, Json};
 use rpc_abi::RpcBlock;
-pub use ureq;
 
 impl RpcBlock {
     pub fn to_inner(self) -> InnerBlock {
         let RpcBlock {
             hash,
             sequence,
-            previous_block_hash: _,
+            previous_block_hash,
             transactions,
         } = self;
         InnerBlock {
             hash,
             sequence: sequence as i64,
+            previous_block_hash: Some(previous_block_hash),
             transactions: Json(
                 transactions
                     .into_iter()
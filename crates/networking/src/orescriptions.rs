@@ -1,7 +1,8 @@
 use params::network::Network;
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::rpc_abi::AssetBalance;
+use crate::{rpc_abi::AssetBalance, rpc_handler::RPC_TIMEOUT};
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -20,21 +21,29 @@ pub fn is_ores_local<N: Network>(asset: &AssetBalance) -> bool {
         && asset.unconfirmed == "1".to_string()
 }
 
-pub async fn get_ores<N: Network>(id: &str) -> anyhow::Result<Ores> {
+pub async fn get_ores<N: Network>(client: &Client, id: &str) -> anyhow::Result<Ores> {
     let path = format!("{}/orescription/{}", N::OREOSRIPTIONS_ENDPOINT, id);
-    Ok(ureq::get(&path).call()?.into_json()?)
+    Ok(client
+        .get(&path)
+        .timeout(RPC_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
 }
 
 #[cfg(test)]
 mod tests {
     use params::mainnet::Mainnet;
+    use reqwest::Client;
 
     use crate::orescriptions::get_ores;
 
     #[tokio::test]
     pub async fn check_ores_should_work() {
         let asset_id = "6272e464d84761d9c6247d9d4d2feb42964a5b2a71b9b179df27bbe0730c88af";
-        let ores = get_ores::<Mainnet>(asset_id).await;
+        let ores = get_ores::<Mainnet>(&Client::new(), asset_id).await;
         assert!(ores.is_ok());
         match ores {
             Ok(ore) => {
@@ -47,7 +56,7 @@ mod tests {
     #[tokio::test]
     pub async fn check_ores_should_fail() {
         let asset_id = "6272e464d84761d9c6247d9d4d2feb42964a5b2a71b9b179df27bbe0x30c88af";
-        let ores = get_ores::<Mainnet>(asset_id).await;
+        let ores = get_ores::<Mainnet>(&Client::new(), asset_id).await;
         assert!(ores.is_err());
         match ores {
             Ok(ore) => {
@@ -1,9 +1,13 @@
 use axum::{response::IntoResponse, Json};
 use params::network::Network;
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use ureq::json;
+use serde_json::json;
 
-use crate::orescriptions::{get_ores, is_ores_local, Ores};
+use crate::{
+    decryption_message::ScanProgress,
+    orescriptions::{get_ores, is_ores_local, Ores},
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RpcResponse<T> {
@@ -85,6 +89,9 @@ pub struct AccountStatus {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RpcGetAccountStatusResponse {
     pub account: AccountStatus,
+    // progress of a quick scan in the scanner, filled in by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan: Option<ScanProgress>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -179,13 +186,13 @@ impl RpcGetBalancesResponse {
         }
     }
 
-    pub async fn ores<N: Network>(base: Self) -> Vec<Ores> {
+    pub async fn ores<N: Network>(client: &Client, base: Self) -> Vec<Ores> {
         let mut result = vec![];
         for asset in base.balances.iter() {
             if !is_ores_local::<N>(asset) {
                 continue;
             }
-            if let Ok(ores) = get_ores::<N>(&asset.asset_id).await {
+            if let Ok(ores) = get_ores::<N>(client, &asset.asset_id).await {
                 result.push(ores);
             }
         }
@@ -266,6 +273,8 @@ pub struct RpcGetTransactionsRequest {
     pub limit: Option<u32>,
     pub offset: Option<u32>,
     pub reverse: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -278,6 +287,9 @@ pub struct TransactionStatus {
     pub block_sequence: Option<u64>,
     pub timestamp: u64,
     pub asset_balance_deltas: Vec<AssetBalanceDelta>,
+    // only returned when requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Vec<RpcNote>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -300,7 +312,7 @@ pub struct AssetBalanceDelta {
     pub asset_name: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RpcNote {
     pub value: String,
     pub memo: String,
@@ -333,6 +345,12 @@ pub struct RpcGetBlockRequest {
     pub serialized: Option<bool>,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RpcGetBlockByHashRequest {
+    pub hash: String,
+    pub serialized: Option<bool>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct BlockIdentifier {
     pub index: String,
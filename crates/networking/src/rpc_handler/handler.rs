@@ -1,335 +1,259 @@
-use std::{fmt::Debug, time::Duration};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
+use futures::future::join_all;
+use metrics::gauge;
 use oreo_errors::OreoError;
-use serde::Deserialize;
-use serde_json::json;
-use tracing::debug;
-use ureq::{Agent, AgentBuilder, Error, Response};
+use tokio::{
+    sync::{oneshot, RwLock},
+    time::sleep,
+};
+use tracing::{info, warn};
 
 use crate::{
     rpc_abi::{
-        RpcAddTxRequest, RpcAddTxResponse, RpcAsset, RpcCreateTxRequest, RpcCreateTxResponse,
-        RpcExportAccountResponse, RpcGetAccountStatusRequest, RpcGetAccountStatusResponse,
-        RpcGetAccountTransactionRequest, RpcGetAccountTransactionResponse, RpcGetBalancesRequest,
-        RpcGetBalancesResponse, RpcGetBlockRequest, RpcGetBlockResponse, RpcGetBlocksRequest,
-        RpcGetBlocksResponse, RpcGetLatestBlockResponse, RpcGetTransactionsRequest,
-        RpcGetTransactionsResponse, RpcImportAccountRequest, RpcImportAccountResponse,
-        RpcRemoveAccountRequest, RpcRemoveAccountResponse, RpcResetAccountRequest, RpcResponse,
-        RpcSetAccountHeadRequest, RpcSetAccountHeadRequestV2, RpcSetScanningRequest,
-        SendTransactionRequest, SendTransactionResponse, TransactionStatus,
+        RpcAsset, RpcGetBlockResponse, RpcGetBlocksResponse, RpcGetLatestBlockResponse, RpcResponse,
     },
-    rpc_handler::RpcError,
-    stream::ResponseExt,
+    rpc_handler::RpcNode,
 };
 
+/// Nodes whose head is more than this many blocks behind the best known tip are unhealthy.
+pub const MAX_NODE_LAG: u64 = 10;
+/// Interval between two rounds of node health checks.
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone)]
-pub struct RpcHandler {
-    pub endpoint: String,
-    pub agent: Agent,
+pub struct NodeHealth {
+    pub healthy: bool,
+    pub head: u64,
 }
 
-impl RpcHandler {
-    pub fn new(endpoint: String) -> Self {
+impl Default for NodeHealth {
+    fn default() -> Self {
+        // Nodes are trusted until the first health check says otherwise.
         Self {
-            endpoint,
-            agent: AgentBuilder::new()
-                .timeout_read(Duration::from_secs(30))
-                .timeout_write(Duration::from_secs(30))
-                .build(),
+            healthy: true,
+            head: 0,
         }
     }
+}
 
-    pub fn import_account(
-        &self,
-        request: RpcImportAccountRequest,
-    ) -> Result<RpcResponse<RpcImportAccountResponse>, OreoError> {
-        let path = format!("http://{}/wallet/importAccount", self.endpoint);
-        let account_str = serde_json::to_string(&request)
-            .map_err(|_| OreoError::InternalRpcError("JSON serialization failed".to_string()))?;
-        let resp = self
-            .agent
-            .clone()
-            .post(&path)
-            .send_json(ureq::json!({"account": account_str}));
-        handle_response(resp)
-    }
-
-    pub fn export_account(
-        &self,
-        account: String,
-    ) -> Result<RpcResponse<RpcExportAccountResponse>, OreoError> {
-        let path = format!("http://{}/wallet/exportAccount", self.endpoint);
-        let resp = self
-            .agent
-            .clone()
-            .post(&path)
-            .send_json(json!({"account": account, "format": "JSON".to_string()}));
-        handle_response(resp)
-    }
-
-    pub fn remove_account(
-        &self,
-        request: RpcRemoveAccountRequest,
-    ) -> Result<RpcResponse<RpcRemoveAccountResponse>, OreoError> {
-        debug!("req: {:?}", request);
-        let path = format!("http://{}/wallet/removeAccount", self.endpoint);
-        let resp = self
-            .agent
-            .clone()
-            .post(&path)
-            .send_json(&request)
-            .map(|res| match res.status() {
-                200 => Response::new(200, "OK", "{\"status\":200,\"data\":{\"removed\":true}}")
-                    .unwrap(),
-                _ => res,
-            });
-        handle_response(resp)
-    }
-
-    pub fn get_account_status(
-        &self,
-        request: RpcGetAccountStatusRequest,
-    ) -> Result<RpcResponse<RpcGetAccountStatusResponse>, OreoError> {
-        let path = format!("http://{}/wallet/getAccountStatus", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
-    }
+/// Routes rpc calls over a set of Ironfish nodes.
+///
+/// Chain reads go to any synced node and fail over to the next one when it is unreachable,
+/// wallet calls are pinned to the node that owns the account, see `wallet`.
+#[derive(Debug, Clone)]
+pub struct RpcHandler {
+    pub nodes: Vec<RpcNode>,
+    pub health: Arc<RwLock<Vec<NodeHealth>>>,
+    cursor: Arc<AtomicUsize>,
+}
 
-    pub fn set_scanning(
-        &self,
-        request: RpcSetScanningRequest,
-    ) -> Result<RpcResponse<Option<()>>, OreoError> {
-        let path = format!("http://{}/wallet/setScanning", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+impl RpcHandler {
+    pub fn new(endpoints: Vec<String>) -> Self {
+        assert!(!endpoints.is_empty(), "At least one rpc node is required");
+        let nodes: Vec<RpcNode> = endpoints.into_iter().map(RpcNode::new).collect();
+        let health = vec![NodeHealth::default(); nodes.len()];
+        Self {
+            nodes,
+            health: Arc::new(RwLock::new(health)),
+            cursor: Arc::new(AtomicUsize::new(0)),
+        }
     }
 
-    pub fn set_account_head(
-        &self,
-        request: RpcSetAccountHeadRequest,
-    ) -> Result<RpcResponse<Option<()>>, OreoError> {
-        let RpcSetAccountHeadRequest {
-            account,
-            start,
-            end,
-            blocks,
-            ..
-        } = request;
-        let request = RpcSetAccountHeadRequestV2 {
-            account,
-            start,
-            end,
-            blocks,
-        };
-        let path = format!("http://{}/wallet/setAccountHead", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+    /// The node whose wallet holds accounts assigned to `worker_id`.
+    pub fn wallet(&self, worker_id: u32) -> Result<&RpcNode, OreoError> {
+        self.nodes.get(worker_id as usize).ok_or_else(|| {
+            OreoError::InternalRpcError(format!("No rpc node configured for worker {}", worker_id))
+        })
     }
 
-    pub fn reset_account(
-        &self,
-        request: RpcResetAccountRequest,
-    ) -> Result<RpcResponse<Option<()>>, OreoError> {
-        let path = format!("http://{}/wallet/resetAccount", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+    pub async fn check_health(&self) {
+        let heads: Vec<Option<u64>> = join_all(self.nodes.iter().map(|node| async move {
+            node.get_latest_block()
+                .await
+                .ok()
+                .and_then(|res| res.data.current_block_identifier.index.parse::<u64>().ok())
+        }))
+        .await;
+        let tip = heads.iter().flatten().max().cloned().unwrap_or(0);
+        let mut health = self.health.write().await;
+        for (id, head) in heads.into_iter().enumerate() {
+            let status = match head {
+                Some(head) => NodeHealth {
+                    healthy: head + MAX_NODE_LAG >= tip,
+                    head,
+                },
+                None => NodeHealth {
+                    healthy: false,
+                    head: health[id].head,
+                },
+            };
+            if health[id].healthy && !status.healthy {
+                warn!(
+                    "Rpc node {} marked unhealthy, head {} tip {}",
+                    self.nodes[id].endpoint, status.head, tip
+                );
+            }
+            if !health[id].healthy && status.healthy {
+                info!("Rpc node {} is healthy again", self.nodes[id].endpoint);
+            }
+            let endpoint = self.nodes[id].endpoint.clone();
+            gauge!("rpc_node_healthy", "node" => endpoint.clone()).set(if status.healthy {
+                1.0
+            } else {
+                0.0
+            });
+            gauge!("rpc_node_head", "node" => endpoint).set(status.head as f64);
+            health[id] = status;
+        }
     }
 
-    pub fn get_balances(
-        &self,
-        request: RpcGetBalancesRequest,
-    ) -> Result<RpcResponse<RpcGetBalancesResponse>, OreoError> {
-        let path = format!("http://{}/wallet/getBalances", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+    pub async fn initialize_health_checker(&self) {
+        let handler = self.clone();
+        let (router, waiter) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = router.send(());
+            loop {
+                handler.check_health().await;
+                sleep(HEALTH_CHECK_INTERVAL).await;
+            }
+        });
+        let _ = waiter.await;
+        info!("Rpc health checker installed!");
     }
 
-    pub fn get_account_transaction(
-        &self,
-        request: RpcGetAccountTransactionRequest,
-    ) -> Result<RpcResponse<RpcGetAccountTransactionResponse>, OreoError> {
-        let path = format!("http://{}/wallet/getAccountTransaction", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+    /// Node ids to try for a chain read, healthy ones first starting from a rotating cursor.
+    async fn candidates(&self) -> Vec<usize> {
+        let count = self.nodes.len();
+        let start = self.cursor.fetch_add(1, Ordering::Relaxed) % count;
+        let health = self.health.read().await;
+        let (mut healthy, unhealthy): (Vec<usize>, Vec<usize>) = (0..count)
+            .map(|offset| (start + offset) % count)
+            .partition(|id| health[*id].healthy);
+        healthy.extend(unhealthy);
+        healthy
     }
 
-    pub fn get_transactions(
-        &self,
-        request: RpcGetTransactionsRequest,
-    ) -> Result<RpcResponse<RpcGetTransactionsResponse>, OreoError> {
-        let path = format!("http://{}/wallet/getAccountTransactions", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-
-        match resp {
-            Ok(response) => {
-                let transactions: Result<Vec<_>, OreoError> =
-                    response.into_stream::<TransactionStatus>().collect();
-
-                Ok(RpcResponse {
-                    status: 200,
-                    data: RpcGetTransactionsResponse {
-                        transactions: transactions?,
-                    },
-                })
+    async fn route<'a, T, F, Fut>(&'a self, call: F) -> Result<T, OreoError>
+    where
+        F: Fn(&'a RpcNode) -> Fut,
+        Fut: Future<Output = Result<T, OreoError>> + 'a,
+    {
+        let mut last_error = OreoError::InternalRpcError("No rpc node available".to_string());
+        for id in self.candidates().await {
+            match call(&self.nodes[id]).await {
+                Err(OreoError::RpcUnreachable(e)) => {
+                    warn!("Rpc node {} failed, {}", self.nodes[id].endpoint, e);
+                    self.health.write().await[id].healthy = false;
+                    last_error = OreoError::RpcUnreachable(e);
+                }
+                // the node answered, another one would answer the same
+                result => return result,
             }
-            Err(e) => Err(OreoError::InternalRpcError(e.to_string())),
         }
+        Err(last_error)
     }
 
-    pub fn create_transaction(
+    pub async fn get_latest_block(
         &self,
-        request: RpcCreateTxRequest,
-    ) -> Result<RpcResponse<RpcCreateTxResponse>, OreoError> {
-        let path = format!("http://{}/wallet/createTransaction", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+    ) -> Result<RpcResponse<RpcGetLatestBlockResponse>, OreoError> {
+        self.route(|node| node.get_latest_block()).await
     }
 
-    pub fn add_transaction(
+    pub async fn get_block(
         &self,
-        request: RpcAddTxRequest,
-    ) -> Result<RpcResponse<RpcAddTxResponse>, OreoError> {
-        let path = format!("http://{}/wallet/addTransaction", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
-    }
-
-    pub fn get_latest_block(&self) -> Result<RpcResponse<RpcGetLatestBlockResponse>, OreoError> {
-        let path = format!("http://{}/chain/getChainInfo", self.endpoint);
-        let resp = self.agent.clone().get(&path).call();
-        handle_response(resp)
+        sequence: i64,
+    ) -> Result<RpcResponse<RpcGetBlockResponse>, OreoError> {
+        self.route(|node| node.get_block(sequence)).await
     }
 
-    pub fn get_block(&self, sequence: i64) -> Result<RpcResponse<RpcGetBlockResponse>, OreoError> {
-        let path = format!("http://{}/chain/getBlock", self.endpoint);
-        let resp = self
-            .agent
-            .clone()
-            .post(&path)
-            .send_json(RpcGetBlockRequest {
-                sequence,
-                serialized: Some(true),
-            });
-        handle_response(resp)
+    pub async fn get_block_by_hash(
+        &self,
+        hash: String,
+    ) -> Result<RpcResponse<RpcGetBlockResponse>, OreoError> {
+        self.route(|node| node.get_block_by_hash(hash.clone()))
+            .await
     }
 
-    pub fn get_blocks(
+    pub async fn get_blocks(
         &self,
         start: u64,
         end: u64,
     ) -> Result<RpcResponse<RpcGetBlocksResponse>, OreoError> {
-        let path = format!("http://{}/chain/getBlocks", self.endpoint);
-        let resp = self
-            .agent
-            .clone()
-            .post(&path)
-            .send_json(RpcGetBlocksRequest {
-                start,
-                end,
-                serialized: true,
-            });
-        handle_response(resp)
-    }
-
-    pub fn send_transaction(
-        &self,
-        request: SendTransactionRequest,
-    ) -> Result<RpcResponse<SendTransactionResponse>, OreoError> {
-        let path = format!("http://{}/wallet/sendTransaction", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(request);
-        handle_response(resp)
-    }
-
-    pub fn get_asset(&self, id: String) -> Result<RpcResponse<RpcAsset>, OreoError> {
-        let path = format!("http://{}/chain/getAsset", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(json!({"id": id}));
-        handle_response(resp)
+        self.route(|node| node.get_blocks(start, end)).await
     }
-}
 
-pub fn handle_response<S: Debug + for<'a> Deserialize<'a>>(
-    resp: Result<Response, Error>,
-) -> Result<RpcResponse<S>, OreoError> {
-    let res = match resp {
-        Ok(response) => match response.into_json::<RpcResponse<S>>() {
-            Ok(data) => Ok(data),
-            Err(e) => Err(RpcError {
-                code: "Unknown".into(),
-                status: 606,
-                message: e.to_string(),
-            }),
-        },
-        Err(ureq::Error::Status(_code, response)) => match response.into_json::<RpcError>() {
-            Ok(data) => Err(data),
-            Err(e) => Err(RpcError {
-                code: "Unknown".into(),
-                status: 606,
-                message: e.to_string(),
-            }),
-        },
-        Err(e) => Err(RpcError {
-            code: "Unknown".into(),
-            status: 606,
-            message: e.to_string(),
-        }),
-    };
-    debug!("Handle rpc response: {:?}", res);
-    match res {
-        Ok(data) => Ok(data),
-        Err(e) => Err(OreoError::try_from(e).unwrap()),
+    pub async fn get_asset(&self, id: String) -> Result<RpcResponse<RpcAsset>, OreoError> {
+        self.route(|node| node.get_asset(id.clone())).await
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rpc_abi::{RpcResetAccountRequest, RpcSetScanningRequest};
+    use axum::{
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+    use serde_json::json;
 
     use super::RpcHandler;
+    use crate::rpc_handler::node::tests::{chain_info, mock_node};
 
-    #[test]
-    pub fn get_block_should_work() {
-        let rpc_handler = RpcHandler::new("127.0.0.1:8021".into());
-        let block = rpc_handler.get_latest_block().unwrap().data;
-        println!("{:?}", block);
+    #[tokio::test]
+    pub async fn unreachable_node_should_be_unhealthy() {
+        let endpoint =
+            mock_node(Router::new().route("/chain/getChainInfo", get(|| async { chain_info(12) })))
+                .await;
+        let rpc_handler = RpcHandler::new(vec![endpoint, "127.0.0.1:1".into()]);
+        rpc_handler.check_health().await;
+        let health = rpc_handler.health.read().await;
+        assert!(health[0].healthy);
+        assert_eq!(health[0].head, 12);
+        assert!(!health[1].healthy);
     }
 
-    #[test]
-    pub fn get_blocks_should_work() {
-        let rpc_handler = RpcHandler::new("127.0.0.1:8021".into());
-        let blocks = rpc_handler.get_blocks(3, 12).unwrap().data;
-        println!("{:?}", blocks.blocks);
-    }
-
-    #[test]
-    pub fn set_scanning_should_work() {
-        let rpc_handler = RpcHandler::new("127.0.0.1:8021".into());
-        let result = rpc_handler.set_scanning(RpcSetScanningRequest {
-            account: "test".to_string(),
-            enabled: false,
-        });
-        assert!(result.is_ok());
-    }
-
-    #[test]
-    pub fn reset_account_should_work() {
-        let rpc_handler = RpcHandler::new("127.0.0.1:8021".into());
-        let result = rpc_handler.reset_account(RpcResetAccountRequest {
-            account: "test".to_string(),
-            reset_created_at: Some(false),
-            reset_scanning_enabled: Some(false),
-        });
-        assert!(result.is_ok());
+    #[tokio::test]
+    pub async fn chain_reads_should_fail_over() {
+        let endpoint =
+            mock_node(Router::new().route("/chain/getChainInfo", get(|| async { chain_info(12) })))
+                .await;
+        let rpc_handler = RpcHandler::new(vec!["127.0.0.1:1".into(), endpoint]);
+        for _ in 0..2 {
+            let block = rpc_handler.get_latest_block().await.unwrap().data;
+            assert_eq!(block.current_block_identifier.index, "12");
+        }
+        assert!(!rpc_handler.health.read().await[0].healthy);
     }
 
-    #[test]
-    pub fn get_asset_should_work() {
-        let rpc_handler = RpcHandler::new("127.0.0.1:8021".into());
-        let header = rpc_handler.get_latest_block();
-        println!("chain header {:?}", header);
-        let result = rpc_handler.get_asset(
-            "8e36e31d677a47cbd883843a345654c814b1e9ec1e0125bac9031f052ced9174".to_string(),
-        );
-        println!("asset info: {:?}", result);
-        assert!(result.is_ok());
+    #[tokio::test]
+    pub async fn rpc_errors_should_not_fail_over() {
+        let failing = || {
+            Router::new().route(
+                "/chain/getAsset",
+                post(|| async {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({"code": "not-found", "status": 400, "message": "no asset"})),
+                    )
+                }),
+            )
+        };
+        let rpc_handler =
+            RpcHandler::new(vec![mock_node(failing()).await, mock_node(failing()).await]);
+        assert!(rpc_handler.get_asset("id".into()).await.is_err());
+        assert!(rpc_handler
+            .health
+            .read()
+            .await
+            .iter()
+            .all(|health| health.healthy));
     }
 }
@@ -2,8 +2,10 @@ use oreo_errors::OreoError;
 use serde::{Deserialize, Serialize};
 
 mod handler;
+mod node;
 
 pub use handler::*;
+pub use node::*;
 use tracing::error;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -0,0 +1,563 @@
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use metrics::histogram;
+use oreo_errors::OreoError;
+use reqwest::{Client, Response};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::debug;
+
+use crate::{
+    rpc_abi::{
+        RpcAddTxRequest, RpcAddTxResponse, RpcAsset, RpcCreateTxRequest, RpcCreateTxResponse,
+        RpcExportAccountResponse, RpcGetAccountStatusRequest, RpcGetAccountStatusResponse,
+        RpcGetAccountTransactionRequest, RpcGetAccountTransactionResponse, RpcGetBalancesRequest,
+        RpcGetBalancesResponse, RpcGetBlockByHashRequest, RpcGetBlockRequest, RpcGetBlockResponse,
+        RpcGetBlocksRequest, RpcGetBlocksResponse, RpcGetLatestBlockResponse,
+        RpcGetTransactionsRequest, RpcGetTransactionsResponse, RpcImportAccountRequest,
+        RpcImportAccountResponse, RpcRemoveAccountRequest, RpcRemoveAccountResponse,
+        RpcResetAccountRequest, RpcResponse, RpcSetAccountHeadRequest, RpcSetAccountHeadRequestV2,
+        RpcSetScanningRequest, SendTransactionRequest, SendTransactionResponse, TransactionStatus,
+    },
+    rpc_handler::RpcError,
+    stream::read_stream_while,
+};
+
+/// Timeout for a single rpc call.
+pub const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+/// Timeout for rpc calls which may return a lot of data, e.g. getBlocks.
+pub const RPC_LONG_TIMEOUT: Duration = Duration::from_secs(120);
+/// Timeout for establishing a new connection to the node.
+pub const RPC_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Max in-flight rpc calls to one node.
+pub const RPC_MAX_CONCURRENT: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct RpcNode {
+    pub endpoint: String,
+    pub client: Client,
+    pub limiter: Arc<Semaphore>,
+}
+
+impl RpcNode {
+    pub fn new(endpoint: String) -> Self {
+        Self::with_limit(endpoint, RPC_MAX_CONCURRENT)
+    }
+
+    pub fn with_limit(endpoint: String, max_concurrent: usize) -> Self {
+        Self {
+            endpoint,
+            client: Client::builder()
+                .connect_timeout(RPC_CONNECT_TIMEOUT)
+                .pool_max_idle_per_host(max_concurrent)
+                .build()
+                .expect("Failed to build rpc client"),
+            limiter: Arc::new(Semaphore::new(max_concurrent)),
+        }
+    }
+
+    async fn acquire(&self) -> Result<SemaphorePermit<'_>, OreoError> {
+        self.limiter
+            .acquire()
+            .await
+            .map_err(|e| OreoError::InternalRpcError(e.to_string()))
+    }
+
+    async fn send<T: Serialize + ?Sized>(
+        &self,
+        method: &str,
+        request: &T,
+        timeout: Duration,
+    ) -> Result<Response, reqwest::Error> {
+        let path = format!("http://{}/{}", self.endpoint, method);
+        self.client
+            .post(&path)
+            .json(request)
+            .timeout(timeout)
+            .send()
+            .await
+    }
+
+    async fn call<T: Serialize + ?Sized, S: Debug + DeserializeOwned>(
+        &self,
+        method: &str,
+        request: &T,
+        timeout: Duration,
+    ) -> Result<RpcResponse<S>, OreoError> {
+        let _permit = self.acquire().await?;
+        let start = Instant::now();
+        let resp = self.send(method, request, timeout).await;
+        let result = handle_response(resp).await;
+        self.record(method, start, result.is_ok());
+        result
+    }
+
+    fn record(&self, method: &str, start: Instant, success: bool) {
+        histogram!(
+            "rpc_request_duration_seconds",
+            "node" => self.endpoint.clone(),
+            "method" => method.to_string(),
+            "result" => if success { "ok" } else { "error" },
+        )
+        .record(start.elapsed().as_secs_f64());
+    }
+
+    pub async fn import_account(
+        &self,
+        request: RpcImportAccountRequest,
+    ) -> Result<RpcResponse<RpcImportAccountResponse>, OreoError> {
+        let account_str = serde_json::to_string(&request)
+            .map_err(|_| OreoError::InternalRpcError("JSON serialization failed".to_string()))?;
+        self.call(
+            "wallet/importAccount",
+            &json!({"account": account_str}),
+            RPC_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn export_account(
+        &self,
+        account: String,
+    ) -> Result<RpcResponse<RpcExportAccountResponse>, OreoError> {
+        self.call(
+            "wallet/exportAccount",
+            &json!({"account": account, "format": "JSON".to_string()}),
+            RPC_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn remove_account(
+        &self,
+        request: RpcRemoveAccountRequest,
+    ) -> Result<RpcResponse<RpcRemoveAccountResponse>, OreoError> {
+        debug!("req: {:?}", request);
+        let _permit = self.acquire().await?;
+        let resp = self
+            .send("wallet/removeAccount", &request, RPC_TIMEOUT)
+            .await;
+        match resp {
+            Ok(res) if res.status().as_u16() == 200 => Ok(RpcResponse {
+                status: 200,
+                data: RpcRemoveAccountResponse { removed: true },
+            }),
+            _ => handle_response(resp).await,
+        }
+    }
+
+    pub async fn get_account_status(
+        &self,
+        request: RpcGetAccountStatusRequest,
+    ) -> Result<RpcResponse<RpcGetAccountStatusResponse>, OreoError> {
+        self.call("wallet/getAccountStatus", &request, RPC_TIMEOUT)
+            .await
+    }
+
+    pub async fn set_scanning(
+        &self,
+        request: RpcSetScanningRequest,
+    ) -> Result<RpcResponse<Option<()>>, OreoError> {
+        self.call("wallet/setScanning", &request, RPC_TIMEOUT).await
+    }
+
+    pub async fn set_account_head(
+        &self,
+        request: RpcSetAccountHeadRequest,
+    ) -> Result<RpcResponse<Option<()>>, OreoError> {
+        let RpcSetAccountHeadRequest {
+            account,
+            start,
+            end,
+            blocks,
+            ..
+        } = request;
+        let request = RpcSetAccountHeadRequestV2 {
+            account,
+            start,
+            end,
+            blocks,
+        };
+        self.call("wallet/setAccountHead", &request, RPC_LONG_TIMEOUT)
+            .await
+    }
+
+    pub async fn reset_account(
+        &self,
+        request: RpcResetAccountRequest,
+    ) -> Result<RpcResponse<Option<()>>, OreoError> {
+        self.call("wallet/resetAccount", &request, RPC_TIMEOUT)
+            .await
+    }
+
+    pub async fn get_balances(
+        &self,
+        request: RpcGetBalancesRequest,
+    ) -> Result<RpcResponse<RpcGetBalancesResponse>, OreoError> {
+        self.call("wallet/getBalances", &request, RPC_TIMEOUT).await
+    }
+
+    pub async fn get_account_transaction(
+        &self,
+        request: RpcGetAccountTransactionRequest,
+    ) -> Result<RpcResponse<RpcGetAccountTransactionResponse>, OreoError> {
+        self.call("wallet/getAccountTransaction", &request, RPC_TIMEOUT)
+            .await
+    }
+
+    pub async fn get_transactions(
+        &self,
+        request: RpcGetTransactionsRequest,
+    ) -> Result<RpcResponse<RpcGetTransactionsResponse>, OreoError> {
+        self.get_transactions_while(request, |_| true).await
+    }
+
+    /// Stream the transactions of an account until `keep` turns one down.
+    pub async fn get_transactions_while(
+        &self,
+        request: RpcGetTransactionsRequest,
+        keep: impl FnMut(&TransactionStatus) -> bool,
+    ) -> Result<RpcResponse<RpcGetTransactionsResponse>, OreoError> {
+        let _permit = self.acquire().await?;
+        let start = Instant::now();
+        let resp = self
+            .send("wallet/getAccountTransactions", &request, RPC_LONG_TIMEOUT)
+            .await;
+        let result = match resp {
+            Ok(response) if response.status().is_success() => read_stream_while(response, keep)
+                .await
+                .map(|transactions| RpcResponse {
+                    status: 200,
+                    data: RpcGetTransactionsResponse { transactions },
+                }),
+            resp => handle_response(resp).await,
+        };
+        self.record("wallet/getAccountTransactions", start, result.is_ok());
+        result
+    }
+
+    pub async fn create_transaction(
+        &self,
+        request: RpcCreateTxRequest,
+    ) -> Result<RpcResponse<RpcCreateTxResponse>, OreoError> {
+        self.call("wallet/createTransaction", &request, RPC_LONG_TIMEOUT)
+            .await
+    }
+
+    pub async fn add_transaction(
+        &self,
+        request: RpcAddTxRequest,
+    ) -> Result<RpcResponse<RpcAddTxResponse>, OreoError> {
+        self.call("wallet/addTransaction", &request, RPC_TIMEOUT)
+            .await
+    }
+
+    pub async fn get_latest_block(
+        &self,
+    ) -> Result<RpcResponse<RpcGetLatestBlockResponse>, OreoError> {
+        let _permit = self.acquire().await?;
+        let path = format!("http://{}/chain/getChainInfo", self.endpoint);
+        let start = Instant::now();
+        let resp = self.client.get(&path).timeout(RPC_TIMEOUT).send().await;
+        let result = handle_response(resp).await;
+        self.record("chain/getChainInfo", start, result.is_ok());
+        result
+    }
+
+    pub async fn get_block(
+        &self,
+        sequence: i64,
+    ) -> Result<RpcResponse<RpcGetBlockResponse>, OreoError> {
+        self.call(
+            "chain/getBlock",
+            &RpcGetBlockRequest {
+                sequence,
+                serialized: Some(true),
+            },
+            RPC_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn get_block_by_hash(
+        &self,
+        hash: String,
+    ) -> Result<RpcResponse<RpcGetBlockResponse>, OreoError> {
+        self.call(
+            "chain/getBlock",
+            &RpcGetBlockByHashRequest {
+                hash,
+                serialized: Some(true),
+            },
+            RPC_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn get_blocks(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Result<RpcResponse<RpcGetBlocksResponse>, OreoError> {
+        self.call(
+            "chain/getBlocks",
+            &RpcGetBlocksRequest {
+                start,
+                end,
+                serialized: true,
+            },
+            RPC_LONG_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn send_transaction(
+        &self,
+        request: SendTransactionRequest,
+    ) -> Result<RpcResponse<SendTransactionResponse>, OreoError> {
+        self.call("wallet/sendTransaction", &request, RPC_LONG_TIMEOUT)
+            .await
+    }
+
+    pub async fn get_asset(&self, id: String) -> Result<RpcResponse<RpcAsset>, OreoError> {
+        self.call("chain/getAsset", &json!({"id": id}), RPC_TIMEOUT)
+            .await
+    }
+}
+
+pub async fn handle_response<S: Debug + DeserializeOwned>(
+    resp: Result<Response, reqwest::Error>,
+) -> Result<RpcResponse<S>, OreoError> {
+    let res = match resp {
+        Ok(response) if response.status().is_success() => response.json::<RpcResponse<S>>().await,
+        Ok(response) => match response.json::<RpcError>().await {
+            Ok(data) => return Err(OreoError::try_from(data).unwrap()),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    debug!("Handle rpc response: {:?}", res);
+    res.map_err(rpc_failure)
+}
+
+/// Connection failures and timeouts mean the node is unreachable, anything else is an
+/// answer of the node.
+pub fn rpc_failure(e: reqwest::Error) -> OreoError {
+    match e.is_decode() {
+        true => OreoError::InternalRpcError(e.to_string()),
+        false => OreoError::RpcUnreachable(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use axum::{
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+    use oreo_errors::OreoError;
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use crate::rpc_abi::{
+        RpcGetTransactionsRequest, RpcResetAccountRequest, RpcSetScanningRequest,
+    };
+
+    use super::RpcNode;
+
+    /// Serves `router` as a fake node and returns its endpoint.
+    pub(crate) async fn mock_node(router: Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        endpoint
+    }
+
+    pub(crate) fn chain_info(head: u64) -> Json<Value> {
+        Json(json!({
+            "status": 200,
+            "data": {
+                "currentBlockIdentifier": {"index": head.to_string(), "hash": "head"},
+                "genesisBlockIdentifier": {"index": "1", "hash": "genesis"},
+            }
+        }))
+    }
+
+    fn block(sequence: u32) -> Value {
+        json!({
+            "block": {
+                "hash": sequence.to_string(),
+                "sequence": sequence,
+                "previousBlockHash": (sequence - 1).to_string(),
+                "transactions": [],
+            }
+        })
+    }
+
+    #[tokio::test]
+    pub async fn get_block_should_work() {
+        let endpoint =
+            mock_node(Router::new().route("/chain/getChainInfo", get(|| async { chain_info(12) })))
+                .await;
+        let block = RpcNode::new(endpoint)
+            .get_latest_block()
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(block.current_block_identifier.index, "12");
+    }
+
+    #[tokio::test]
+    pub async fn get_blocks_should_work() {
+        let endpoint = mock_node(Router::new().route(
+            "/chain/getBlocks",
+            post(|| async {
+                Json(json!({"status": 200, "data": {"blocks": [block(3), block(4)]}}))
+            }),
+        ))
+        .await;
+        let blocks = RpcNode::new(endpoint).get_blocks(3, 4).await.unwrap().data;
+        assert_eq!(
+            blocks
+                .blocks
+                .iter()
+                .map(|item| item.block.sequence)
+                .collect::<Vec<u32>>(),
+            vec![3, 4]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn set_scanning_should_work() {
+        let endpoint = mock_node(Router::new().route(
+            "/wallet/setScanning",
+            post(|| async { Json(json!({"status": 200, "data": null})) }),
+        ))
+        .await;
+        let result = RpcNode::new(endpoint)
+            .set_scanning(RpcSetScanningRequest {
+                account: "test".to_string(),
+                enabled: false,
+            })
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    pub async fn reset_account_should_work() {
+        let endpoint = mock_node(Router::new().route(
+            "/wallet/resetAccount",
+            post(|| async { Json(json!({"status": 200, "data": null})) }),
+        ))
+        .await;
+        let result = RpcNode::new(endpoint)
+            .reset_account(RpcResetAccountRequest {
+                account: "test".to_string(),
+                reset_created_at: Some(false),
+                reset_scanning_enabled: Some(false),
+            })
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    pub async fn get_asset_should_work() {
+        let id = "8e36e31d677a47cbd883843a345654c814b1e9ec1e0125bac9031f052ced9174";
+        let endpoint = mock_node(Router::new().route(
+            "/chain/getAsset",
+            post(|Json(request): Json<Value>| async move {
+                Json(json!({
+                    "status": 200,
+                    "data": {"id": request["id"], "verification": {"status": "unknown"}},
+                }))
+            }),
+        ))
+        .await;
+        let asset = RpcNode::new(endpoint)
+            .get_asset(id.to_string())
+            .await
+            .unwrap()
+            .data;
+        assert_eq!(asset.id, id);
+        assert_eq!(asset.verification.status, "unknown");
+    }
+
+    #[tokio::test]
+    pub async fn failures_should_be_told_apart() {
+        let endpoint = mock_node(Router::new().route(
+            "/chain/getAsset",
+            post(|| async {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({"code": "insufficient-balance", "status": 400, "message": "no"})),
+                )
+            }),
+        ))
+        .await;
+        let answered = RpcNode::new(endpoint).get_asset("id".into()).await;
+        assert_eq!(answered.unwrap_err(), OreoError::BalanceNotEnough);
+        let unreachable = RpcNode::new("127.0.0.1:1".into())
+            .get_asset("id".into())
+            .await;
+        assert!(matches!(
+            unreachable.unwrap_err(),
+            OreoError::RpcUnreachable(_)
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn get_transactions_should_stream() {
+        let transaction = json!({
+            "hash": "tx",
+            "fee": "1",
+            "type": "send",
+            "status": "confirmed",
+            "blockSequence": 3,
+            "timestamp": 1,
+            "assetBalanceDeltas": [],
+        });
+        let body = format!(
+            "{}\x0c{}\x0c{}\x0c",
+            json!({ "data": transaction }),
+            json!({ "data": transaction }),
+            json!({"status": 200})
+        );
+        let endpoint = mock_node(Router::new().route(
+            "/wallet/getAccountTransactions",
+            post(move || async move { body }),
+        ))
+        .await;
+        let request = || RpcGetTransactionsRequest {
+            account: "test".into(),
+            limit: None,
+            offset: None,
+            reverse: None,
+            notes: None,
+        };
+        let transactions = RpcNode::new(endpoint)
+            .get_transactions(request())
+            .await
+            .unwrap()
+            .data
+            .transactions;
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].hash, "tx");
+
+        let failing = mock_node(Router::new().route(
+            "/wallet/getAccountTransactions",
+            post(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "node crashed") }),
+        ))
+        .await;
+        let failed = RpcNode::new(failing).get_transactions(request()).await;
+        assert!(matches!(
+            failed.unwrap_err(),
+            OreoError::InternalRpcError(_)
+        ));
+    }
+}
@@ -1,53 +1,86 @@
 use std::{fmt::Debug, time::Duration};
 
 use oreo_errors::OreoError;
-use serde::Deserialize;
+use reqwest::{Client, Error, Response};
+use serde::{de::DeserializeOwned, Serialize};
 use tracing::{debug, error};
-use ureq::{Agent, AgentBuilder, Error, Response};
 
-use crate::decryption_message::{DecryptionMessage, ScanRequest, ScanResponse, SuccessResponse};
+use crate::decryption_message::{
+    DecryptionMessage, DryRunResponse, ScanRequest, ScanResponse, ScanStatusResponse, ScannerQuery,
+    SuccessResponse,
+};
+
+/// Timeout for a single call between the server and the scanner.
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone)]
 pub struct ServerHandler {
     pub endpoint: String,
-    pub agent: Agent,
+    pub client: Client,
 }
 
 impl ServerHandler {
     pub fn new(endpoint: String) -> Self {
         Self {
             endpoint,
-            agent: AgentBuilder::new()
-                .timeout_read(Duration::from_secs(60))
-                .timeout_write(Duration::from_secs(60))
-                .build(),
+            client: Client::builder()
+                .timeout(SERVER_TIMEOUT)
+                .build()
+                .expect("Failed to build server client"),
         }
     }
 
-    pub fn submit_scan_request(
+    async fn post<T: Serialize + ?Sized, S: Debug + DeserializeOwned>(
+        &self,
+        method: &str,
+        request: &T,
+    ) -> Result<S, OreoError> {
+        let path = format!("http://{}/{}", self.endpoint, method);
+        let resp = self.client.post(&path).json(request).send().await;
+        handle_response(resp).await
+    }
+
+    pub async fn submit_scan_request(
         &self,
         request: DecryptionMessage<ScanRequest>,
     ) -> Result<SuccessResponse, OreoError> {
-        let path = format!("http://{}/scanAccount", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+        self.post("scanAccount", &request).await
+    }
+
+    pub async fn get_scan_status(
+        &self,
+        request: DecryptionMessage<ScannerQuery>,
+    ) -> Result<ScanStatusResponse, OreoError> {
+        self.post("scanStatus", &request).await
+    }
+
+    pub async fn get_dry_run(
+        &self,
+        request: DecryptionMessage<ScannerQuery>,
+    ) -> Result<DryRunResponse, OreoError> {
+        self.post("dryRunResult", &request).await
+    }
+
+    pub async fn remove_account(
+        &self,
+        request: DecryptionMessage<ScannerQuery>,
+    ) -> Result<SuccessResponse, OreoError> {
+        self.post("removeAccount", &request).await
     }
 
-    pub fn submit_scan_response(
+    pub async fn submit_scan_response(
         &self,
         request: DecryptionMessage<ScanResponse>,
     ) -> Result<SuccessResponse, OreoError> {
-        let path = format!("http://{}/updateScan", self.endpoint);
-        let resp = self.agent.clone().post(&path).send_json(&request);
-        handle_response(resp)
+        self.post("updateScan", &request).await
     }
 }
 
-fn handle_response<S: Debug + for<'a> Deserialize<'a>>(
+async fn handle_response<S: Debug + DeserializeOwned>(
     resp: Result<Response, Error>,
 ) -> Result<S, OreoError> {
-    let res = match resp {
-        Ok(response) => match response.into_json::<S>() {
+    let res = match resp.and_then(|response| response.error_for_status()) {
+        Ok(response) => match response.json::<S>().await {
             Ok(data) => Ok(data),
             Err(e) => {
                 error!("failed to parse json {}", e);
@@ -1,22 +1,89 @@
-use anyhow::Result;
-use bytes::{BufMut, BytesMut};
-use db_handler::{Account, DBTransaction};
-use serde::{Deserialize, Serialize};
-use std::io::Write;
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use db_handler::{Account, DBTransaction, ScanOptions};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashMap, fmt};
 use tokio_util::codec::{Decoder, Encoder};
 use uuid::Uuid;
+use zeroize::Zeroize;
 
+use super::noise::{open, seal, Cipher};
 use crate::decryption_message::ScanRequest;
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct RegisterWorker {
     pub name: String,
+    // tasks the worker takes at once, workers predating it take one
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+}
+
+fn default_capacity() -> usize {
+    1
+}
+
+/// Default upper bound of a single frame on the scanner-worker connection.
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Raw bytes of a serialized note, hex encoded in json and carried as is in binary frames.
+#[derive(Clone, Debug, PartialEq, Hash, Eq)]
+pub struct NoteBytes(pub Vec<u8>);
+
+impl NoteBytes {
+    /// Notes are stored hex encoded, invalid ones are dropped.
+    pub fn from_hex_notes(notes: Vec<String>) -> Vec<Self> {
+        notes
+            .into_iter()
+            .filter_map(|note| hex::decode(note).ok())
+            .map(Self)
+            .collect()
+    }
+}
+
+impl Serialize for NoteBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match serializer.is_human_readable() {
+            true => serializer.serialize_str(&hex::encode(&self.0)),
+            false => serializer.serialize_bytes(&self.0),
+        }
+    }
+}
+
+struct NoteBytesVisitor;
+
+impl<'de> de::Visitor<'de> for NoteBytesVisitor {
+    type Value = NoteBytes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("note bytes or a hex string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        hex::decode(v).map(NoteBytes).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(NoteBytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(NoteBytes(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for NoteBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.is_human_readable() {
+            true => deserializer.deserialize_str(NoteBytesVisitor),
+            false => deserializer.deserialize_byte_buf(NoteBytesVisitor),
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Hash, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct SingleRequest {
-    pub serialized_note: Vec<String>,
+    pub serialized_note: Vec<NoteBytes>,
     pub tx_hash: String,
 }
 
@@ -29,6 +96,11 @@ pub struct DRequest {
     pub outgoing_view_key: String,
     pub decrypt_for_spender: bool,
     pub data: Vec<SingleRequest>,
+    // return the decrypted notes besides the matched transactions
+    #[serde(default)]
+    pub with_notes: bool,
+    #[serde(default)]
+    pub options: ScanOptions,
 }
 
 impl DRequest {
@@ -37,7 +109,7 @@ impl DRequest {
         let serializeds = transaction.serialized_notes;
         let data = SingleRequest {
             tx_hash,
-            serialized_note: serializeds,
+            serialized_note: NoteBytes::from_hex_notes(serializeds),
         };
         Self {
             id: Uuid::new_v4().to_string(),
@@ -46,6 +118,8 @@ impl DRequest {
             outgoing_view_key: account.out_vk.clone(),
             decrypt_for_spender: true,
             data: vec![data],
+            with_notes: false,
+            options: account.scan_options.0.clone(),
         }
     }
 
@@ -54,7 +128,7 @@ impl DRequest {
             .into_iter()
             .map(|tx| SingleRequest {
                 tx_hash: tx.hash.to_string(),
-                serialized_note: tx.serialized_notes,
+                serialized_note: NoteBytes::from_hex_notes(tx.serialized_notes),
             })
             .collect();
         Self {
@@ -64,8 +138,138 @@ impl DRequest {
             outgoing_view_key: account.out_vk.clone(),
             decrypt_for_spender: true,
             data,
+            with_notes: false,
+            options: account.options.clone(),
         }
     }
+
+    /// Request of one account of a batch task, for workers predating batches.
+    pub fn from_batch(request: &DBatchRequest, keys: &RegisterKeys) -> Self {
+        Self {
+            id: request.id.clone(),
+            address: keys.keys.address.clone(),
+            incoming_view_key: keys.keys.incoming_view_key.clone(),
+            outgoing_view_key: keys.keys.outgoing_view_key.clone(),
+            decrypt_for_spender: request.decrypt_for_spender,
+            data: request.data.clone(),
+            with_notes: request.with_notes,
+            options: keys.options.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Hash, Eq, Zeroize)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewKeys {
+    pub address: String,
+    pub incoming_view_key: String,
+    pub outgoing_view_key: String,
+}
+
+// keys never end up in logs
+impl fmt::Debug for ViewKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ViewKeys")
+            .field("address", &self.address)
+            .finish_non_exhaustive()
+    }
+}
+
+/// View keys sent once per connection, tasks refer to them by `handle`.
+/// `options` select the notes matched for the account.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RegisterKeys {
+    pub handle: u64,
+    pub keys: ViewKeys,
+    #[serde(default)]
+    pub options: ScanOptions,
+}
+
+/// Keys of ended scan sessions, workers forget and zeroize them.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ReleaseKeys {
+    pub handles: Vec<u64>,
+}
+
+/// An account of a batch task, its keys are registered under `handle` beforehand.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Hash, Eq)]
+pub struct AccountHandle {
+    pub address: String,
+    pub handle: u64,
+}
+
+/// Notes of one block to decrypt with the view keys of many accounts.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Hash, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DBatchRequest {
+    pub id: String,
+    pub accounts: Vec<AccountHandle>,
+    pub decrypt_for_spender: bool,
+    pub data: Vec<SingleRequest>,
+    #[serde(default)]
+    pub with_notes: bool,
+}
+
+impl From<&ScanRequest> for ViewKeys {
+    fn from(account: &ScanRequest) -> Self {
+        Self {
+            address: account.address.clone(),
+            incoming_view_key: account.in_vk.clone(),
+            outgoing_view_key: account.out_vk.clone(),
+        }
+    }
+}
+
+impl DBatchRequest {
+    pub fn from_transactions(
+        accounts: Vec<AccountHandle>,
+        transactions: Vec<DBTransaction>,
+    ) -> Self {
+        let data = transactions
+            .into_iter()
+            .map(|tx| SingleRequest {
+                tx_hash: tx.hash.to_string(),
+                serialized_note: NoteBytes::from_hex_notes(tx.serialized_notes),
+            })
+            .collect();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            accounts,
+            decrypt_for_spender: true,
+            data,
+            with_notes: false,
+        }
+    }
+}
+
+/// Whether the account received the note or sent it.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum NoteDirection {
+    Received,
+    Sent,
+}
+
+impl NoteDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Received => "received",
+            Self::Sent => "sent",
+        }
+    }
+}
+
+/// A note decrypted by a worker, `index` is its position in the transaction.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct DecryptedNote {
+    pub tx_hash: String,
+    pub index: u32,
+    pub direction: NoteDirection,
+    pub value: u64,
+    pub asset_id: String,
+    pub memo: String,
+    pub sender: String,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -73,6 +277,75 @@ pub struct DResponse {
     pub address: String,
     pub id: String,
     pub data: Vec<String>,
+    // only filled if the request asked for notes
+    #[serde(default)]
+    pub notes: Vec<DecryptedNote>,
+}
+
+/// Matched transaction hashes by address, accounts without a match are left out.
+/// `notes` holds the decrypted notes the same way if the request asked for them.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DBatchResponse {
+    pub id: String,
+    pub data: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub notes: HashMap<String, Vec<DecryptedNote>>,
+}
+
+impl DBatchResponse {
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            data: HashMap::new(),
+            notes: HashMap::new(),
+        }
+    }
+
+    /// Add the response of one account of the batch, sent by a worker predating batches.
+    pub fn merge(&mut self, response: DResponse) {
+        if !response.data.is_empty() {
+            let _ = self.data.insert(response.address.clone(), response.data);
+        }
+        if !response.notes.is_empty() {
+            let _ = self.notes.insert(response.address, response.notes);
+        }
+    }
+}
+
+/// Batch tasks sent to a worker predating batches as one `DRequest` per account, the
+/// responses are gathered until every account of the batch answered.
+#[derive(Debug, Default)]
+pub struct LegacyBatches {
+    pending: HashMap<String, (usize, DBatchResponse)>,
+}
+
+impl LegacyBatches {
+    /// Requests of the accounts of `request` whose keys are given.
+    pub fn split(&mut self, request: &DBatchRequest, keys: &[RegisterKeys]) -> Vec<DRequest> {
+        let requests: Vec<DRequest> = keys
+            .iter()
+            .map(|keys| DRequest::from_batch(request, keys))
+            .collect();
+        if !requests.is_empty() {
+            let _ = self.pending.insert(
+                request.id.clone(),
+                (requests.len(), DBatchResponse::new(request.id.clone())),
+            );
+        }
+        requests
+    }
+
+    /// The batch response once the last account of the batch answered.
+    pub fn gather(&mut self, response: DResponse) -> Option<DBatchResponse> {
+        let id = response.id.clone();
+        let (remaining, batch) = self.pending.get_mut(&id)?;
+        batch.merge(response);
+        *remaining -= 1;
+        if *remaining > 0 {
+            return None;
+        }
+        self.pending.remove(&id).map(|(_, batch)| batch)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -80,19 +353,131 @@ pub enum DMessage {
     RegisterWorker(RegisterWorker),
     DRequest(DRequest),
     DResponse(DResponse),
+    DBatchRequest(DBatchRequest),
+    DBatchResponse(DBatchResponse),
+    RegisterKeys(RegisterKeys),
+    ReleaseKeys(ReleaseKeys),
+}
+
+/// Wire encoding of a connection, agreed on during the handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Newline delimited json, spoken by workers predating the handshake.
+    Json,
+    /// u32 big endian length prefixed bincode frames.
+    Binary,
 }
 
-#[derive(Default)]
 pub struct DMessageCodec {
+    encoding: Encoding,
+    max_frame: usize,
     cursor: usize,
+    cipher: Option<Cipher>,
+    nonce: u64,
+}
+
+impl DMessageCodec {
+    pub fn new(encoding: Encoding, max_frame: usize) -> Self {
+        Self {
+            encoding,
+            max_frame,
+            cursor: 0,
+            cipher: None,
+            nonce: 0,
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Seal binary frames with the noise cipher of the connection, a codec either
+    /// only encodes or only decodes once it is set.
+    pub fn with_cipher(self, cipher: Cipher) -> Self {
+        Self {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+
+    fn decode_json(&mut self, src: &mut BytesMut) -> Result<Option<DMessage>> {
+        let end = src[self.cursor..].iter().position(|byte| *byte == b'\n');
+        let len = end.map_or(src.len(), |offset| self.cursor + offset);
+        if len > self.max_frame {
+            return Err(anyhow!("Frame exceeds {} bytes", self.max_frame));
+        }
+        match end {
+            Some(_) => {
+                let data = src.split_to(len + 1);
+                self.cursor = 0;
+                Ok(Some(serde_json::from_slice(&data[..len])?))
+            }
+            None => {
+                self.cursor = len;
+                Ok(None)
+            }
+        }
+    }
+
+    fn decode_binary(&mut self, src: &mut BytesMut) -> Result<Option<DMessage>> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if len > self.max_frame {
+            return Err(anyhow!("Frame of {} bytes exceeds {}", len, self.max_frame));
+        }
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        let data = src.split_to(len);
+        match &self.cipher {
+            Some(cipher) => {
+                let data = open(cipher, &mut self.nonce, &data)?;
+                Ok(Some(bincode::deserialize(&data)?))
+            }
+            None => Ok(Some(bincode::deserialize(&data[..])?)),
+        }
+    }
+}
+
+impl Default for DMessageCodec {
+    fn default() -> Self {
+        Self::new(Encoding::Json, DEFAULT_MAX_FRAME)
+    }
 }
 
 impl Encoder<DMessage> for DMessageCodec {
     type Error = anyhow::Error;
     fn encode(&mut self, message: DMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let json_string = serde_json::to_string(&message).unwrap();
-        dst.writer().write_all(json_string.as_bytes())?;
-        dst.writer().write_all("\n".as_bytes())?;
+        let data = match (self.encoding, &self.cipher) {
+            (Encoding::Json, _) => serde_json::to_vec(&message)?,
+            (Encoding::Binary, None) => bincode::serialize(&message)?,
+            (Encoding::Binary, Some(cipher)) => {
+                seal(cipher, &mut self.nonce, &bincode::serialize(&message)?)?
+            }
+        };
+        if data.len() > self.max_frame {
+            return Err(anyhow!(
+                "Frame of {} bytes exceeds {}",
+                data.len(),
+                self.max_frame
+            ));
+        }
+        match self.encoding {
+            Encoding::Json => {
+                dst.reserve(data.len() + 1);
+                dst.put_slice(&data);
+                dst.put_u8(b'\n');
+            }
+            Encoding::Binary => {
+                dst.reserve(data.len() + 4);
+                dst.put_u32(data.len() as u32);
+                dst.put_slice(&data);
+            }
+        }
         Ok(())
     }
 }
@@ -102,21 +487,132 @@ impl Decoder for DMessageCodec {
     type Item = DMessage;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let mut i = self.cursor;
-        while i < src.len() {
-            if src[i] == 10u8 {
-                self.cursor = 0;
-                let mut data = src.split_to(i + 1);
-                unsafe {
-                    data.set_len(i);
-                }
-                src.reserve(100);
-                let message = serde_json::from_slice(&data[..])?;
-                return Ok(Some(message));
-            }
-            i += 1;
+        match self.encoding {
+            Encoding::Json => self.decode_json(src),
+            Encoding::Binary => self.decode_binary(src),
         }
-        self.cursor = i;
-        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bytes::BytesMut;
+    use db_handler::DBTransaction;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::{
+        AccountHandle, DBatchRequest, DBatchResponse, DMessage, DMessageCodec, DecryptedNote,
+        Encoding, NoteBytes, NoteDirection, RegisterKeys, RegisterWorker, ViewKeys,
+    };
+
+    fn messages() -> (DMessage, DMessage) {
+        let account = |address: &str, handle: u64| AccountHandle {
+            address: address.into(),
+            handle,
+        };
+        let request = DMessage::DBatchRequest(DBatchRequest::from_transactions(
+            vec![account("a", 1), account("b", 2)],
+            vec![DBTransaction {
+                hash: "tx".into(),
+                serialized_notes: vec!["0a0b".into(), "invalid".into()],
+            }],
+        ));
+        let response = DMessage::DBatchResponse(DBatchResponse {
+            id: "id".into(),
+            data: HashMap::from([("a".to_string(), vec!["tx".to_string()])]),
+            notes: HashMap::from([(
+                "a".to_string(),
+                vec![DecryptedNote {
+                    tx_hash: "tx".into(),
+                    index: 0,
+                    direction: NoteDirection::Received,
+                    value: 1,
+                    asset_id: "asset".into(),
+                    memo: "memo".into(),
+                    sender: "b".into(),
+                }],
+            )]),
+        });
+        (request, response)
+    }
+
+    #[test]
+    fn batch_messages_should_roundtrip() {
+        for encoding in [Encoding::Json, Encoding::Binary] {
+            let (request, response) = messages();
+            let mut codec = DMessageCodec::new(encoding, 1024);
+            let mut buf = BytesMut::new();
+            codec.encode(request.clone(), &mut buf).unwrap();
+            codec.encode(response.clone(), &mut buf).unwrap();
+            // frames may arrive split anywhere
+            let mut rest = buf.split_off(3);
+            assert_eq!(codec.decode(&mut buf).unwrap(), None);
+            buf.unsplit(rest.split());
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(request));
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(response));
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn view_keys_should_not_be_logged() {
+        let message = DMessage::RegisterKeys(RegisterKeys {
+            handle: 1,
+            keys: ViewKeys {
+                address: "a".into(),
+                incoming_view_key: "secret_ivk".into(),
+                outgoing_view_key: "secret_ovk".into(),
+            },
+            options: Default::default(),
+        });
+        assert!(!format!("{:?}", message).contains("secret"));
+    }
+
+    #[test]
+    fn json_notes_should_stay_hex() {
+        let (request, _) = messages();
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains(r#""serializedNote":["0a0b"]"#));
+        let note: NoteBytes = serde_json::from_str(r#""0a0b""#).unwrap();
+        assert_eq!(note, NoteBytes(vec![10, 11]));
+    }
+
+    #[test]
+    fn oversized_frame_should_be_rejected() {
+        for encoding in [Encoding::Json, Encoding::Binary] {
+            let (request, _) = messages();
+            let mut buf = BytesMut::new();
+            DMessageCodec::new(encoding, 1024)
+                .encode(request.clone(), &mut buf)
+                .unwrap();
+            let mut codec = DMessageCodec::new(encoding, 16);
+            assert!(codec.decode(&mut buf).is_err());
+            assert!(codec.encode(request, &mut BytesMut::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn responses_without_notes_should_decode() {
+        let message: DMessage =
+            serde_json::from_str(r#"{"DBatchResponse":{"id":"id","data":{"a":["tx"]}}}"#).unwrap();
+        match message {
+            DMessage::DBatchResponse(response) => assert!(response.notes.is_empty()),
+            _ => panic!("unexpected message"),
+        }
+    }
+
+    #[test]
+    fn legacy_worker_should_take_one_task() {
+        let message: DMessage =
+            serde_json::from_str(r#"{"RegisterWorker":{"name":"dworker"}}"#).unwrap();
+        assert_eq!(
+            message,
+            DMessage::RegisterWorker(RegisterWorker {
+                name: "dworker".into(),
+                capacity: 1,
+            })
+        );
     }
 }
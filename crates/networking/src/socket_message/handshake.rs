@@ -0,0 +1,401 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use zeroize::Zeroizing;
+
+use super::{
+    codec::{DMessageCodec, Encoding},
+    noise::{noise_accept, noise_connect, NoiseConfig},
+};
+
+/// Leading bytes of a handshake, a legacy worker starts with a json object instead.
+pub const PROTOCOL_MAGIC: &[u8; 4] = b"OREO";
+/// Highest protocol version spoken here, 0 is newline delimited json, 1 binary frames
+/// and 2 binary frames over an authenticated noise channel.
+pub const PROTOCOL_VERSION: u16 = 2;
+pub const BINARY_VERSION: u16 = 1;
+pub const SECURE_VERSION: u16 = 2;
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn encoding(version: u16) -> Encoding {
+    match version {
+        0 => Encoding::Json,
+        _ => Encoding::Binary,
+    }
+}
+
+async fn read_hello(stream: &mut TcpStream) -> Result<u16> {
+    let mut hello = [0u8; 6];
+    stream.read_exact(&mut hello).await?;
+    if &hello[..4] != PROTOCOL_MAGIC {
+        return Err(anyhow!("Invalid handshake"));
+    }
+    Ok(u16::from_be_bytes([hello[4], hello[5]]))
+}
+
+async fn write_hello(stream: &mut TcpStream, version: u16) -> Result<()> {
+    let mut hello = PROTOCOL_MAGIC.to_vec();
+    hello.extend_from_slice(&version.to_be_bytes());
+    stream.write_all(&hello).await?;
+    Ok(())
+}
+
+/// Scanner side, answers with the highest version both ends speak, up to `version`.
+/// Workers predating the handshake send json right away and keep talking json.
+pub async fn accept_handshake(stream: &mut TcpStream, version: u16) -> Result<u16> {
+    let mut first = [0u8; 1];
+    if stream.peek(&mut first).await? == 0 {
+        return Err(anyhow!("Connection closed before handshake"));
+    }
+    if first[0] != PROTOCOL_MAGIC[0] {
+        return Ok(0);
+    }
+    let agreed = read_hello(stream).await?.min(version);
+    write_hello(stream, agreed).await?;
+    Ok(agreed)
+}
+
+/// Worker side, offers `version` and returns the version agreed on by the scanner.
+pub async fn connect_handshake(stream: &mut TcpStream, version: u16) -> Result<u16> {
+    write_hello(stream, version).await?;
+    let agreed = read_hello(stream).await?;
+    if agreed > version {
+        return Err(anyhow!("Unsupported protocol version {}", agreed));
+    }
+    Ok(agreed)
+}
+
+/// How one end of the scanner-worker connection talks to the other.
+#[derive(Clone)]
+pub struct TransportConfig {
+    pub max_frame: usize,
+    /// Noise keys, the connection is neither encrypted nor authenticated without them.
+    pub noise: Option<NoiseConfig>,
+}
+
+impl TransportConfig {
+    /// Plaintext transport is only allowed with `insecure` set explicitly.
+    pub fn new(
+        max_frame: usize,
+        noise_key: Option<String>,
+        peers: Vec<String>,
+        insecure: bool,
+    ) -> Result<Self> {
+        let noise = match noise_key {
+            Some(key) => Some(NoiseConfig::new(&Zeroizing::new(key), peers)?),
+            None if !peers.is_empty() => {
+                return Err(anyhow!("Peer keys are set without a noise key"));
+            }
+            None if !insecure => {
+                return Err(anyhow!(
+                    "No noise key set, pass --insecure-transport to allow plaintext"
+                ));
+            }
+            None => None,
+        };
+        Ok(Self { max_frame, noise })
+    }
+
+    fn version(&self) -> u16 {
+        match self.noise {
+            Some(_) => SECURE_VERSION,
+            None => BINARY_VERSION,
+        }
+    }
+
+    fn codecs(&self, version: u16) -> (DMessageCodec, DMessageCodec) {
+        (
+            DMessageCodec::new(encoding(version), self.max_frame),
+            DMessageCodec::new(encoding(version), self.max_frame),
+        )
+    }
+
+    /// Scanner side, returns the reader and writer codecs and the key of an authenticated
+    /// worker. With noise keys set anything but an allowed worker is rejected here.
+    pub async fn accept(
+        &self,
+        stream: &mut TcpStream,
+    ) -> Result<(DMessageCodec, DMessageCodec, Option<String>)> {
+        timeout(HANDSHAKE_TIMEOUT, async {
+            let version = accept_handshake(stream, self.version()).await?;
+            match &self.noise {
+                Some(noise) => {
+                    if version < SECURE_VERSION {
+                        return Err(anyhow!("Unauthenticated worker rejected"));
+                    }
+                    let (cipher, remote) = noise_accept(stream, noise).await?;
+                    let (reader, writer) = self.codecs(version);
+                    Ok((
+                        reader.with_cipher(cipher.clone()),
+                        writer.with_cipher(cipher),
+                        Some(remote),
+                    ))
+                }
+                None => {
+                    let (reader, writer) = self.codecs(version);
+                    Ok((reader, writer, None))
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("Handshake timeout"))?
+    }
+
+    /// Worker side, returns the reader and writer codecs. `legacy` skips the handshake
+    /// for scanners predating it.
+    pub async fn connect(
+        &self,
+        stream: &mut TcpStream,
+        legacy: bool,
+    ) -> Result<(DMessageCodec, DMessageCodec)> {
+        if legacy {
+            if self.noise.is_some() {
+                return Err(anyhow!("Legacy protocol can't be encrypted"));
+            }
+            return Ok(self.codecs(0));
+        }
+        timeout(HANDSHAKE_TIMEOUT, async {
+            let version = connect_handshake(stream, self.version()).await?;
+            match &self.noise {
+                Some(noise) => {
+                    if version < SECURE_VERSION {
+                        return Err(anyhow!("Scanner doesn't support encrypted transport"));
+                    }
+                    let cipher = noise_connect(stream, noise).await?;
+                    let (reader, writer) = self.codecs(version);
+                    Ok((
+                        reader.with_cipher(cipher.clone()),
+                        writer.with_cipher(cipher),
+                    ))
+                }
+                None => Ok(self.codecs(version)),
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("Handshake timeout"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use db_handler::DBTransaction;
+    use futures::{SinkExt, StreamExt};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_util::codec::{Decoder, Encoder, Framed};
+
+    use super::{accept_handshake, connect_handshake, TransportConfig, PROTOCOL_VERSION};
+    use crate::socket_message::codec::{
+        AccountHandle, DBatchRequest, DMessage, DResponse, LegacyBatches, RegisterKeys,
+        RegisterWorker, ViewKeys, DEFAULT_MAX_FRAME,
+    };
+
+    const SCANNER_KEY: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+    const WORKER_KEY: &str = "0202020202020202020202020202020202020202020202020202020202020202";
+    const STRANGER_KEY: &str = "0303030303030303030303030303030303030303030303030303030303030303";
+
+    async fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (server, client)
+    }
+
+    fn public_key(private_key: &str) -> String {
+        TransportConfig::new(0, Some(private_key.into()), vec![SCANNER_KEY.into()], false)
+            .unwrap()
+            .noise
+            .unwrap()
+            .public_key
+    }
+
+    fn secure(private_key: &str, peer: &str) -> TransportConfig {
+        TransportConfig::new(
+            DEFAULT_MAX_FRAME,
+            Some(private_key.into()),
+            vec![public_key(peer)],
+            false,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn handshake_should_negotiate_version() {
+        for (supported, offered, expected) in [(2, 2, 2), (1, 2, 1), (2, 1, 1), (2, 0, 0)] {
+            let (mut server, mut client) = pair().await;
+            let (accepted, connected) = tokio::join!(
+                accept_handshake(&mut server, supported),
+                connect_handshake(&mut client, offered)
+            );
+            assert_eq!(accepted.unwrap(), expected);
+            assert_eq!(connected.unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_worker_should_speak_json() {
+        let (mut server, mut client) = pair().await;
+        client
+            .write_all(b"{\"RegisterWorker\":{\"name\":\"dworker\"}}\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            accept_handshake(&mut server, PROTOCOL_VERSION)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn legacy_worker_should_get_one_request_per_account() {
+        let plain = TransportConfig::new(DEFAULT_MAX_FRAME, None, vec![], true).unwrap();
+        let (mut server, mut client) = pair().await;
+        let (reader, writer) = plain.connect(&mut client, true).await.unwrap();
+        let mut worker = Framed::new(client, writer);
+        worker
+            .send(DMessage::RegisterWorker(RegisterWorker {
+                name: "dworker".into(),
+                capacity: 1,
+            }))
+            .await
+            .unwrap();
+        let (codec, _, _) = plain.accept(&mut server).await.unwrap();
+        let mut scanner = Framed::new(server, codec);
+        assert!(matches!(
+            scanner.next().await.unwrap().unwrap(),
+            DMessage::RegisterWorker(_)
+        ));
+
+        let keys = |address: &str, handle: u64| RegisterKeys {
+            handle,
+            keys: ViewKeys {
+                address: address.into(),
+                incoming_view_key: format!("{}-in", address),
+                outgoing_view_key: format!("{}-out", address),
+            },
+            options: Default::default(),
+        };
+        let request = DBatchRequest::from_transactions(
+            vec![
+                AccountHandle {
+                    address: "a".into(),
+                    handle: 1,
+                },
+                AccountHandle {
+                    address: "b".into(),
+                    handle: 2,
+                },
+            ],
+            vec![DBTransaction {
+                hash: "tx".into(),
+                serialized_notes: vec!["0a0b".into()],
+            }],
+        );
+        let mut batches = LegacyBatches::default();
+        let requests = batches.split(&request, &[keys("a", 1), keys("b", 2)]);
+        assert_eq!(requests.len(), 2);
+        for request in requests {
+            scanner.send(DMessage::DRequest(request)).await.unwrap();
+        }
+
+        // answers the way workers predating batches do, one response per request
+        let mut worker = Framed::new(worker.into_inner(), reader);
+        for _ in 0..2 {
+            let request = match worker.next().await.unwrap().unwrap() {
+                DMessage::DRequest(request) => request,
+                message => panic!("unexpected message {:?}", message),
+            };
+            assert_eq!(request.incoming_view_key, format!("{}-in", request.address));
+            let data = match request.address.as_str() {
+                "a" => vec![request.data[0].tx_hash.clone()],
+                _ => vec![],
+            };
+            worker
+                .send(DMessage::DResponse(DResponse {
+                    address: request.address,
+                    id: request.id,
+                    data,
+                    notes: vec![],
+                }))
+                .await
+                .unwrap();
+        }
+
+        let mut gathered = vec![];
+        for _ in 0..2 {
+            match scanner.next().await.unwrap().unwrap() {
+                DMessage::DResponse(response) => gathered.push(batches.gather(response)),
+                message => panic!("unexpected message {:?}", message),
+            }
+        }
+        assert!(gathered[0].is_none());
+        let response = gathered[1].clone().unwrap();
+        assert_eq!(response.id, request.id);
+        assert_eq!(response.data.len(), 1);
+        assert_eq!(response.data["a"], vec!["tx".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn allowed_worker_should_talk_encrypted() {
+        let scanner = secure(SCANNER_KEY, WORKER_KEY);
+        let worker = secure(WORKER_KEY, SCANNER_KEY);
+        let (mut server, mut client) = pair().await;
+        let (accepted, connected) = tokio::join!(
+            scanner.accept(&mut server),
+            worker.connect(&mut client, false)
+        );
+        let (mut reader, _, remote) = accepted.unwrap();
+        let (_, mut writer) = connected.unwrap();
+        assert_eq!(remote, Some(public_key(WORKER_KEY)));
+        // the second one spans several noise messages
+        let messages: Vec<DMessage> = ["dworker".to_string(), "dworker".repeat(20000)]
+            .into_iter()
+            .map(|name| DMessage::RegisterWorker(RegisterWorker { name, capacity: 1 }))
+            .collect();
+        let mut buf = BytesMut::new();
+        for message in messages.iter() {
+            writer.encode(message.clone(), &mut buf).unwrap();
+        }
+        assert!(!buf.windows(7).any(|window| window == b"dworker"));
+        for message in messages {
+            assert_eq!(reader.decode(&mut buf).unwrap(), Some(message));
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_or_plain_worker_should_be_rejected() {
+        let scanner = secure(SCANNER_KEY, WORKER_KEY);
+        let stranger = secure(STRANGER_KEY, SCANNER_KEY);
+        let (mut server, mut client) = pair().await;
+        let (accepted, _) = tokio::join!(
+            scanner.accept(&mut server),
+            stranger.connect(&mut client, false)
+        );
+        assert!(accepted.is_err());
+
+        assert!(TransportConfig::new(DEFAULT_MAX_FRAME, None, vec![], false).is_err());
+        let plain = TransportConfig::new(DEFAULT_MAX_FRAME, None, vec![], true).unwrap();
+        for legacy in [false, true] {
+            let (mut server, mut client) = pair().await;
+            let (accepted, _) = tokio::join!(scanner.accept(&mut server), async {
+                let result = plain.connect(&mut client, legacy).await;
+                if legacy {
+                    let _ = client.write_all(b"{}\n").await;
+                }
+                result
+            });
+            assert!(accepted.is_err());
+        }
+    }
+}
@@ -1 +1,3 @@
 pub mod codec;
+pub mod handshake;
+pub mod noise;
@@ -0,0 +1,143 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use snow::{params::NoiseParams, Builder, HandshakeState, StatelessTransportState};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use zeroize::Zeroizing;
+
+/// Both ends prove their static key, the worker learns the scanner key in the second message
+/// and the scanner learns the worker key in the third.
+pub const NOISE_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// Largest noise message, longer frames are sealed in several messages.
+pub const NOISE_MAX_MESSAGE: usize = 65535;
+pub const NOISE_TAG: usize = 16;
+
+/// Shared by the reader and the writer of a connection, each keeps its own nonce.
+pub type Cipher = Arc<StatelessTransportState>;
+
+/// Static x25519 key of this end and the public keys of the peers it accepts.
+#[derive(Clone)]
+pub struct NoiseConfig {
+    private_key: Zeroizing<[u8; 32]>,
+    pub public_key: String,
+    pub peers: HashSet<String>,
+}
+
+impl NoiseConfig {
+    pub fn new(private_key: &str, peers: Vec<String>) -> Result<Self> {
+        let decoded = Zeroizing::new(hex::decode(private_key)?);
+        let private_key: Zeroizing<[u8; 32]> = Zeroizing::new(
+            decoded
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("Noise private key must be 32 bytes"))?,
+        );
+        let peers: HashSet<String> = peers
+            .into_iter()
+            .map(|key| key.trim().to_lowercase())
+            .filter(|key| !key.is_empty())
+            .collect();
+        if peers.is_empty() {
+            return Err(anyhow!("No peer key allowed"));
+        }
+        Ok(Self {
+            public_key: hex::encode(MontgomeryPoint::mul_base_clamped(*private_key).to_bytes()),
+            private_key,
+            peers,
+        })
+    }
+
+    fn builder(&self) -> Builder<'_> {
+        let params: NoiseParams = NOISE_PATTERN.parse().unwrap();
+        Builder::new(params)
+            .local_private_key(self.private_key.as_slice())
+            .prologue(super::handshake::PROTOCOL_MAGIC)
+    }
+
+    /// Remote static key of a finished handshake, if allowed.
+    fn check_peer(&self, noise: &HandshakeState) -> Result<String> {
+        let remote = noise
+            .get_remote_static()
+            .map(hex::encode)
+            .ok_or_else(|| anyhow!("Peer sent no static key"))?;
+        match self.peers.contains(&remote) {
+            true => Ok(remote),
+            false => Err(anyhow!("Peer key {} is not allowed", remote)),
+        }
+    }
+}
+
+async fn send(stream: &mut TcpStream, noise: &mut HandshakeState) -> Result<()> {
+    let mut message = vec![0u8; NOISE_MAX_MESSAGE];
+    let len = noise.write_message(&[], &mut message)?;
+    stream.write_all(&(len as u16).to_be_bytes()).await?;
+    stream.write_all(&message[..len]).await?;
+    Ok(())
+}
+
+async fn receive(stream: &mut TcpStream, noise: &mut HandshakeState) -> Result<()> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message).await?;
+    let mut payload = vec![0u8; NOISE_MAX_MESSAGE];
+    let _ = noise.read_message(&message, &mut payload)?;
+    Ok(())
+}
+
+fn into_cipher(noise: HandshakeState) -> Result<Cipher> {
+    Ok(Arc::new(noise.into_stateless_transport_mode()?))
+}
+
+/// Scanner side, returns the cipher and the key of the worker.
+pub async fn noise_accept(
+    stream: &mut TcpStream,
+    config: &NoiseConfig,
+) -> Result<(Cipher, String)> {
+    let mut noise = config.builder().build_responder()?;
+    receive(stream, &mut noise).await?;
+    send(stream, &mut noise).await?;
+    receive(stream, &mut noise).await?;
+    let remote = config.check_peer(&noise)?;
+    Ok((into_cipher(noise)?, remote))
+}
+
+/// Worker side, fails unless the scanner holds one of the allowed keys.
+pub async fn noise_connect(stream: &mut TcpStream, config: &NoiseConfig) -> Result<Cipher> {
+    let mut noise = config.builder().build_initiator()?;
+    send(stream, &mut noise).await?;
+    receive(stream, &mut noise).await?;
+    // the scanner key is known now, don't reveal ours to anyone else
+    let _ = config.check_peer(&noise)?;
+    send(stream, &mut noise).await?;
+    into_cipher(noise)
+}
+
+/// Encrypt `data` in as many noise messages as needed.
+pub fn seal(cipher: &StatelessTransportState, nonce: &mut u64, data: &[u8]) -> Result<Vec<u8>> {
+    let chunks = data.chunks(NOISE_MAX_MESSAGE - NOISE_TAG);
+    let mut sealed = vec![0u8; data.len() + chunks.len() * NOISE_TAG];
+    let mut offset = 0;
+    for chunk in chunks {
+        offset += cipher.write_message(*nonce, chunk, &mut sealed[offset..])?;
+        *nonce += 1;
+    }
+    Ok(sealed)
+}
+
+/// Decrypt a frame sealed by `seal`.
+pub fn open(cipher: &StatelessTransportState, nonce: &mut u64, data: &[u8]) -> Result<Vec<u8>> {
+    let chunks = data.chunks(NOISE_MAX_MESSAGE);
+    let mut opened = vec![0u8; data.len()];
+    let mut offset = 0;
+    for chunk in chunks {
+        offset += cipher.read_message(*nonce, chunk, &mut opened[offset..])?;
+        *nonce += 1;
+    }
+    opened.truncate(offset);
+    Ok(opened)
+}
@@ -1,18 +1,9 @@
-use std::io::{BufRead, BufReader, Read};
-use std::marker::PhantomData;
-
 use oreo_errors::OreoError;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use ureq::Response;
 
 use crate::rpc_abi::RpcResponseStream;
 
-pub struct StreamReader<T, R> {
-    reader: BufReader<R>,
-    _marker: PhantomData<T>,
-}
-
 #[derive(Deserialize, Serialize)]
 #[serde(untagged)]
 enum ResponseItem<T> {
@@ -20,89 +11,60 @@ enum ResponseItem<T> {
     Status { status: u16 },
 }
 
-impl<T, R> StreamReader<T, R>
-where
-    T: DeserializeOwned,
-    R: Read,
-{
-    pub fn new(reader: R) -> Self {
-        Self {
-            reader: BufReader::new(reader),
-            _marker: PhantomData,
+/// Parses a chunk of data into a `ResponseItem<T>`, `None` once the stream ended fine.
+fn parse_item<T: DeserializeOwned>(chunk: &[u8]) -> Result<Option<T>, OreoError> {
+    match serde_json::from_slice::<ResponseItem<T>>(chunk) {
+        Ok(ResponseItem::Data(item)) => Ok(Some(item.data)),
+        Ok(ResponseItem::Status { status: 200 }) => Ok(None),
+        Ok(ResponseItem::Status { status }) => Err(OreoError::RpcStreamError(format!(
+            "Received error status: {}",
+            status
+        ))),
+        Err(e) => {
+            let err_str = format!("Failed to parse JSON object: {:?}", e);
+            Err(OreoError::RpcStreamError(err_str))
         }
     }
+}
 
-    fn read_item(&mut self) -> Result<Option<Vec<u8>>, OreoError> {
-        let mut item = Vec::new();
-        loop {
-            let bytes_read = self
-                .reader
-                .read_until(b'\x0c', &mut item)
-                .map_err(|e| OreoError::RpcStreamError(e.to_string()))?;
-            if bytes_read == 0 {
-                break;
-            }
-            if item.last() == Some(&b'\x0c') {
-                item.pop();
-                break;
-            }
-        }
-        match item.len() {
-            0 => Ok(None),
-            _ => Ok(Some(item)),
-        }
-    }
+/// Reads the items of a streamed rpc response as its chunks arrive.
+pub async fn read_stream<T: DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<Vec<T>, OreoError> {
+    read_stream_while(response, |_| true).await
+}
 
-    /// Parses a chunk of data into a `ResponseItem<T>`.
-    fn parse_item(&self, chunk: &[u8]) -> Result<Option<T>, OreoError> {
-        match serde_json::from_slice::<ResponseItem<T>>(chunk) {
-            Ok(ResponseItem::Data(item)) => Ok(Some(item.data)),
-            Ok(ResponseItem::Status { status: 200 }) => Ok(None),
-            Ok(ResponseItem::Status { status }) => Err(OreoError::RpcStreamError(format!(
-                "Received error status: {}",
-                status
-            ))),
-            Err(e) => {
-                let err_str = format!("Failed to parse JSON object: {:?}", e);
-                Err(OreoError::RpcStreamError(err_str))
+/// Reads the items of a streamed rpc response until `keep` turns one down, the rest of
+/// the response is never read.
+pub async fn read_stream_while<T: DeserializeOwned>(
+    mut response: reqwest::Response,
+    mut keep: impl FnMut(&T) -> bool,
+) -> Result<Vec<T>, OreoError> {
+    let mut items = vec![];
+    let mut pending: Vec<u8> = vec![];
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| OreoError::RpcStreamError(e.to_string()))?
+    {
+        pending.extend_from_slice(&chunk);
+        while let Some(end) = pending.iter().position(|byte| *byte == b'\x0c') {
+            let item: Vec<u8> = pending.drain(..=end).collect();
+            match parse_item(&item[..end])? {
+                Some(item) if keep(&item) => items.push(item),
+                _ => return Ok(items),
             }
         }
     }
-}
-
-impl<T, R> Iterator for StreamReader<T, R>
-where
-    T: DeserializeOwned,
-    R: Read,
-{
-    type Item = Result<T, OreoError>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.read_item() {
-            Ok(Some(chunk)) => match self.parse_item(&chunk) {
-                Ok(Some(data)) => Some(Ok(data)),
-                Ok(None) => None, // End of stream
-                Err(e) => Some(Err(e)),
-            },
-            Ok(None) => None, // EOF reached
-            Err(e) => Some(Err(e)),
+    // the last item may come without a separator
+    if !pending.is_empty() {
+        if let Some(item) = parse_item(&pending)? {
+            if keep(&item) {
+                items.push(item);
+            }
         }
     }
-}
-
-pub trait ResponseExt {
-    fn into_stream<T: DeserializeOwned>(
-        self,
-    ) -> StreamReader<T, Box<dyn Read + Send + Sync + 'static>>;
-}
-
-impl ResponseExt for Response {
-    fn into_stream<T: DeserializeOwned>(
-        self,
-    ) -> StreamReader<T, Box<dyn Read + Send + Sync + 'static>> {
-        let reader = self.into_reader();
-        StreamReader::new(Box::new(reader))
-    }
+    Ok(items)
 }
 
 #[cfg(test)]
@@ -111,8 +73,8 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn test_stream_reader_with_status() {
+    #[tokio::test]
+    async fn test_stream_reader_with_status() {
         // Prepare some test data.
         let transaction_status = TransactionStatus {
             hash: "cde4c2a5bc7cb6cbad93a414ff76176e07412fbd48f2f3d1ee8f7fc1238626a5".to_string(),
@@ -122,6 +84,7 @@ mod tests {
             block_sequence: Some(1),
             timestamp: 1,
             asset_balance_deltas: Vec::new(),
+            notes: None,
         };
         let item1: RpcResponseStream<TransactionStatus> = RpcResponseStream {
             data: transaction_status.clone(),
@@ -136,9 +99,9 @@ mod tests {
         // Add \x0c separators
         let data = format!("{}\x0c{}\x0c", json1, json_status);
 
-        let reader = StreamReader::<TransactionStatus, _>::new(data.as_bytes());
-        let items: Vec<_> = reader.collect();
-        let returned_status = items[0].as_ref().unwrap();
+        let response = reqwest::Response::from(axum::http::Response::new(data));
+        let items = read_stream::<TransactionStatus>(response).await.unwrap();
+        let returned_status = &items[0];
         assert_eq!(returned_status.hash, transaction_status.hash);
         assert_eq!(returned_status.fee, transaction_status.fee);
         assert_eq!(returned_status.r#type, transaction_status.r#type);
@@ -154,8 +117,8 @@ mod tests {
         );
     }
 
-    #[test]
-    fn test_stream_reader_with_error_status() {
+    #[tokio::test]
+    async fn test_stream_reader_with_error_status() {
         // Prepare some test data.
         let item1 = RpcResponseStream { data: 42u32 };
         let response1 = ResponseItem::Data(item1);
@@ -168,17 +131,34 @@ mod tests {
         // Add \x0c separators
         let data = format!("{}\x0c{}\x0c", json1, json_status);
 
-        let reader = StreamReader::<u32, _>::new(data.as_bytes());
-        let items: Vec<_> = reader.collect();
+        let response = reqwest::Response::from(axum::http::Response::new(data));
+        let items = read_stream::<u32>(response).await;
 
-        assert_eq!(items.len(), 2);
-        assert_eq!(items[0], Ok(42u32));
-
-        match &items[1] {
+        match &items {
             Err(OreoError::RpcStreamError(msg)) => {
                 assert!(msg.contains("Received error status: 500"))
             }
             _ => panic!("Expected error with status code 500"),
         }
     }
+
+    #[tokio::test]
+    async fn read_stream_should_collect_items() {
+        let item =
+            |data: u32| serde_json::to_string(&ResponseItem::Data(RpcResponseStream { data }));
+        let status = serde_json::to_string(&ResponseItem::<u32>::Status { status: 200 }).unwrap();
+        let data = format!(
+            "{}\x0c{}\x0c{}\x0c",
+            item(1).unwrap(),
+            item(2).unwrap(),
+            status
+        );
+        let response = reqwest::Response::from(axum::http::Response::new(data.clone()));
+        assert_eq!(read_stream::<u32>(response).await, Ok(vec![1, 2]));
+        let response = reqwest::Response::from(axum::http::Response::new(data));
+        assert_eq!(
+            read_stream_while::<u32>(response, |item| *item < 2).await,
+            Ok(vec![1])
+        );
+    }
 }
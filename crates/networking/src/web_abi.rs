@@ -1,4 +1,4 @@
-use db_handler::{address_to_name, Account};
+use db_handler::{address_to_name, Account, Json, ScanNote, ScanOptions};
 use oreo_errors::OreoError;
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +14,8 @@ pub struct ImportAccountRequest {
     pub outgoing_view_key: String,
     pub public_address: String,
     pub created_at: Option<BlockInfo>,
+    #[serde(default)]
+    pub scan_options: ScanOptions,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -38,6 +40,8 @@ impl ImportAccountRequest {
             out_vk: self.outgoing_view_key.clone(),
             vk: self.view_key.clone(),
             need_scan: false,
+            worker_id: 0,
+            scan_options: Json(self.scan_options.clone()),
         }
     }
 }
@@ -134,7 +138,146 @@ pub struct GenerateProofResponse {
     pub mint_asset_proofs: Vec<Vec<u8>>,
 }
 
+/// Rescan an account, a start block may be given by sequence or hash. A real rescan resets the
+/// node wallet and scans from where the reset leaves it, the creation block of the account, so
+/// it rejects a start past that block. A dry run leaves the node wallet alone, scans from the
+/// start block or else the creation block, and its result is read from `/rescan/result`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RescanAccountRequest {
+    pub account: String,
+    pub from_sequence: Option<u64>,
+    pub from_hash: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RescanAccountResponse {
     pub success: bool,
 }
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeRequest {
+    pub account: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeResponse {
+    pub challenge: String,
+    pub expires_at: u64,
+}
+
+/// `proof` is hex encoded HMAC-SHA256 over the challenge bytes keyed with the view key bytes.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginRequest {
+    pub account: String,
+    pub challenge: String,
+    pub proof: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    pub token: String,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RevokeResponse {
+    pub success: bool,
+}
+
+/// Bytes of an Ironfish note memo.
+pub const MEMO_LENGTH: usize = 32;
+
+/// Messages of an account, received in or sent from block `start` on.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMessagesRequest {
+    pub account: String,
+    pub start: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDetail {
+    pub tx_hash: String,
+    pub block_sequence: i64,
+    pub direction: String,
+    pub sender: String,
+    pub memo: String,
+    pub memo_hex: String,
+}
+
+impl MessageDetail {
+    /// Zero value notes with a memo are messages, padding of the memo is dropped.
+    pub fn from_note(note: ScanNote) -> Option<Self> {
+        if note.value != 0 {
+            return None;
+        }
+        let memo = hex::decode(note.memo.trim()).ok()?;
+        let len = memo.iter().rposition(|byte| *byte != 0)? + 1;
+        Some(Self {
+            tx_hash: note.tx_hash,
+            block_sequence: note.sequence,
+            direction: note.direction,
+            sender: note.sender,
+            memo: String::from_utf8_lossy(&memo[..len]).into(),
+            memo_hex: note.memo,
+        })
+    }
+
+    /// Message among the notes of a transaction indexed by the node wallet of `address`.
+    pub fn from_rpc_note(
+        address: &str,
+        tx_hash: &str,
+        sequence: i64,
+        note: RpcNote,
+    ) -> Option<Self> {
+        if note.value.parse::<u64>().ok()? != 0 {
+            return None;
+        }
+        let memo = note.memo.trim_end_matches('\0');
+        if memo.is_empty() {
+            return None;
+        }
+        let direction = match (note.owner == address, note.sender == address) {
+            (true, _) => "received",
+            (false, true) => "sent",
+            _ => return None,
+        };
+        // padded like the memos decrypted by workers
+        let mut memo_hex = memo.as_bytes().to_vec();
+        memo_hex.resize(MEMO_LENGTH.max(memo_hex.len()), 0);
+        Some(Self {
+            tx_hash: tx_hash.into(),
+            block_sequence: sequence,
+            direction: direction.into(),
+            sender: note.sender,
+            memo: memo.into(),
+            memo_hex: hex::encode(memo_hex),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GetMessagesResponse {
+    pub account: String,
+    pub messages: Vec<MessageDetail>,
+}
+
+/// A zero value note carrying `memo` to `recipient`, the returned transaction is signed and
+/// broadcast like the ones of `/createTx`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SendMessageRequest {
+    pub account: String,
+    pub recipient: String,
+    pub memo: String,
+    pub fee: Option<String>,
+    pub expiration_delta: Option<u32>,
+}
@@ -49,6 +49,14 @@ pub enum OreoError {
     RpcStreamError(String),
     #[error("Invalid signature")]
     BadSignature,
+    #[error("Too many requests, slow down")]
+    TooManyRequests,
+    #[error("Memo exceeds `{0}` bytes")]
+    MemoTooLong(usize),
+    #[error("Rescan can not start from `{0}`")]
+    BadRescanStart(String),
+    #[error("Ironfish rpc node unreachable")]
+    RpcUnreachable(String),
 }
 
 impl IntoResponse for OreoError {
@@ -93,6 +101,10 @@ fn get_status_code(err: OreoError) -> (StatusCode, String) {
         OreoError::TooManyProofs => (StatusCode::from_u16(616).unwrap(), err.to_string()),
         OreoError::GenerateProofError(_) => (StatusCode::from_u16(617).unwrap(), err.to_string()),
         OreoError::BadSignature => (StatusCode::from_u16(618).unwrap(), err.to_string()),
+        OreoError::TooManyRequests => (StatusCode::from_u16(619).unwrap(), err.to_string()),
+        OreoError::MemoTooLong(_) => (StatusCode::from_u16(620).unwrap(), err.to_string()),
+        OreoError::BadRescanStart(_) => (StatusCode::from_u16(621).unwrap(), err.to_string()),
+        OreoError::RpcUnreachable(_) => (StatusCode::from_u16(622).unwrap(), err.to_string()),
     };
     (status_code, err_msg)
 }
@@ -28,7 +28,5 @@ impl Network for Mainnet {
 
     const RESCHEDULING_DURATION: std::time::Duration = std::time::Duration::from_secs(30);
 
-    const LOCAL_BLOCKS_CHECKPOINT: u64 = 939_400;
-
     const SET_ACCOUNT_LIMIT: usize = 20;
 }
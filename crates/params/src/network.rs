@@ -23,8 +23,6 @@ pub trait Network: 'static + Send + Sync + Debug + Eq + PartialEq + Copy + Clone
     const SECONDARY_BATCH: i64;
     /// The time duration to rescheduling scan task.
     const RESCHEDULING_DURATION: Duration;
-    /// The local block checkpoint for scanning.
-    const LOCAL_BLOCKS_CHECKPOINT: u64;
     /// The set account head request limit.
     const SET_ACCOUNT_LIMIT: usize;
 }
@@ -1,7 +1,10 @@
+use std::time::Instant;
+
 use axum::{extract, response::IntoResponse, Json};
 use bellperson::groth16;
 use ironfish_rust::sapling_bls12::SAPLING;
 use ironfish_zkp::proofs::{MintAsset, Output, Spend};
+use metrics::histogram;
 use networking::web_abi::{GenerateProofRequest, GenerateProofResponse};
 use oreo_errors::OreoError;
 use rand::thread_rng;
@@ -26,8 +29,11 @@ pub async fn generate_proof_handler(
         .enumerate()
         .map(|(_, bytes)| {
             let proof_bytes = if let Ok(spend) = Spend::read(&bytes[..]) {
+                let start = Instant::now();
                 let proof =
                     groth16::create_random_proof(spend, &SAPLING.spend_params, &mut thread_rng());
+                histogram!("prover_proof_duration_seconds", "circuit" => "spend")
+                    .record(start.elapsed().as_secs_f64());
                 if let Ok(proof) = proof {
                     let mut writer = vec![];
                     proof.write(&mut writer).unwrap();
@@ -54,8 +60,11 @@ pub async fn generate_proof_handler(
         .enumerate()
         .map(|(_, bytes)| {
             let proof_bytes = if let Ok(output) = Output::read(&bytes[..]) {
+                let start = Instant::now();
                 let proof =
                     groth16::create_random_proof(output, &SAPLING.output_params, &mut thread_rng());
+                histogram!("prover_proof_duration_seconds", "circuit" => "output")
+                    .record(start.elapsed().as_secs_f64());
                 if let Ok(proof) = proof {
                     let mut writer = vec![];
                     proof.write(&mut writer).unwrap();
@@ -82,11 +91,14 @@ pub async fn generate_proof_handler(
         .enumerate()
         .map(|(_, bytes)| {
             let proof_bytes = if let Ok(mint_asset) = MintAsset::read(&bytes[..]) {
+                let start = Instant::now();
                 let proof = groth16::create_random_proof(
                     mint_asset,
                     &SAPLING.mint_params,
                     &mut thread_rng(),
                 );
+                histogram!("prover_proof_duration_seconds", "circuit" => "mint_asset")
+                    .record(start.elapsed().as_secs_f64());
                 if let Ok(proof) = proof {
                     let mut writer = vec![];
                     proof.write(&mut writer).unwrap();
@@ -1,7 +1,9 @@
 use anyhow::Result;
 use prover::run_prover;
 use tracing::info;
-use utils::{initialize_logger, initialize_logger_filter, EnvFilter, Parser, Prover};
+use utils::{
+    initialize_logger, initialize_logger_filter, initialize_metrics, EnvFilter, Parser, Prover,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -10,6 +12,7 @@ async fn main() -> Result<()> {
     let filter = EnvFilter::from_default_env().add_directive("bellperson=off".parse().unwrap());
     initialize_logger_filter(filter);
     info!("Prover starts {:?}", prover);
+    initialize_metrics(prover.metrics)?;
     run_prover(prover.listen).await?;
     Ok(())
 }
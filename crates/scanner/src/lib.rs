@@ -1,11 +1,13 @@
 use std::{
     cmp::{self, Reverse},
+    collections::HashMap,
     net::SocketAddr,
     ops::Deref,
-    sync::Arc,
-    time::{Duration, Instant},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
 
+use anyhow::anyhow;
 use axum::{
     error_handling::HandleErrorLayer,
     extract::{self, State},
@@ -14,13 +16,20 @@ use axum::{
     routing::post,
     BoxError, Json, Router,
 };
-use db_handler::{DBHandler, InnerBlock};
-use manager::{AccountInfo, Manager, ServerMessage, SharedState, TaskInfo};
+use db_handler::{DBHandler, InnerBlock, ScanSession};
+use manager::{AccountInfo, Manager, SharedState, TaskInfo, LEASE_RENEW_INTERVAL};
 use networking::{
-    decryption_message::{DecryptionMessage, ScanRequest, SuccessResponse},
+    decryption_message::{
+        DecryptionMessage, ScanRequest, ScanSessionsResponse, ScanStatusResponse, ScannerQuery,
+        SuccessResponse, WorkersResponse,
+    },
     rpc_abi::BlockInfo,
-    socket_message::codec::DRequest,
+    socket_message::{
+        codec::{AccountHandle, DBatchRequest},
+        handshake::TransportConfig,
+    },
 };
+use oreo_errors::OreoError;
 use params::network::Network;
 use tokio::{net::TcpListener, sync::oneshot, time::sleep};
 use tower::{timeout::TimeoutLayer, ServiceBuilder};
@@ -31,89 +40,306 @@ use utils::blocks_range;
 pub mod manager;
 pub mod router;
 
+/// Dispatch one task per block, carrying the key handles of every account which still needs it.
+///
+/// Queued tasks are served by round, the position of the block in its sweep starting at
+/// `round`, so concurrent sweeps progress at the same pace. Workers get them as they have
+/// credits left, see `Manager::dispatch`. Accounts whose session was restarted since the sweep
+/// was planned are left to the sweep of the new session.
 pub async fn scheduling_tasks(
     scheduler: Arc<Manager>,
-    accounts: &Vec<ScanRequest>,
+    accounts: &[(ScanRequest, u64)],
     blocks: Vec<InnerBlock>,
+    round: u64,
 ) -> anyhow::Result<()> {
-    for account in accounts {
-        let account_info_maybe = scheduler
-            .account_mappling
-            .read()
+    let mut ranges = vec![];
+    {
+        let account_mappling = scheduler.account_mappling.read().await;
+        for (account, epoch) in accounts {
+            if let Some(account_info) = account_mappling
+                .get(&account.address)
+                .filter(|account_info| account_info.epoch == *epoch)
+            {
+                debug!(
+                    "start scanning {} blocks for account {:?}",
+                    blocks.len(),
+                    account.address.clone()
+                );
+                ranges.push((
+                    account,
+                    account_info.scanned,
+                    account_info.end_block.sequence,
+                    account_info.completed.clone(),
+                ));
+            }
+        }
+    }
+    let mut handles = HashMap::new();
+    for (account, _, _, _) in ranges.iter() {
+        let _ = handles.insert(account.address.clone(), scheduler.key_handle(account).await);
+    }
+    for (index, block) in blocks.iter().enumerate() {
+        let sequence = block.sequence as u64;
+        let block_accounts: Vec<AccountHandle> = ranges
+            .iter()
+            .filter(|(_, scanned, end, completed)| {
+                sequence > *scanned && sequence <= *end && !completed.contains(&sequence)
+            })
+            .map(|(account, _, _, _)| handles[&account.address].clone())
+            .collect();
+        if block_accounts.is_empty() {
+            debug!("skip height {:?}", block.sequence);
+            continue;
+        }
+        let mut task =
+            DBatchRequest::from_transactions(block_accounts, block.transactions.deref().clone());
+        task.with_notes = scheduler.note_details;
+        let mut task_info = TaskInfo::new(task.clone(), block.sequence, block.hash.clone());
+        task_info.verify = rand::random::<f64>() < scheduler.verify_fraction;
+        let _ = scheduler
+            .task_mapping
+            .write()
             .await
-            .get(&account.address)
-            .cloned();
-        if let Some(account_info) = account_info_maybe {
-            debug!(
-                "start scanning {} blocks for account {:?}",
-                blocks.len(),
-                account.address.clone()
+            .insert(task.id.clone(), task_info);
+        let _ = scheduler
+            .task_queue
+            .write()
+            .await
+            .push(task, Reverse((round + index as u64, block.sequence)));
+    }
+    scheduler.dispatch().await;
+    Ok(())
+}
+
+/// Blocks of `start..=end` from rpc nodes, which must extend `parent` when it is known.
+pub async fn fetch_blocks(
+    scheduler: &Manager,
+    start: u64,
+    end: u64,
+    parent: Option<&str>,
+) -> anyhow::Result<Vec<InnerBlock>> {
+    let blocks: Vec<InnerBlock> = scheduler
+        .shared
+        .rpc_handler
+        .get_blocks(start, end)
+        .await?
+        .data
+        .blocks
+        .into_iter()
+        .map(|item| item.block.to_inner())
+        .collect();
+    if let Some(sequence) = InnerBlock::broken_link(&blocks, parent) {
+        return Err(anyhow!("Chain reorg detected at block {}", sequence));
+    }
+    Ok(blocks)
+}
+
+/// Blocks of `start..=end`, from local db below the chain loader progress and from rpc nodes above.
+///
+/// Local blocks no longer on the canonical chain are rolled back and replaced.
+pub async fn load_blocks(
+    scheduler: &Manager,
+    start: u64,
+    end: u64,
+    parent: Option<&str>,
+) -> anyhow::Result<Vec<InnerBlock>> {
+    let db_handler = &scheduler.shared.db_handler;
+    let loaded = db_handler
+        .get_chain_progress()
+        .await?
+        .map_or(0, |progress| progress.sequence as u64);
+    if end > loaded {
+        return fetch_blocks(scheduler, start, end, parent).await;
+    }
+    let blocks = db_handler.get_blocks(start as i64, end as i64).await?;
+    let canonical = scheduler
+        .shared
+        .rpc_handler
+        .get_block(end as i64)
+        .await?
+        .data
+        .block
+        .hash;
+    let forked = InnerBlock::broken_link(&blocks, parent).is_some()
+        || !blocks
+            .last()
+            .is_some_and(|block| block.hash.eq_ignore_ascii_case(&canonical));
+    if !forked {
+        return Ok(blocks);
+    }
+    warn!(
+        "Local blocks {} to {} are not on the canonical chain, rolling back",
+        start, end
+    );
+    db_handler.remove_blocks(start as i64, end as i64).await?;
+    let blocks = fetch_blocks(scheduler, start, end, parent).await?;
+    db_handler.save_blocks(blocks.clone()).await?;
+    Ok(blocks)
+}
+
+pub async fn scheduling_range<N: Network>(
+    scheduler: Arc<Manager>,
+    accounts: &[(ScanRequest, u64)],
+    start: u64,
+    end: u64,
+) -> anyhow::Result<()> {
+    // a sweep starts level with the ones already running and then takes turns with them
+    let base_round = scheduler.dispatch_round.load(Ordering::Relaxed);
+    let mut parent: Option<String> = None;
+    for group in blocks_range(start..end + 1, N::PRIMARY_BATCH) {
+        let blocks = load_blocks(&scheduler, group.start, group.end, parent.as_deref()).await?;
+        parent = blocks.last().map(|block| block.hash.clone());
+        let round = base_round + group.start - start;
+        scheduling_tasks(scheduler.clone(), accounts, blocks, round).await?;
+        // avoid too much memory usage
+        scheduler.wait_queue_space().await;
+    }
+    Ok(())
+}
+
+/// Accounts whose scan starts within this many blocks of each other share one sweep.
+pub const SWEEP_JOIN_BLOCKS: u64 = 1000;
+
+#[derive(Debug)]
+pub struct Sweep {
+    pub start: u64,
+    pub end: u64,
+    // accounts with the epoch of the session they are scanned for
+    pub accounts: Vec<(ScanRequest, u64)>,
+}
+
+/// Group `(start, end, account, epoch)` scans into sweeps over close block ranges, so an
+/// import near the tip doesn't wait behind a rescan from genesis.
+pub fn plan_sweeps(mut accounts: Vec<(u64, u64, ScanRequest, u64)>) -> Vec<Sweep> {
+    accounts.sort_by_key(|(start, _, _, _)| *start);
+    let mut sweeps: Vec<Sweep> = vec![];
+    for (start, end, account, epoch) in accounts {
+        match sweeps.last_mut() {
+            Some(sweep) if start < sweep.start + SWEEP_JOIN_BLOCKS => {
+                sweep.end = cmp::max(sweep.end, end);
+                sweep.accounts.push((account, epoch));
+            }
+            _ => sweeps.push(Sweep {
+                start,
+                end,
+                accounts: vec![(account, epoch)],
+            }),
+        }
+    }
+    sweeps
+}
+
+/// Run sweeps concurrently, accounts of a failed sweep are scanned again from their start.
+pub fn spawn_sweeps<N: Network>(scheduler: Arc<Manager>, sweeps: Vec<Sweep>) {
+    for sweep in sweeps {
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            info!(
+                "Start sweep of {} accounts from {} to {}",
+                sweep.accounts.len(),
+                sweep.start,
+                sweep.end
             );
-            for block in blocks.iter() {
-                if (block.sequence as u64) < account_info.start_block.sequence
-                    || (block.sequence as u64) > account_info.end_block.sequence
-                {
-                    debug!("skip height {:?}", block.sequence);
-                    continue;
+            if let Err(e) =
+                scheduling_range::<N>(scheduler.clone(), &sweep.accounts, sweep.start, sweep.end)
+                    .await
+            {
+                error!("Failed to schedule scan tasks, {}", e);
+                for (account, epoch) in sweep.accounts.iter() {
+                    scheduler.abort_session(&account.address, *epoch).await;
                 }
-                let task = DRequest::from_transactions(account, block.transactions.deref().clone());
-                let task_id = task.id.clone();
-                let _ = scheduler.task_mapping.write().await.insert(
-                    task_id,
-                    TaskInfo {
-                        since: Instant::now(),
-                        sequence: block.sequence,
-                        hash: block.hash.clone(),
-                        address: account.address.clone(),
-                    },
+            }
+        });
+    }
+}
+
+/// Reload scan sessions left by a previous run or by a scanner which lost its leases,
+/// scheduled ones continue from their watermark.
+pub async fn resume_scan_sessions<N: Network>(scheduler: Arc<Manager>) -> anyhow::Result<()> {
+    let sessions = scheduler.load_scan_sessions().await?;
+    let mut accounts = vec![];
+    let mut finished = vec![];
+    for session in sessions {
+        if scheduler.should_skip_request(session.address.clone()).await {
+            continue;
+        }
+        let request = ScanRequest {
+            address: session.address.clone(),
+            in_vk: session.in_vk.clone(),
+            out_vk: session.out_vk.clone(),
+            head: Some(BlockInfo {
+                hash: session.start_hash.clone(),
+                sequence: session.start_sequence as u64,
+            }),
+            options: session.scan_options.0.clone(),
+            dry_run: false,
+        };
+        let results = scheduler
+            .shared
+            .db_handler
+            .get_scan_results(session.address.clone())
+            .await?;
+        match AccountInfo::from_session(session, results) {
+            Some(account) => {
+                info!(
+                    "Resume scanning account {} from {} to {}",
+                    request.address,
+                    account.scanned + 1,
+                    account.end_block.sequence
                 );
-                let mut task_sent = false;
-                for (k, worker) in scheduler.workers.read().await.iter() {
-                    if worker.status == 1 {
-                        match worker
-                            .router
-                            .send(ServerMessage {
-                                name: Some(k.to_string()),
-                                request: task.clone(),
-                            })
-                            .await
-                        {
-                            Ok(_) => {
-                                task_sent = true;
-                                break;
-                            }
-                            Err(e) => {
-                                error!("failed to send message to worker, {:?}", e);
-                            }
-                        }
-                    }
-                }
-                if task_sent {
-                    continue;
-                }
+                let range = (account.scanned + 1, account.end_block.sequence);
+                let finished_maybe = account.remaining_task == 0;
+                let epoch = account.epoch;
                 let _ = scheduler
-                    .task_queue
+                    .account_mappling
                     .write()
                     .await
-                    .push(task, Reverse(block.sequence));
+                    .insert(request.address.clone(), account);
+                match finished_maybe {
+                    true => finished.push(request.address),
+                    false => accounts.push((range.0, range.1, request, epoch)),
+                }
+            }
+            None => {
+                info!("Resume pending scan request of account {}", request.address);
+                scheduler.accounts_to_scan.write().await.push(request);
             }
         }
     }
+    for address in finished {
+        scheduler.finish_account(address).await;
+    }
+    spawn_sweeps::<N>(scheduler, plan_sweeps(accounts));
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_dserver<N: Network>(
     dlisten: SocketAddr,
     restful: SocketAddr,
-    rpc_server: String,
+    rpc_server: Vec<String>,
     db_handler: Box<dyn Send + Sync + DBHandler>,
     server: String,
     operator: String,
+    transport: TransportConfig,
+    note_details: bool,
+    verify_fraction: f64,
+    instance: Option<String>,
 ) -> anyhow::Result<()> {
-    let shared_resource = Arc::new(SharedState::new(db_handler, &rpc_server, &server, operator));
-    let manager = Manager::new(shared_resource, N::ID);
+    let shared_resource = Arc::new(SharedState::new(db_handler, rpc_server, &server, operator));
+    shared_resource
+        .rpc_handler
+        .initialize_health_checker()
+        .await;
+    let manager = Manager::new(
+        shared_resource,
+        N::ID,
+        note_details,
+        verify_fraction,
+        instance,
+    );
 
-    if let Err(e) = Manager::initialize_networking(manager.clone(), dlisten).await {
+    if let Err(e) = Manager::initialize_networking(manager.clone(), dlisten, transport).await {
         error!("Init networking server failed {}", e);
     }
 
@@ -121,38 +347,58 @@ pub async fn run_dserver<N: Network>(
         error!("Init status updater failed {}", e);
     }
 
+    if let Err(e) = Manager::initialize_metrics_updater(manager.clone()).await {
+        error!("Init metrics updater failed {}", e);
+    }
+
     {
         info!("Warmup, waiting for workers to join");
         sleep(Duration::from_secs(60)).await;
     }
 
+    if let Err(e) = Manager::initialize_reconciler(manager.clone()).await {
+        error!("Init reconciler failed {}", e);
+    }
+
     // primary task scheduling
     let schduler = manager.clone();
     let (router, handler) = oneshot::channel();
     tokio::spawn(async move {
         let _ = router.send(());
+        if let Err(e) = resume_scan_sessions::<N>(schduler.clone()).await {
+            error!("Failed to resume scan sessions, {}", e);
+        }
         loop {
             sleep(N::RESCHEDULING_DURATION).await;
             if !schduler.accounts_to_scan.read().await.is_empty() {
-                if !schduler.account_mappling.read().await.is_empty() {
-                    continue;
-                }
                 let mut accounts_should_scan = vec![];
-                let mut scan_start = u64::MAX;
-                let latest = schduler
-                    .shared
-                    .rpc_handler
-                    .get_latest_block()
-                    .unwrap()
-                    .data
-                    .current_block_identifier;
-                let scan_end = schduler
+                // nodes may all be briefly unreachable, retry on the next round
+                let latest = match schduler.shared.rpc_handler.get_latest_block().await {
+                    Ok(latest) => latest.data.current_block_identifier,
+                    Err(e) => {
+                        error!("Failed to get latest block, {}", e);
+                        continue;
+                    }
+                };
+                let latest_height = match latest.index.parse::<i64>() {
+                    Ok(height) => height,
+                    Err(e) => {
+                        error!("Unparsable latest block height {}, {}", latest.index, e);
+                        continue;
+                    }
+                };
+                let scan_end = match schduler
                     .shared
                     .rpc_handler
-                    .get_block(latest.index.parse::<i64>().unwrap() - N::REORG_DEPTH)
-                    .unwrap()
-                    .data
-                    .block;
+                    .get_block(latest_height - N::REORG_DEPTH)
+                    .await
+                {
+                    Ok(block) => block.data.block,
+                    Err(e) => {
+                        error!("Failed to get scan end block, {}", e);
+                        continue;
+                    }
+                };
                 let scan_end = BlockInfo {
                     sequence: scan_end.sequence as u64,
                     hash: scan_end.hash,
@@ -176,145 +422,85 @@ pub async fn run_dserver<N: Network>(
                             false => head,
                         }
                     };
-                    let _ = schduler.account_mappling.write().await.insert(
-                        account.address.clone(),
-                        AccountInfo::new(
-                            head.clone(),
-                            scan_end.clone(),
-                            account.in_vk.clone(),
-                            account.out_vk.clone(),
-                        ),
+                    let session = ScanSession {
+                        address: account.address.clone(),
+                        in_vk: account.in_vk.clone(),
+                        out_vk: account.out_vk.clone(),
+                        start_hash: head.hash.clone(),
+                        start_sequence: head.sequence as i64,
+                        end_hash: Some(scan_end.hash.clone()),
+                        end_sequence: Some(scan_end.sequence as i64),
+                        scanned_sequence: head.sequence as i64 - 1,
+                        scan_options: db_handler::Json(account.options.clone()),
+                        // keeps the lease this scanner claimed the session with
+                        owner: None,
+                        completed_ranges: db_handler::Json::default(),
+                    };
+                    if !account.dry_run {
+                        if let Err(e) = schduler.shared.db_handler.save_scan_session(session).await
+                        {
+                            error!("Failed to save scan session {}, {}", account.address, e);
+                        }
+                    }
+                    let mut account_info = AccountInfo::new(
+                        head.clone(),
+                        scan_end.clone(),
+                        account.in_vk.clone(),
+                        account.out_vk.clone(),
+                        account.options.clone(),
                     );
-                    scan_start = cmp::min(scan_start, head.sequence);
-                    accounts_should_scan.push(account);
+                    account_info.dry_run = account.dry_run;
+                    let epoch = account_info.epoch;
+                    let _ = schduler
+                        .account_mappling
+                        .write()
+                        .await
+                        .insert(account.address.clone(), account_info);
+                    accounts_should_scan.push((head.sequence, scan_end.sequence, account, epoch));
                 }
                 if accounts_should_scan.is_empty() {
                     continue;
                 }
                 info!("accounts to scan, {:?}", accounts_should_scan);
-                let blocks_to_scan =
-                    blocks_range(scan_start..scan_end.sequence + 1, N::PRIMARY_BATCH);
-                for group in blocks_to_scan {
-                    let blocks = match group.end <= N::LOCAL_BLOCKS_CHECKPOINT {
-                        true => schduler
-                            .shared
-                            .db_handler
-                            .get_blocks(group.start as i64, group.end as i64)
-                            .await
-                            .unwrap(),
-                        false => {
-                            let items = schduler
-                                .shared
-                                .rpc_handler
-                                .get_blocks(group.start, group.end)
-                                .unwrap()
-                                .data
-                                .blocks;
-                            items
-                                .into_iter()
-                                .map(|item| item.block.to_inner())
-                                .collect()
-                        }
-                    };
-
-                    let _ = scheduling_tasks(schduler.clone(), &accounts_should_scan, blocks)
-                        .await
-                        .unwrap();
-                    // avoid too much memory usage
-                    if (schduler.task_queue.read().await.len() > 10000) {
-                        sleep(Duration::from_secs(3)).await;
-                    }
-                }
+                spawn_sweeps::<N>(schduler.clone(), plan_sweeps(accounts_should_scan));
             }
         }
     });
     let _ = handler.await;
 
-    // secondary task scheduling
+    // secondary task scheduling, retry tasks lost or stuck on workers and results left unchecked
     let secondary = manager.clone();
     let (router, handler) = oneshot::channel();
     tokio::spawn(async move {
         let _ = router.send(());
         loop {
-            let mut keys_to_reschedule = vec![];
-            for key in secondary
-                .task_mapping
-                .read()
-                .await
-                .iter()
-                .filter(|(_k, v)| v.since.elapsed().as_secs() >= 600)
-                .map(|(k, _)| k.to_string())
-            {
-                keys_to_reschedule.push(key);
-            }
-            let keys_count = keys_to_reschedule.len();
-            if keys_count > 0 {
-                info!("Rescheduling {} tasks", keys_count);
-            }
-            let mut tasks_to_resechedule = vec![];
-            for key in keys_to_reschedule {
-                let key_maybe = secondary.task_mapping.write().await.remove(&key).clone();
-                if let Some(task_info) = key_maybe {
-                    let address = task_info.address;
-                    let sequence = task_info.sequence;
-                    let address_maybe = secondary
-                        .account_mappling
-                        .read()
-                        .await
-                        .get(&address)
-                        .cloned();
-                    if let Some(account) = address_maybe {
-                        if let Ok(block) = secondary.shared.rpc_handler.get_block(sequence) {
-                            let block = block.data.block.to_inner();
-                            tasks_to_resechedule.push((
-                                vec![ScanRequest {
-                                    address: address.clone(),
-                                    in_vk: account.in_vk.clone(),
-                                    out_vk: account.out_vk.clone(),
-                                    head: Some(account.start_block.clone()),
-                                }],
-                                vec![block],
-                            ));
-                            if tasks_to_resechedule.len() % 500 == 0 {
-                                info!(
-                                    "Tasks to reschedule len now {:?}",
-                                    tasks_to_resechedule.len()
-                                );
-                            }
-                            // We dont want to reschedule so many tasks at once
-                            if tasks_to_resechedule.len() >= 20000 {
-                                break;
-                            }
-                        }
-                    } else {
-                        error!("Account info missed for exist task, account {}", address);
-                    }
-                }
-            }
-            if !tasks_to_resechedule.is_empty() {
-                info!(
-                    "Done prepping tasks to be rescheduled.. now rescheduling {:?}",
-                    tasks_to_resechedule.len()
-                );
-            }
-            let mut count = 0;
-            for (scan_request, blocks) in tasks_to_resechedule {
-                scheduling_tasks(secondary.clone(), &scan_request, blocks)
-                    .await
-                    .unwrap();
-                if count % 1000 == 0 {
-                    info!("Rescheduled tasks so far {:?}", count);
-                }
-                count += 1;
-            }
-            if count > 0 {
-                info!("Done rescheduling {:?} tasks", count);
-            }
+            secondary.requeue_slow_tasks().await;
+            secondary.finish_scanned_accounts().await;
             sleep(N::RESCHEDULING_DURATION).await;
         }
     });
     let _ = handler.await;
 
+    // with a shared db, keep leases on the sessions scanned here and take over expired ones
+    if manager.instance.is_some() {
+        let keeper = manager.clone();
+        let (router, handler) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = router.send(());
+            loop {
+                sleep(LEASE_RENEW_INTERVAL).await;
+                if let Err(e) = keeper.renew_leases().await {
+                    error!("Failed to renew scan session leases, {}", e);
+                    continue;
+                }
+                if let Err(e) = resume_scan_sessions::<N>(keeper.clone()).await {
+                    error!("Failed to claim scan sessions, {}", e);
+                }
+            }
+        });
+        let _ = handler.await;
+    }
+
     let (router, handler) = oneshot::channel();
     tokio::spawn(async move {
         let _ = router.send(());
@@ -329,6 +515,12 @@ pub async fn run_dserver<N: Network>(
 pub async fn start_rest(server: Arc<Manager>, restful: SocketAddr) -> anyhow::Result<()> {
     let router = Router::new()
         .route("/scanAccount", post(account_scanner_handler))
+        .route("/scanSessions", post(scan_sessions_handler))
+        .route("/scanStatus", post(scan_status_handler))
+        .route("/cancelScan", post(cancel_scan_handler))
+        .route("/removeAccount", post(remove_account_handler))
+        .route("/dryRunResult", post(dry_run_result_handler))
+        .route("/workers", post(workers_handler))
         .with_state(server)
         .layer(
             ServiceBuilder::new()
@@ -354,23 +546,475 @@ pub async fn account_scanner_handler(
     extract::Json(request): extract::Json<DecryptionMessage<ScanRequest>>,
 ) -> impl IntoResponse {
     info!("new scan request coming: {:?}", request);
+    Json(SuccessResponse {
+        success: manager.submit_scan_request(request).await,
+    })
+}
+
+/// The query of a request signed by the operator, refused once stale.
+pub fn verify_query(
+    manager: &Manager,
+    request: DecryptionMessage<ScannerQuery>,
+) -> Result<ScannerQuery, OreoError> {
     let DecryptionMessage { message, signature } = request;
-    if let Ok(true) = manager.shared.operator.verify(&message, signature) {
-        if !manager.should_skip_request(message.address.clone()).await {
-            let _ = manager.accounts_to_scan.write().await.push(message);
-        }
-        return Json(SuccessResponse { success: true });
+    match manager.shared.operator.verify(&message, signature) {
+        Ok(true) if message.is_fresh() => Ok(message),
+        _ => Err(OreoError::BadSignature),
+    }
+}
+
+fn query_address(query: ScannerQuery) -> Result<String, OreoError> {
+    query
+        .address
+        .ok_or(OreoError::ParseError("address is required".into()))
+}
+
+pub async fn scan_sessions_handler(
+    State(manager): State<Arc<Manager>>,
+    extract::Json(request): extract::Json<DecryptionMessage<ScannerQuery>>,
+) -> impl IntoResponse {
+    match verify_query(&manager, request) {
+        Ok(_) => Json(ScanSessionsResponse {
+            sessions: manager.scan_sessions().await,
+        })
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn scan_status_handler(
+    State(manager): State<Arc<Manager>>,
+    extract::Json(request): extract::Json<DecryptionMessage<ScannerQuery>>,
+) -> impl IntoResponse {
+    match verify_query(&manager, request).and_then(query_address) {
+        Ok(address) => Json(ScanStatusResponse {
+            progress: manager.scan_progress(&address).await,
+            address,
+        })
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn cancel_scan_handler(
+    State(manager): State<Arc<Manager>>,
+    extract::Json(request): extract::Json<DecryptionMessage<ScannerQuery>>,
+) -> impl IntoResponse {
+    match verify_query(&manager, request).and_then(query_address) {
+        Ok(address) => Json(SuccessResponse {
+            success: manager.cancel_scan(&address).await,
+        })
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn remove_account_handler(
+    State(manager): State<Arc<Manager>>,
+    extract::Json(request): extract::Json<DecryptionMessage<ScannerQuery>>,
+) -> impl IntoResponse {
+    match verify_query(&manager, request).and_then(query_address) {
+        Ok(address) => Json(SuccessResponse {
+            success: manager.remove_account(&address).await,
+        })
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn dry_run_result_handler(
+    State(manager): State<Arc<Manager>>,
+    extract::Json(request): extract::Json<DecryptionMessage<ScannerQuery>>,
+) -> impl IntoResponse {
+    match verify_query(&manager, request).and_then(query_address) {
+        Ok(address) => Json(manager.dry_run(&address).await).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn workers_handler(
+    State(manager): State<Arc<Manager>>,
+    extract::Json(request): extract::Json<DecryptionMessage<ScannerQuery>>,
+) -> impl IntoResponse {
+    match verify_query(&manager, request) {
+        Ok(_) => Json(WorkersResponse {
+            workers: manager.worker_statuses().await,
+        })
+        .into_response(),
+        Err(e) => e.into_response(),
     }
-    Json(SuccessResponse { success: false })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::blocks_range;
+    use db_handler::{InnerBlock, Json, RedisClient, ScanSession};
+    use networking::rpc_abi::BlockInfo;
+
+    use networking::decryption_message::ScanRequest;
+
+    use std::{
+        cmp::Reverse,
+        collections::{BTreeSet, HashMap},
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use networking::socket_message::codec::{AccountHandle, DBatchRequest, DBatchResponse};
+    use params::{network::Network, testnet::Testnet};
+    use tokio::sync::mpsc;
+
+    use crate::{
+        blocks_range,
+        manager::{
+            agreed_response, AccountInfo, Manager, ServerMessage, ServerWorker, SharedState,
+            TaskInfo,
+        },
+        plan_sweeps,
+    };
+
+    /// A manager on a redis db, which keeps no scan sessions, and unreachable nodes.
+    fn manager() -> Arc<Manager> {
+        let db_handler = RedisClient::connect("redis://127.0.0.1:1", 1).unwrap();
+        let shared = SharedState::new(
+            Box::new(db_handler),
+            vec!["127.0.0.1:1".to_string()],
+            "127.0.0.1:1",
+            "0101010101010101010101010101010101010101010101010101010101010101".to_string(),
+        );
+        Manager::new(Arc::new(shared), Testnet::ID, false, 0.0, None)
+    }
+
+    fn task(id: &str, addresses: &[&str]) -> DBatchRequest {
+        DBatchRequest {
+            id: id.to_string(),
+            accounts: addresses
+                .iter()
+                .map(|address| AccountHandle {
+                    address: address.to_string(),
+                    handle: 0,
+                })
+                .collect(),
+            decrypt_for_spender: true,
+            data: vec![],
+            with_notes: false,
+        }
+    }
+
+    /// Track `request` as a task handed to `worker`.
+    async fn dispatched(manager: &Manager, request: DBatchRequest, worker: &str) {
+        let mut info = TaskInfo::new(request.clone(), 1, "hash".to_string());
+        info.worker = Some(worker.to_string());
+        info.dispatched = Some(Instant::now());
+        let _ = manager.task_mapping.write().await.insert(request.id, info);
+    }
 
     #[test]
     fn block_range_test() {
-        let range = blocks_range(1..100, 30);
-        println!("ranges, {:?}", range);
+        assert_eq!(
+            blocks_range(1..100, 30),
+            vec![1..30, 31..60, 61..90, 91..100]
+        );
+    }
+
+    #[test]
+    fn account_watermark_should_advance_in_order() {
+        let block = |sequence: u64| BlockInfo {
+            hash: sequence.to_string(),
+            sequence,
+        };
+        let mut account = AccountInfo::new(
+            block(10),
+            block(20),
+            "".into(),
+            "".into(),
+            Default::default(),
+        );
+        assert_eq!(account.scanned, 9);
+        account.complete(11);
+        assert_eq!(account.scanned, 9);
+        account.complete(10);
+        assert_eq!(account.scanned, 11);
+        assert!(account.completed.is_empty());
+    }
+
+    #[test]
+    fn duplicate_answers_should_count_once() {
+        let block = |sequence: u64| BlockInfo {
+            hash: sequence.to_string(),
+            sequence,
+        };
+        let mut account = AccountInfo::new(
+            block(10),
+            block(11),
+            "".into(),
+            "".into(),
+            Default::default(),
+        );
+        assert!(account.scan_block(11));
+        // the same block answered for a second sweep of the account
+        assert!(!account.scan_block(11));
+        assert_eq!(account.remaining_task, 1);
+        assert!(account.scan_block(10));
+        assert!(!account.scan_block(10));
+        assert_eq!(account.remaining_task, 0);
+        assert_eq!(account.scanned, 11);
+        let restarted = AccountInfo::new(
+            block(10),
+            block(11),
+            "".into(),
+            "".into(),
+            Default::default(),
+        );
+        assert_ne!(restarted.epoch, account.epoch);
+    }
+
+    #[test]
+    fn completed_ranges_should_survive_sessions() {
+        let block = |sequence: u64| BlockInfo {
+            hash: sequence.to_string(),
+            sequence,
+        };
+        let mut account = AccountInfo::new(
+            block(10),
+            block(20),
+            "".into(),
+            "".into(),
+            Default::default(),
+        );
+        for sequence in [12, 13, 14, 17] {
+            account.complete(sequence);
+        }
+        assert_eq!(account.completed_ranges(), vec![(12, 14), (17, 17)]);
+        let session = ScanSession {
+            address: "a".into(),
+            in_vk: "".into(),
+            out_vk: "".into(),
+            start_hash: "10".into(),
+            start_sequence: 10,
+            end_hash: Some("20".into()),
+            end_sequence: Some(20),
+            scanned_sequence: 10,
+            scan_options: Json::default(),
+            owner: None,
+            completed_ranges: Json(account.completed_ranges()),
+        };
+        let account = AccountInfo::from_session(session, vec![]).unwrap();
+        assert_eq!(account.scanned, 10);
+        assert_eq!(account.completed.len(), 4);
+        assert_eq!(account.remaining_task, 6);
+    }
+
+    #[test]
+    fn scan_eta_should_follow_pace() {
+        let block = |sequence: u64| BlockInfo {
+            hash: sequence.to_string(),
+            sequence,
+        };
+        let mut account = AccountInfo::new(
+            block(1),
+            block(100),
+            "".into(),
+            "".into(),
+            Default::default(),
+        );
+        assert!(account.eta().is_none());
+        account.since = Some((Instant::now() - Duration::from_secs(100), 100));
+        account.remaining_task = 50;
+        let eta = account.eta().unwrap().as_secs();
+        assert!((100..=101).contains(&eta));
+        let progress = account.progress("a");
+        assert_eq!((progress.total_task, progress.remaining_task), (100, 50));
+    }
+
+    #[test]
+    fn broken_hash_chain_should_be_detected() {
+        let block = |sequence: i64, hash: &str, previous: &str| InnerBlock {
+            hash: hash.into(),
+            sequence,
+            transactions: Json(vec![]),
+            previous_block_hash: Some(previous.into()),
+        };
+        let blocks = vec![block(10, "b", "a"), block(11, "c", "b")];
+        assert_eq!(InnerBlock::broken_link(&blocks, Some("a")), None);
+        assert_eq!(InnerBlock::broken_link(&blocks, Some("x")), Some(10));
+        let blocks = vec![block(10, "b", "a"), block(11, "c", "x")];
+        assert_eq!(InnerBlock::broken_link(&blocks, None), Some(11));
+        let blocks = vec![block(10, "b", "a"), block(12, "c", "b")];
+        assert_eq!(InnerBlock::broken_link(&blocks, None), Some(12));
+    }
+
+    #[test]
+    fn distant_scans_should_get_own_sweep() {
+        let request = |address: &str| ScanRequest {
+            address: address.into(),
+            in_vk: "".into(),
+            out_vk: "".into(),
+            head: None,
+            options: Default::default(),
+            dry_run: false,
+        };
+        let sweeps = plan_sweeps(vec![
+            (900_000, 950_000, request("new"), 0),
+            (1, 950_000, request("genesis"), 1),
+            (500, 950_100, request("old"), 2),
+        ]);
+        assert_eq!(sweeps.len(), 2);
+        assert_eq!((sweeps[0].start, sweeps[0].end), (1, 950_100));
+        assert_eq!(sweeps[0].accounts.len(), 2);
+        assert_eq!(sweeps[1].accounts[0].0.address, "new");
+    }
+
+    #[test]
+    fn verified_tasks_should_need_two_agreeing_workers() {
+        let response = |worker: &str, txs: &[&str]| {
+            (
+                worker.to_string(),
+                DBatchResponse {
+                    id: "id".into(),
+                    data: HashMap::from([(
+                        "a".to_string(),
+                        txs.iter().map(|tx| tx.to_string()).collect(),
+                    )]),
+                    notes: HashMap::new(),
+                },
+            )
+        };
+        let addresses = BTreeSet::from(["a".to_string()]);
+        let mut responses = vec![response("w1", &["tx1"]), response("w2", &[])];
+        assert!(agreed_response(&responses, &addresses).is_none());
+        responses.push(response("w3", &["tx1"]));
+        let (agreed, dissenters) = agreed_response(&responses, &addresses).unwrap();
+        assert_eq!(agreed.data["a"], vec!["tx1".to_string()]);
+        assert_eq!(dissenters, vec!["w2".to_string()]);
+        let responses = vec![
+            response("w1", &["tx1", "tx2"]),
+            response("w2", &["tx2", "tx1"]),
+        ];
+        let (_, dissenters) = agreed_response(&responses, &addresses).unwrap();
+        assert!(dissenters.is_empty());
+    }
+
+    #[test]
+    fn expired_tasks_should_return_credit_once() {
+        let (router, _) = tokio::sync::mpsc::channel(1);
+        let mut worker = ServerWorker::new(router, "worker".into(), 2);
+        worker.in_flight = 2;
+        worker.expire("t1");
+        worker.expire("t1");
+        assert_eq!(worker.credits(), 1);
+        // the late answer of the expired task must not free another credit
+        worker.settle("t1");
+        assert_eq!(worker.credits(), 1);
+        worker.settle("t2");
+        assert_eq!(worker.credits(), 2);
+        assert!(worker.expired.is_empty());
+    }
+
+    #[tokio::test]
+    async fn requeued_tasks_should_skip_their_worker() {
+        let manager = manager();
+        let request = task("t1", &["a"]);
+        dispatched(&manager, request.clone(), "slow").await;
+        let _ = manager
+            .task_queue
+            .write()
+            .await
+            .push(request, Reverse((0, 1)));
+        assert!(manager.next_task("slow").await.is_none());
+        assert_eq!(manager.next_task("fast").await.unwrap().id, "t1");
+    }
+
+    #[tokio::test]
+    async fn disconnected_worker_tasks_should_be_requeued() {
+        let manager = manager();
+        dispatched(&manager, task("t1", &["a"]), "gone").await;
+        dispatched(&manager, task("t2", &["a"]), "other").await;
+        let (router, mut tasks) = mpsc::channel(4);
+        let _ = manager.workers.write().await.insert(
+            "next".to_string(),
+            ServerWorker::new(router, "next".to_string(), 4),
+        );
+        manager.requeue_worker_tasks("gone").await;
+        match tasks.try_recv() {
+            Ok(ServerMessage::Task(request)) => assert_eq!(request.id, "t1"),
+            _ => panic!("the task of the gone worker should be dispatched again"),
+        }
+        assert!(tasks.try_recv().is_err());
+        let task_mapping = manager.task_mapping.read().await;
+        assert_eq!(task_mapping["t1"].worker.as_deref(), Some("next"));
+        assert_eq!(task_mapping["t2"].worker.as_deref(), Some("other"));
+        assert!(manager.task_queue.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn removed_accounts_should_leave_tasks() {
+        let manager = manager();
+        dispatched(&manager, task("t1", &["a"]), "w1").await;
+        dispatched(&manager, task("t2", &["a", "b"]), "w1").await;
+        {
+            let mut task_queue = manager.task_queue.write().await;
+            let _ = task_queue.push(task("t3", &["a"]), Reverse((0, 3)));
+            let _ = task_queue.push(task("t4", &["a", "b"]), Reverse((0, 4)));
+            let _ = task_queue.push(task("t5", &["b"]), Reverse((0, 5)));
+        }
+        // redis keeps no scan sessions to remove, so the removal reports a failure
+        assert!(!manager.remove_account("a").await);
+        let addresses = |request: &DBatchRequest| {
+            request
+                .accounts
+                .iter()
+                .map(|account| account.address.clone())
+                .collect::<Vec<String>>()
+        };
+        let task_mapping = manager.task_mapping.read().await;
+        assert!(!task_mapping.contains_key("t1"));
+        assert_eq!(addresses(&task_mapping["t2"].request), vec!["b"]);
+        let queued: HashMap<String, Vec<String>> = manager
+            .task_queue
+            .read()
+            .await
+            .iter()
+            .map(|(request, _)| (request.id.clone(), addresses(request)))
+            .collect();
+        assert_eq!(
+            queued,
+            HashMap::from([
+                ("t4".to_string(), vec!["b".to_string()]),
+                ("t5".to_string(), vec!["b".to_string()]),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_accounts_should_release_keys() {
+        let manager = manager();
+        let account = |address: &str| ScanRequest {
+            address: address.into(),
+            in_vk: "".into(),
+            out_vk: "".into(),
+            head: None,
+            options: Default::default(),
+            dry_run: false,
+        };
+        let removed = manager.key_handle(&account("a")).await;
+        let kept = manager.key_handle(&account("b")).await;
+        let (router, mut messages) = mpsc::channel(4);
+        let _ = manager.workers.write().await.insert(
+            "w1".to_string(),
+            ServerWorker::new(router, "w1".to_string(), 4),
+        );
+        let worker = tokio::spawn(async move {
+            match messages.recv().await {
+                Some(ServerMessage::ReleaseKeys(handles, confirm)) => {
+                    let _ = confirm.send(true);
+                    handles
+                }
+                _ => vec![],
+            }
+        });
+        let _ = manager.remove_account("a").await;
+        assert_eq!(worker.await.unwrap(), vec![removed.handle]);
+        assert!(manager.registered_keys(&removed).await.is_none());
+        assert!(manager.registered_keys(&kept).await.is_some());
     }
 }
@@ -1,21 +1,36 @@
 use std::{
-    cmp::Reverse,
-    collections::HashMap,
+    cmp::{self, Reverse},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     net::SocketAddr,
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
 use anyhow::Result;
-use db_handler::DBHandler;
+use db_handler::{Account, DBHandler, Json, ScanNote, ScanOptions, ScanResult, ScanSession};
 use futures::{SinkExt, StreamExt};
+use metrics::{counter, gauge};
 use networking::{
-    decryption_message::{DecryptionMessage, ScanRequest},
-    rpc_abi::{BlockInfo, BlockWithHash, RpcSetAccountHeadRequest, TransactionWithHash},
+    decryption_message::{
+        DecryptionMessage, DryRunResponse, ScanProgress, ScanRequest, ScanResponse, WorkerStatus,
+    },
+    rpc_abi::{
+        BlockInfo, BlockWithHash, RpcGetAccountStatusRequest, RpcSetAccountHeadRequest,
+        RpcSetScanningRequest, TransactionWithHash,
+    },
     rpc_handler::RpcHandler,
     server_handler::ServerHandler,
-    socket_message::codec::{DMessage, DMessageCodec, DRequest, DResponse},
+    socket_message::{
+        codec::{
+            AccountHandle, DBatchRequest, DBatchResponse, DMessage, DecryptedNote, Encoding,
+            LegacyBatches, RegisterKeys, ReleaseKeys, ViewKeys,
+        },
+        handshake::TransportConfig,
+    },
 };
 use params::{mainnet::Mainnet, network::Network, testnet::Testnet};
 use priority_queue::PriorityQueue;
@@ -25,7 +40,7 @@ use tokio::{
     net::{TcpListener, TcpStream},
     sync::{
         mpsc::{self, Sender},
-        oneshot, RwLock,
+        oneshot, Notify, RwLock,
     },
     time::{sleep, timeout},
 };
@@ -33,23 +48,54 @@ use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::{debug, error, info, warn};
 use utils::Signer;
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct ServerMessage {
-    pub name: Option<String>,
-    pub request: DRequest,
+#[derive(Debug)]
+pub enum ServerMessage {
+    Task(DBatchRequest),
+    // handles of ended sessions, only forwarded if registered with the worker, the sender
+    // learns whether the release was written to the connection
+    ReleaseKeys(Vec<u64>, oneshot::Sender<bool>),
 }
 
 #[derive(Debug, Clone)]
 pub struct ServerWorker {
     pub router: Sender<ServerMessage>,
-    // 1: Idle; 2: Busy
-    pub status: u8,
+    // name the worker reported, its identity is the key it is registered under
+    pub name: String,
+    // tasks the worker advertised it can take at once
+    pub capacity: usize,
+    // tasks sent and not answered yet
+    pub in_flight: usize,
+    // timed out tasks whose credit was returned before the worker answered them
+    pub expired: HashSet<String>,
 }
 
 impl ServerWorker {
-    pub fn new(router: Sender<ServerMessage>) -> Self {
-        Self { router, status: 1 }
+    pub fn new(router: Sender<ServerMessage>, name: String, capacity: usize) -> Self {
+        Self {
+            router,
+            name,
+            capacity: cmp::max(capacity, 1),
+            in_flight: 0,
+            expired: HashSet::new(),
+        }
+    }
+
+    pub fn credits(&self) -> usize {
+        self.capacity.saturating_sub(self.in_flight)
+    }
+
+    /// Return the credit of a task the worker did not answer in time.
+    pub fn expire(&mut self, task_id: &str) {
+        if self.expired.insert(task_id.to_string()) {
+            self.in_flight = self.in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Return the credit of an answered task, unless it was returned when the task expired.
+    pub fn settle(&mut self, task_id: &str) {
+        if !self.expired.remove(task_id) {
+            self.in_flight = self.in_flight.saturating_sub(1);
+        }
     }
 }
 
@@ -58,37 +104,279 @@ pub struct TaskInfo {
     pub since: Instant,
     pub sequence: i64,
     pub hash: String,
-    pub address: String,
+    // kept to requeue the task without fetching the block again
+    pub request: DBatchRequest,
+    // worker the task was last handed to
+    pub worker: Option<String>,
+    pub dispatched: Option<Instant>,
+    pub speculated: bool,
+    // answered by two workers which must agree before the result is used
+    pub verify: bool,
+    // responses of a verified task by worker, until two of them agree
+    pub responses: Vec<(String, DBatchResponse)>,
+}
+
+impl TaskInfo {
+    pub fn new(request: DBatchRequest, sequence: i64, hash: String) -> Self {
+        Self {
+            since: Instant::now(),
+            sequence,
+            hash,
+            request,
+            worker: None,
+            dispatched: None,
+            speculated: false,
+            verify: false,
+            responses: vec![],
+        }
+    }
+
+    /// Accounts the block is scanned for in this task.
+    pub fn addresses(&self) -> impl Iterator<Item = &String> {
+        self.request.accounts.iter().map(|account| &account.address)
+    }
+
+    /// Whether `worker` already answered this verified task.
+    pub fn answered_by(&self, worker: &str) -> bool {
+        self.responses.iter().any(|(name, _)| name == worker)
+    }
+}
+
+/// The response two workers agree on for `addresses`, with the workers which answered
+/// something else.
+pub fn agreed_response(
+    responses: &[(String, DBatchResponse)],
+    addresses: &BTreeSet<String>,
+) -> Option<(DBatchResponse, Vec<String>)> {
+    let results: Vec<BTreeMap<&String, BTreeSet<&String>>> = responses
+        .iter()
+        .map(|(_, response)| {
+            addresses
+                .iter()
+                .filter_map(|address| {
+                    let hashes: BTreeSet<&String> = response.data.get(address)?.iter().collect();
+                    (!hashes.is_empty()).then_some((address, hashes))
+                })
+                .collect()
+        })
+        .collect();
+    let agreed = results
+        .iter()
+        .position(|result| results.iter().filter(|other| *other == result).count() >= 2)?;
+    let dissenters = responses
+        .iter()
+        .zip(results.iter())
+        .filter(|(_, result)| **result != results[agreed])
+        .map(|((worker, _), _)| worker.clone())
+        .collect();
+    Some((responses[agreed].1.clone(), dissenters))
 }
 
+/// Dispatched tasks without a response for this long are requeued.
+pub const TASK_TIMEOUT: Duration = Duration::from_secs(600);
+/// Upper bounds of the progress buckets scanning accounts are counted in.
+pub const PROGRESS_BUCKETS: [f64; 4] = [0.25, 0.5, 0.75, 1.0];
+/// Tasks running this many times longer than the median are dispatched to a second worker.
+pub const SPECULATIVE_FACTOR: f64 = 4.0;
+/// Tasks are never speculated on before running for this long.
+pub const SPECULATIVE_MIN_ELAPSED: Duration = Duration::from_secs(30);
+/// Number of recent task durations the median is taken from.
+pub const TASK_DURATION_SAMPLES: usize = 1000;
+/// Sweeps wait for room once this many tasks are queued.
+pub const MAX_QUEUED_TASKS: usize = 10000;
+/// Workers which disagree with the others get no tasks for this long.
+pub const QUARANTINE_DURATION: Duration = Duration::from_secs(24 * 3600);
+/// Accounts of the tasks a worker answered this recently are rescanned once it is quarantined.
+pub const RECENT_ANSWERS_WINDOW: Duration = Duration::from_secs(1800);
+/// Leases on scan sessions are renewed this often, well within `SCAN_LEASE_SECS`.
+pub const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(30);
+/// Most scan sessions an instance claims at once.
+pub const CLAIM_BATCH: i64 = 100;
+/// Longest wait for the workers to confirm they released the keys of an account.
+pub const KEY_RELEASE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait for the scanner leasing a removed account to release its keys, enough
+/// for a lease renewal and a key release.
+pub const REVOCATION_TIMEOUT: Duration = Duration::from_secs(45);
+/// Worker throughput is averaged over this window.
+pub const THROUGHPUT_WINDOW: Duration = Duration::from_secs(300);
+
+/// Scan progress of an account is persisted each time this many more of its blocks are scanned.
+pub const SCAN_CHECKPOINT_BLOCKS: u64 = 100;
+/// Interval between two rounds of need_scan accounts reconciliation.
+pub const RECONCILE_INTERVAL: Duration = Duration::from_secs(600);
+/// Scanner job lease held by the only instance reconciling need_scan accounts.
+pub const RECONCILER_LEASE: &str = "reconciler";
+/// Accounts resubmitted this many times without finishing are reported as stuck.
+pub const MAX_RECONCILE_ATTEMPTS: u32 = 3;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountInfo {
     pub start_block: BlockInfo,
     pub end_block: BlockInfo,
     pub remaining_task: u64,
+    // all blocks up to this sequence are scanned
+    pub scanned: u64,
+    // scanned blocks above the watermark
+    pub completed: BTreeSet<u64>,
+    // blocks scanned since progress was last persisted
+    #[serde(skip)]
+    pub unsaved: u64,
     pub in_vk: String,
     pub out_vk: String,
+    #[serde(default)]
+    pub options: ScanOptions,
     // mapping from block_hash to transaction list in this block
     pub blocks: HashMap<String, (i64, Vec<TransactionWithHash>)>,
+    // when the scan was scheduled or resumed here, with the tasks remaining then
+    #[serde(skip)]
+    pub since: Option<(Instant, u64)>,
+    // nothing is persisted, the result is kept in `Manager::dry_runs`
+    #[serde(default)]
+    pub dry_run: bool,
+    // tells a session from the ones restarted for the same account, sweeps of older ones stop
+    #[serde(skip)]
+    pub epoch: u64,
 }
 
+/// Epoch of the next account session, see `AccountInfo::epoch`.
+static NEXT_SESSION_EPOCH: AtomicU64 = AtomicU64::new(0);
+
 impl AccountInfo {
     pub fn new(
         start_block: BlockInfo,
         end_block: BlockInfo,
         in_vk: String,
         out_vk: String,
+        options: ScanOptions,
     ) -> Self {
         let remaining_task = end_block.sequence - start_block.sequence + 1;
+        let scanned = start_block.sequence.saturating_sub(1);
         Self {
             start_block,
             end_block,
             remaining_task,
+            scanned,
+            completed: BTreeSet::new(),
+            unsaved: 0,
             in_vk,
             out_vk,
+            options,
             blocks: HashMap::new(),
+            since: Some((Instant::now(), remaining_task)),
+            dry_run: false,
+            epoch: NEXT_SESSION_EPOCH.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Restore an account from a persisted scan session and the blocks it matched.
+    pub fn from_session(session: ScanSession, results: Vec<ScanResult>) -> Option<Self> {
+        let end_block = BlockInfo {
+            hash: session.end_hash?,
+            sequence: session.end_sequence? as u64,
+        };
+        let start_block = BlockInfo {
+            hash: session.start_hash,
+            sequence: session.start_sequence as u64,
+        };
+        let mut account = Self::new(
+            start_block,
+            end_block,
+            session.in_vk,
+            session.out_vk,
+            session.scan_options.0,
+        );
+        account.scanned = cmp::max(account.scanned, session.scanned_sequence as u64);
+        for (start, end) in session.completed_ranges.0 {
+            let start = cmp::max(start, account.scanned as i64 + 1) as u64;
+            let end = cmp::min(end, account.end_block.sequence as i64);
+            if end >= 0 {
+                for sequence in start..=end as u64 {
+                    account.complete(sequence);
+                }
+            }
+        }
+        account.remaining_task = account
+            .end_block
+            .sequence
+            .saturating_sub(account.scanned)
+            .saturating_sub(account.completed.len() as u64);
+        account.since = Some((Instant::now(), account.remaining_task));
+        for result in results {
+            account.blocks.insert(
+                result.hash,
+                (
+                    result.sequence,
+                    result
+                        .transactions
+                        .0
+                        .into_iter()
+                        .map(|hash| TransactionWithHash { hash })
+                        .collect(),
+                ),
+            );
         }
+        Some(account)
+    }
+
+    /// Time left at the pace kept since the scan was scheduled or resumed.
+    pub fn eta(&self) -> Option<Duration> {
+        let (since, remaining) = self.since?;
+        let done = remaining
+            .checked_sub(self.remaining_task)
+            .filter(|done| *done > 0)?;
+        Some(
+            since
+                .elapsed()
+                .mul_f64(self.remaining_task as f64 / done as f64),
+        )
+    }
+
+    pub fn progress(&self, address: &str) -> ScanProgress {
+        ScanProgress {
+            address: address.to_string(),
+            status: "scanning".into(),
+            start_sequence: self.start_block.sequence,
+            end_sequence: Some(self.end_block.sequence),
+            scanned_sequence: self.scanned,
+            total_task: self.end_block.sequence - self.start_block.sequence + 1,
+            remaining_task: self.remaining_task,
+            eta_seconds: self.eta().map(|eta| eta.as_secs()),
+        }
+    }
+
+    /// Mark a block scanned, returns false if it already was.
+    pub fn complete(&mut self, sequence: u64) -> bool {
+        if sequence <= self.scanned || !self.completed.insert(sequence) {
+            return false;
+        }
+        while self.completed.remove(&(self.scanned + 1)) {
+            self.scanned += 1;
+        }
+        true
+    }
+
+    /// Count the answer for a block, returns false for a block answered before, which must not
+    /// count twice.
+    pub fn scan_block(&mut self, sequence: u64) -> bool {
+        if !self.complete(sequence) {
+            return false;
+        }
+        self.remaining_task = self.remaining_task.saturating_sub(1);
+        self.unsaved += 1;
+        true
+    }
+
+    /// Blocks scanned above the watermark as inclusive ranges.
+    pub fn completed_ranges(&self) -> Vec<(i64, i64)> {
+        let mut ranges: Vec<(i64, i64)> = vec![];
+        for sequence in self.completed.iter().map(|sequence| *sequence as i64) {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == sequence => *end = sequence,
+                _ => ranges.push((sequence, sequence)),
+            }
+        }
+        ranges
     }
 }
 
@@ -105,14 +393,14 @@ unsafe impl Sync for SharedState {}
 impl SharedState {
     pub fn new(
         db_handler: Box<dyn Send + Sync + DBHandler>,
-        endpoint: &str,
+        endpoints: Vec<String>,
         server: &str,
         operator: String,
     ) -> Self {
         let operator = Signer::from_str(&operator).expect("Invalid secret key used");
         Self {
             db_handler: db_handler,
-            rpc_handler: RpcHandler::new(endpoint.into()),
+            rpc_handler: RpcHandler::new(endpoints),
             server_handler: ServerHandler::new(server.into()),
             operator,
         }
@@ -121,27 +409,328 @@ impl SharedState {
 
 pub struct Manager {
     pub workers: Arc<RwLock<HashMap<String, ServerWorker>>>,
-    pub task_queue: Arc<RwLock<PriorityQueue<DRequest, Reverse<i64>>>>,
+    // served by (round, sequence), see `scheduling_tasks`
+    pub task_queue: Arc<RwLock<PriorityQueue<DBatchRequest, Reverse<(u64, i64)>>>>,
+    // round of the last task handed to a worker
+    pub dispatch_round: Arc<AtomicU64>,
+    // recent durations in seconds from dispatch to response
+    pub task_durations: Arc<RwLock<VecDeque<f64>>>,
+    // woken when queued tasks are handed to workers
+    pub queue_space: Arc<Notify>,
     pub task_mapping: Arc<RwLock<HashMap<String, TaskInfo>>>,
+    // ids of the verified tasks each worker answered which still wait for another answer
+    pub answered: Arc<RwLock<HashMap<String, HashSet<String>>>>,
     pub account_mappling: Arc<RwLock<HashMap<String, AccountInfo>>>,
     pub shared: Arc<SharedState>,
     pub accounts_to_scan: Arc<RwLock<Vec<ScanRequest>>>,
+    // keys and scan options of the accounts being scanned, with the handle tasks refer to them by
+    pub view_keys: Arc<RwLock<HashMap<String, RegisterKeys>>>,
+    pub next_key_handle: Arc<AtomicU64>,
+    // times each need_scan account was resubmitted by the reconciler
+    pub reconcile_attempts: Arc<RwLock<HashMap<String, u32>>>,
     pub network: u8,
+    // whether workers return the decrypted notes, which are saved as `ScanNote`
+    pub note_details: bool,
+    // fraction of tasks answered by two workers, see `Manager::check_response`
+    pub verify_fraction: f64,
+    // workers which answered wrong and since when, they get no tasks for a while
+    pub quarantined: Arc<RwLock<HashMap<String, Instant>>>,
+    // accounts of the tasks each worker answered within `RECENT_ANSWERS_WINDOW`
+    pub recent_answers: Arc<RwLock<HashMap<String, VecDeque<(Instant, Vec<String>)>>>>,
+    // id of this scanner when scan sessions are leased from a db shared with other scanners
+    pub instance: Option<String>,
+    // results of finished dry run scans by account
+    pub dry_runs: Arc<RwLock<HashMap<String, ScanResponse>>>,
 }
 
 impl Manager {
-    pub fn new(shared: Arc<SharedState>, network: u8) -> Arc<Self> {
+    pub fn new(
+        shared: Arc<SharedState>,
+        network: u8,
+        note_details: bool,
+        verify_fraction: f64,
+        instance: Option<String>,
+    ) -> Arc<Self> {
         Arc::new(Self {
             workers: Arc::new(RwLock::new(HashMap::new())),
             task_queue: Arc::new(RwLock::new(PriorityQueue::new())),
+            dispatch_round: Arc::new(AtomicU64::new(0)),
+            task_durations: Arc::new(RwLock::new(VecDeque::new())),
+            queue_space: Arc::new(Notify::new()),
             task_mapping: Arc::new(RwLock::new(HashMap::new())),
+            answered: Arc::new(RwLock::new(HashMap::new())),
             account_mappling: Arc::new(RwLock::new(HashMap::new())),
             shared,
             accounts_to_scan: Arc::new(RwLock::new(vec![])),
+            view_keys: Arc::new(RwLock::new(HashMap::new())),
+            next_key_handle: Arc::new(AtomicU64::new(1)),
+            reconcile_attempts: Arc::new(RwLock::new(HashMap::new())),
             network: network,
+            note_details,
+            verify_fraction,
+            quarantined: Arc::new(RwLock::new(HashMap::new())),
+            recent_answers: Arc::new(RwLock::new(HashMap::new())),
+            instance,
+            dry_runs: Arc::new(RwLock::new(HashMap::new())),
         })
     }
 
+    /// Pop the next queued task `worker` may take, sweeps take turns by round. Verified tasks
+    /// skip the workers which already answered them, requeued tasks the worker still runs.
+    pub async fn next_task(&self, worker: &str) -> Option<DBatchRequest> {
+        let mut answered: HashSet<String> = self
+            .answered
+            .read()
+            .await
+            .get(worker)
+            .cloned()
+            .unwrap_or_default();
+        // a speculative copy must go to another worker than the slow one
+        answered.extend(
+            self.task_mapping
+                .read()
+                .await
+                .iter()
+                .filter(|(_, task)| task.worker.as_deref() == Some(worker))
+                .map(|(id, _)| id.clone()),
+        );
+        let next = {
+            let mut task_queue = self.task_queue.write().await;
+            let mut skipped = vec![];
+            let next = loop {
+                match task_queue.pop() {
+                    Some((task, priority)) if answered.contains(&task.id) => {
+                        skipped.push((task, priority))
+                    }
+                    next => break next,
+                }
+            };
+            for (task, priority) in skipped {
+                let _ = task_queue.push(task, priority);
+            }
+            next
+        };
+        let (task, Reverse((round, _))) = next?;
+        self.dispatch_round.fetch_max(round, Ordering::Relaxed);
+        self.queue_space.notify_waiters();
+        Some(task)
+    }
+
+    /// Wait until the queue has room for more tasks.
+    pub async fn wait_queue_space(&self) {
+        loop {
+            let notified = self.queue_space.notified();
+            if self.task_queue.read().await.len() < MAX_QUEUED_TASKS {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Hand queued tasks to the workers with the most free credits until either runs out,
+    /// quarantined workers get none.
+    pub async fn dispatch(&self) {
+        // workers left without a task they may take
+        let mut skipped = self.quarantined_workers().await;
+        loop {
+            let target = {
+                let mut workers = self.workers.write().await;
+                match workers
+                    .iter_mut()
+                    .filter(|(name, worker)| worker.credits() > 0 && !skipped.contains(*name))
+                    .max_by_key(|(_, worker)| worker.credits())
+                {
+                    Some((name, worker)) => {
+                        worker.in_flight += 1;
+                        (name.clone(), worker.router.clone())
+                    }
+                    None => return,
+                }
+            };
+            let (name, router) = target;
+            let task = match self.next_task(&name).await {
+                Some(task) => task,
+                None => {
+                    self.release_credit(&name).await;
+                    if self.task_queue.read().await.is_empty() {
+                        return;
+                    }
+                    let _ = skipped.insert(name);
+                    continue;
+                }
+            };
+            self.assign_task(&task.id, &name).await;
+            if let Err(e) = router.send(ServerMessage::Task(task)).await {
+                error!("failed to send message to worker {}, {}", name, e);
+                let _ = self.workers.write().await.remove(&name);
+                self.requeue_worker_tasks(&name).await;
+            }
+        }
+    }
+
+    /// Handle of the account keys, registered with each worker before its first task.
+    pub async fn key_handle(&self, account: &ScanRequest) -> AccountHandle {
+        let handle = self
+            .view_keys
+            .write()
+            .await
+            .entry(account.address.clone())
+            .or_insert_with(|| RegisterKeys {
+                handle: self.next_key_handle.fetch_add(1, Ordering::Relaxed),
+                keys: ViewKeys::from(account),
+                options: account.options.clone(),
+            })
+            .handle;
+        AccountHandle {
+            address: account.address.clone(),
+            handle,
+        }
+    }
+
+    /// Keys to register for `account`, none once its session ended.
+    pub async fn registered_keys(&self, account: &AccountHandle) -> Option<RegisterKeys> {
+        match self.view_keys.read().await.get(&account.address) {
+            Some(keys) if keys.handle == account.handle => Some(keys.clone()),
+            _ => None,
+        }
+    }
+
+    /// Forget the keys of an ended session and have workers zeroize their copy.
+    pub async fn release_keys(&self, address: &str) -> bool {
+        let handle = match self.view_keys.write().await.remove(address) {
+            Some(keys) => keys.handle,
+            None => return true,
+        };
+        let routers: Vec<Sender<ServerMessage>> = self
+            .workers
+            .read()
+            .await
+            .values()
+            .map(|worker| worker.router.clone())
+            .collect();
+        let mut confirmations = vec![];
+        for router in routers {
+            let (confirm, confirmation) = oneshot::channel();
+            if router
+                .send(ServerMessage::ReleaseKeys(vec![handle], confirm))
+                .await
+                .is_ok()
+            {
+                confirmations.push(confirmation);
+            }
+        }
+        let mut released = true;
+        for confirmation in confirmations {
+            // a closed connection took the keys registered with it along
+            released &= !matches!(
+                timeout(KEY_RELEASE_TIMEOUT, confirmation).await,
+                Err(_) | Ok(Ok(false))
+            );
+        }
+        released
+    }
+
+    async fn release_credit(&self, worker: &str) {
+        if let Some(worker) = self.workers.write().await.get_mut(worker) {
+            worker.in_flight = worker.in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Record `worker` as the owner of a dispatched task.
+    pub async fn assign_task(&self, task_id: &str, worker: &str) {
+        if let Some(task) = self.task_mapping.write().await.get_mut(task_id) {
+            task.worker = Some(worker.to_string());
+            task.dispatched.get_or_insert(Instant::now());
+        }
+    }
+
+    /// Queue a task again ahead of the sweeps in progress.
+    async fn queue_task(&self, request: DBatchRequest, sequence: i64) {
+        let round = self.dispatch_round.load(Ordering::Relaxed);
+        let _ = self
+            .task_queue
+            .write()
+            .await
+            .push(request, Reverse((round, sequence)));
+    }
+
+    /// Put the in-flight tasks of a gone worker back in the queue.
+    pub async fn requeue_worker_tasks(&self, worker: &str) {
+        let tasks: Vec<(DBatchRequest, i64)> = self
+            .task_mapping
+            .write()
+            .await
+            .values_mut()
+            .filter(|task| task.worker.as_deref() == Some(worker))
+            .map(|task| {
+                task.worker = None;
+                task.dispatched = None;
+                (task.request.clone(), task.sequence)
+            })
+            .collect();
+        if tasks.is_empty() {
+            return;
+        }
+        warn!("Worker {} left, requeue its {} tasks", worker, tasks.len());
+        counter!("scanner_tasks_requeued_total", "reason" => "disconnect")
+            .increment(tasks.len() as u64);
+        for (request, sequence) in tasks {
+            self.queue_task(request, sequence).await;
+        }
+        Box::pin(self.dispatch()).await;
+    }
+
+    /// Requeue tasks which timed out, and hand a copy of tasks running far slower than the
+    /// median to another worker. Whichever response comes first completes the task.
+    pub async fn requeue_slow_tasks(&self) {
+        let median = median(&*self.task_durations.read().await);
+        let mut timed_out = vec![];
+        let mut speculative = vec![];
+        let mut expired = vec![];
+        for task in self.task_mapping.write().await.values_mut() {
+            let elapsed = match task.dispatched {
+                Some(dispatched) => dispatched.elapsed(),
+                None => continue,
+            };
+            if elapsed >= TASK_TIMEOUT {
+                if let Some(worker) = task.worker.take() {
+                    expired.push((worker, task.request.id.clone()));
+                }
+                task.dispatched = None;
+                timed_out.push((task.request.clone(), task.sequence));
+            } else if !task.speculated
+                && elapsed >= SPECULATIVE_MIN_ELAPSED
+                && median.is_some_and(|median| elapsed.as_secs_f64() > median * SPECULATIVE_FACTOR)
+            {
+                task.speculated = true;
+                speculative.push((task.request.clone(), task.sequence));
+            }
+        }
+        if !timed_out.is_empty() {
+            info!("Requeue {} timed out tasks", timed_out.len());
+        }
+        // a worker which hangs but keeps beating would otherwise run out of credits
+        {
+            let mut workers = self.workers.write().await;
+            for (worker, task_id) in expired {
+                if let Some(worker) = workers.get_mut(&worker) {
+                    worker.expire(&task_id);
+                }
+            }
+        }
+        if !speculative.is_empty() {
+            info!("Speculatively dispatch {} slow tasks", speculative.len());
+        }
+        counter!("scanner_tasks_requeued_total", "reason" => "timeout")
+            .increment(timed_out.len() as u64);
+        counter!("scanner_tasks_requeued_total", "reason" => "speculative")
+            .increment(speculative.len() as u64);
+        for (request, sequence) in timed_out.into_iter().chain(speculative) {
+            self.queue_task(request, sequence).await;
+        }
+        self.dispatch().await;
+    }
+
     pub fn genesis_block(&self) -> BlockInfo {
         match self.network {
             Mainnet::ID => BlockInfo {
@@ -174,6 +763,623 @@ impl Manager {
         false
     }
 
+    /// Verify and queue a scan request, a pending session is saved so it survives restarts.
+    pub async fn submit_scan_request(&self, request: DecryptionMessage<ScanRequest>) -> bool {
+        let DecryptionMessage { message, signature } = request;
+        if let Ok(true) = self.shared.operator.verify(&message, signature) {
+            if self.should_skip_request(message.address.clone()).await {
+                return false;
+            }
+            if message.dry_run {
+                let _ = self.dry_runs.write().await.remove(&message.address);
+            }
+            self.queue_scan_request(message).await;
+            return true;
+        }
+        false
+    }
+
+    /// With a shared db the session is saved without owner and left to the scanner which
+    /// claims it first, a single scanner queues it right away.
+    async fn queue_scan_request(&self, request: ScanRequest) {
+        let head = request.head.clone().unwrap_or(self.genesis_block());
+        let session = ScanSession {
+            address: request.address.clone(),
+            in_vk: request.in_vk.clone(),
+            out_vk: request.out_vk.clone(),
+            start_hash: head.hash,
+            start_sequence: head.sequence as i64,
+            end_hash: None,
+            end_sequence: None,
+            scanned_sequence: head.sequence as i64 - 1,
+            scan_options: Json(request.options.clone()),
+            owner: None,
+            completed_ranges: Json::default(),
+        };
+        if !request.dry_run {
+            if let Err(e) = self.shared.db_handler.save_scan_session(session).await {
+                error!("Failed to save scan session {}, {}", request.address, e);
+            }
+            if self.instance.is_some() {
+                return;
+            }
+        }
+        self.accounts_to_scan.write().await.push(request);
+    }
+
+    /// Drop everything scanned in the `epoch` session of an account and scan it again from its
+    /// start, used when the blocks it was scanned against left the canonical chain.
+    pub async fn abort_session(&self, address: &str, epoch: u64) {
+        let account_info = {
+            let mut account_mappling = self.account_mappling.write().await;
+            match account_mappling.get(address) {
+                Some(account_info) if account_info.epoch == epoch => {
+                    account_mappling.remove(address)
+                }
+                _ => None,
+            }
+        };
+        if let Some(account_info) = account_info {
+            self.restart_session(address, account_info).await;
+        }
+    }
+
+    /// Drop a task which is done with from the answers of the workers which answered it.
+    async fn forget_answers(&self, task: &TaskInfo) {
+        let mut answered = self.answered.write().await;
+        for (worker, _) in task.responses.iter() {
+            if let Some(ids) = answered.get_mut(worker) {
+                ids.remove(&task.request.id);
+                if ids.is_empty() {
+                    let _ = answered.remove(worker);
+                }
+            }
+        }
+    }
+
+    /// Strip an account from the queued and running tasks and release its keys, returns
+    /// whether every worker confirmed the release.
+    async fn drop_session_tasks(&self, address: &str) -> bool {
+        let dropped: Vec<TaskInfo> = {
+            let mut task_mapping = self.task_mapping.write().await;
+            let emptied: Vec<String> = task_mapping
+                .iter_mut()
+                .filter_map(|(id, task)| {
+                    task.request
+                        .accounts
+                        .retain(|account| account.address != address);
+                    task.request.accounts.is_empty().then(|| id.clone())
+                })
+                .collect();
+            emptied
+                .iter()
+                .filter_map(|id| task_mapping.remove(id))
+                .collect()
+        };
+        for task in dropped.iter() {
+            self.forget_answers(task).await;
+        }
+        {
+            let mut task_queue = self.task_queue.write().await;
+            let queued: Vec<DBatchRequest> = task_queue
+                .iter()
+                .filter(|(task, _)| {
+                    task.accounts
+                        .iter()
+                        .any(|account| account.address == address)
+                })
+                .map(|(task, _)| task.clone())
+                .collect();
+            for task in queued {
+                if let Some((mut task, priority)) = task_queue.remove(&task) {
+                    task.accounts.retain(|account| account.address != address);
+                    if !task.accounts.is_empty() {
+                        let _ = task_queue.push(task, priority);
+                    }
+                }
+            }
+        }
+        self.release_keys(address).await
+    }
+
+    /// Stop scanning an account whose session is now leased by another scanner.
+    async fn forget_session(&self, address: &str) {
+        warn!("Lease on scan session of account {} was lost", address);
+        counter!("scanner_leases_lost_total").increment(1);
+        let _ = self.account_mappling.write().await.remove(address);
+        self.accounts_to_scan
+            .write()
+            .await
+            .retain(|account| account.address != address);
+        self.drop_session_tasks(address).await;
+    }
+
+    /// Progress of the scan of `address` queued or running here.
+    pub async fn scan_progress(&self, address: &str) -> Option<ScanProgress> {
+        if let Some(account) = self.account_mappling.read().await.get(address) {
+            return Some(account.progress(address));
+        }
+        self.accounts_to_scan
+            .read()
+            .await
+            .iter()
+            .find(|account| account.address == address)
+            .map(|account| self.queued_progress(account))
+    }
+
+    /// Progress of the scans queued or running here.
+    pub async fn scan_sessions(&self) -> Vec<ScanProgress> {
+        let mut sessions: Vec<ScanProgress> = self
+            .accounts_to_scan
+            .read()
+            .await
+            .iter()
+            .map(|account| self.queued_progress(account))
+            .collect();
+        sessions.extend(
+            self.account_mappling
+                .read()
+                .await
+                .iter()
+                .map(|(address, account)| account.progress(address)),
+        );
+        sessions
+    }
+
+    fn queued_progress(&self, request: &ScanRequest) -> ScanProgress {
+        let head = request.head.clone().unwrap_or(self.genesis_block());
+        ScanProgress {
+            address: request.address.clone(),
+            status: "queued".into(),
+            start_sequence: head.sequence,
+            end_sequence: None,
+            scanned_sequence: head.sequence.saturating_sub(1),
+            total_task: 0,
+            remaining_task: 0,
+            eta_seconds: None,
+        }
+    }
+
+    /// Progress of a dry run scan of `address`, with its result once finished.
+    pub async fn dry_run(&self, address: &str) -> DryRunResponse {
+        let progress = match self.account_mappling.read().await.get(address) {
+            Some(account) if account.dry_run => Some(account.progress(address)),
+            _ => None,
+        };
+        let progress = match progress {
+            Some(progress) => Some(progress),
+            None => self
+                .accounts_to_scan
+                .read()
+                .await
+                .iter()
+                .find(|account| account.address == address && account.dry_run)
+                .map(|account| self.queued_progress(account)),
+        };
+        DryRunResponse {
+            address: address.to_string(),
+            progress,
+            result: self.dry_runs.read().await.get(address).cloned(),
+        }
+    }
+
+    /// Connected workers with the tasks they answered per minute over `THROUGHPUT_WINDOW`.
+    pub async fn worker_statuses(&self) -> Vec<WorkerStatus> {
+        let quarantined = self.quarantined_workers().await;
+        let recent_answers = self.recent_answers.read().await;
+        let window = cmp::min(THROUGHPUT_WINDOW, RECENT_ANSWERS_WINDOW);
+        self.workers
+            .read()
+            .await
+            .iter()
+            .map(|(id, worker)| {
+                let answered = recent_answers.get(id).map_or(0, |answers| {
+                    answers
+                        .iter()
+                        .filter(|(since, _)| since.elapsed() <= window)
+                        .count()
+                });
+                WorkerStatus {
+                    id: id.clone(),
+                    name: worker.name.clone(),
+                    capacity: worker.capacity,
+                    in_flight: worker.in_flight,
+                    quarantined: quarantined.contains(id),
+                    tasks_per_minute: answered as f64 * 60.0 / window.as_secs_f64(),
+                }
+            })
+            .collect()
+    }
+
+    /// Forget an account being removed: its queued and running tasks, its dry run, its session
+    /// with the partial results and notes, and its keys. Returns whether all of it is gone,
+    /// including the keys registered with connected workers. With a shared db, the lease on
+    /// the session is revoked and the scanner holding it waited for until it released the keys.
+    pub async fn remove_account(&self, address: &str) -> bool {
+        info!("Remove account {} from scanner", address);
+        let _ = self.account_mappling.write().await.remove(address);
+        self.accounts_to_scan
+            .write()
+            .await
+            .retain(|account| account.address != address);
+        let _ = self.reconcile_attempts.write().await.remove(address);
+        let _ = self.dry_runs.write().await.remove(address);
+        let mut removed = self.drop_session_tasks(address).await;
+        if !self.revoke_session(address).await {
+            removed = false;
+        }
+        let db_handler = &self.shared.db_handler;
+        if let Err(e) = db_handler.remove_scan_session(address.to_string()).await {
+            error!(
+                "Failed to remove scan session of account {}, {}",
+                address, e
+            );
+            removed = false;
+        }
+        if let Err(e) = db_handler.remove_scan_notes(address.to_string(), -1).await {
+            error!("Failed to remove notes of account {}, {}", address, e);
+            removed = false;
+        }
+        if !removed {
+            counter!("scanner_account_removals_failed_total").increment(1);
+        }
+        removed
+    }
+
+    /// Revoke the lease on the session of `address` and wait for the peer scanner holding it
+    /// to release its keys, returns whether no other scanner holds them anymore.
+    async fn revoke_session(&self, address: &str) -> bool {
+        let started = Instant::now();
+        loop {
+            match self
+                .shared
+                .db_handler
+                .revoke_scan_lease(address.to_string())
+                .await
+            {
+                Ok(None) => return true,
+                Ok(owner) if owner == self.instance => return true,
+                Ok(Some(owner)) if started.elapsed() >= REVOCATION_TIMEOUT => {
+                    error!(
+                        "Scanner {} did not release the keys of account {} in time",
+                        owner, address
+                    );
+                    return false;
+                }
+                Ok(Some(_)) => sleep(Duration::from_secs(1)).await,
+                Err(e) => {
+                    error!(
+                        "Failed to revoke scan session of account {}, {}",
+                        address, e
+                    );
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Stop the scan of an account and drop its session, its node goes back to scanning
+    /// on its own from its current head. Returns whether a scan was running here.
+    pub async fn cancel_scan(&self, address: &str) -> bool {
+        let scanning = self
+            .account_mappling
+            .write()
+            .await
+            .remove(address)
+            .map(|account| account.dry_run);
+        let queued = {
+            let mut accounts_to_scan = self.accounts_to_scan.write().await;
+            let queued = accounts_to_scan
+                .iter()
+                .find(|account| account.address == address)
+                .map(|account| account.dry_run);
+            accounts_to_scan.retain(|account| account.address != address);
+            queued
+        };
+        let dry_run = match scanning.or(queued) {
+            Some(dry_run) => dry_run,
+            None => return false,
+        };
+        info!("Cancel scan of account {}", address);
+        self.drop_session_tasks(address).await;
+        // the node and the db were never touched by a dry run
+        if dry_run {
+            return true;
+        }
+        let db_handler = &self.shared.db_handler;
+        if let Err(e) = db_handler.remove_scan_session(address.to_string()).await {
+            error!(
+                "Failed to remove scan session of account {}, {}",
+                address, e
+            );
+        }
+        if let Ok(account) = db_handler.get_account(address.to_string()).await {
+            let resumed = match self.shared.rpc_handler.wallet(account.worker_id as u32) {
+                Ok(node) => node
+                    .set_scanning(RpcSetScanningRequest {
+                        account: account.name.clone(),
+                        enabled: true,
+                    })
+                    .await
+                    .is_ok(),
+                Err(_) => false,
+            };
+            if !resumed {
+                error!("Failed to resume node scanning of account {}", address);
+            }
+            if let Err(e) = db_handler
+                .update_scan_status(address.to_string(), false)
+                .await
+            {
+                error!("Failed to clear need_scan of account {}, {}", address, e);
+            }
+        }
+        true
+    }
+
+    /// Unfinished scan sessions to run here, either all of them or the ones claimed from
+    /// scanners whose leases expired. Nothing is claimed without workers to scan them.
+    pub async fn load_scan_sessions(&self) -> Result<Vec<ScanSession>> {
+        let db_handler = &self.shared.db_handler;
+        let sessions = match &self.instance {
+            Some(_) if self.workers.read().await.is_empty() => vec![],
+            Some(owner) => {
+                db_handler
+                    .claim_scan_sessions(owner.clone(), CLAIM_BATCH)
+                    .await?
+            }
+            None => db_handler.get_scan_sessions().await?,
+        };
+        Ok(sessions)
+    }
+
+    /// Renew the leases on the sessions scanned here, the ones taken over meanwhile are dropped.
+    pub async fn renew_leases(&self) -> Result<()> {
+        let owner = match &self.instance {
+            Some(owner) => owner.clone(),
+            None => return Ok(()),
+        };
+        // dry runs have no session to lease
+        let mut local: Vec<String> = self
+            .account_mappling
+            .read()
+            .await
+            .iter()
+            .filter(|(_, account)| !account.dry_run)
+            .map(|(address, _)| address.clone())
+            .collect();
+        local.extend(
+            self.accounts_to_scan
+                .read()
+                .await
+                .iter()
+                .filter(|account| !account.dry_run)
+                .map(|account| account.address.clone()),
+        );
+        let held: HashSet<String> = self
+            .shared
+            .db_handler
+            .renew_scan_leases(owner.clone(), local.clone())
+            .await?
+            .into_iter()
+            .collect();
+        let lost: Vec<String> = local
+            .into_iter()
+            .filter(|address| !held.contains(address))
+            .collect();
+        for address in lost.iter() {
+            self.forget_session(address).await;
+        }
+        // the keys are released now, which the scanner removing a revoked session waits for
+        if !lost.is_empty() {
+            self.shared
+                .db_handler
+                .release_scan_leases(owner, lost)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn restart_session(&self, address: &str, account_info: AccountInfo) {
+        warn!(
+            "Restarting scan of account {} from block {}",
+            address, account_info.start_block.sequence
+        );
+        counter!("scanner_sessions_restarted_total").increment(1);
+        self.drop_session_tasks(address).await;
+        if account_info.dry_run {
+            self.queue_scan_request(ScanRequest {
+                address: address.to_string(),
+                in_vk: account_info.in_vk,
+                out_vk: account_info.out_vk,
+                head: Some(account_info.start_block),
+                options: account_info.options,
+                dry_run: true,
+            })
+            .await;
+            return;
+        }
+        if let Err(e) = self
+            .shared
+            .db_handler
+            .remove_scan_session(address.to_string())
+            .await
+        {
+            error!("Failed to reset scan session of account {}, {}", address, e);
+        }
+        // notes of the scanned blocks may be orphaned, they are indexed again by the rescan
+        if self.note_details {
+            if let Err(e) = self
+                .shared
+                .db_handler
+                .remove_scan_notes(
+                    address.to_string(),
+                    account_info.start_block.sequence as i64 - 1,
+                )
+                .await
+            {
+                error!("Failed to reset notes of account {}, {}", address, e);
+            }
+        }
+        self.queue_scan_request(ScanRequest {
+            address: address.to_string(),
+            in_vk: account_info.in_vk,
+            out_vk: account_info.out_vk,
+            head: Some(account_info.start_block),
+            options: account_info.options,
+            dry_run: false,
+        })
+        .await;
+    }
+
+    /// Whether the end block and every matched block are still on the canonical chain.
+    async fn is_canonical(&self, account_info: &AccountInfo) -> Result<bool> {
+        let mut blocks: Vec<(i64, &String)> = account_info
+            .blocks
+            .iter()
+            .map(|(hash, (sequence, _))| (*sequence, hash))
+            .collect();
+        blocks.push((
+            account_info.end_block.sequence as i64,
+            &account_info.end_block.hash,
+        ));
+        for (sequence, hash) in blocks {
+            let block = self
+                .shared
+                .rpc_handler
+                .get_block(sequence)
+                .await?
+                .data
+                .block;
+            if !block.hash.eq_ignore_ascii_case(hash) {
+                warn!("Block {} {} was reorged out", sequence, hash);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Head of the account in its node wallet, where a new scan should start from.
+    pub async fn account_head(&self, account: &Account) -> Result<BlockInfo> {
+        let node = self.shared.rpc_handler.wallet(account.worker_id as u32)?;
+        let status = node
+            .get_account_status(RpcGetAccountStatusRequest {
+                account: account.name.clone(),
+            })
+            .await?;
+        Ok(status.data.account.head.unwrap_or(self.genesis_block()))
+    }
+
+    /// Resubmit `need_scan` accounts which have no scan session, e.g. lost by a crash
+    /// before sessions were persisted, and report the ones which keep coming back.
+    pub async fn reconcile_accounts(&self) -> Result<()> {
+        if let Some(owner) = &self.instance {
+            let seconds = 2 * RECONCILE_INTERVAL.as_secs() as i64;
+            if !self
+                .shared
+                .db_handler
+                .claim_scanner_lease(RECONCILER_LEASE.to_string(), owner.clone(), seconds)
+                .await?
+            {
+                debug!("Accounts are reconciled by another scanner");
+                return Ok(());
+            }
+        }
+        let accounts = self.shared.db_handler.get_scan_accounts().await?;
+        let sessions: HashSet<String> = self
+            .shared
+            .db_handler
+            .get_scan_sessions()
+            .await?
+            .into_iter()
+            .map(|session| session.address)
+            .collect();
+        {
+            let need_scan: HashSet<&String> =
+                accounts.iter().map(|account| &account.address).collect();
+            self.reconcile_attempts
+                .write()
+                .await
+                .retain(|address, _| need_scan.contains(address));
+        }
+        let mut stuck = 0;
+        for account in accounts {
+            if sessions.contains(&account.address)
+                || self.should_skip_request(account.address.clone()).await
+            {
+                continue;
+            }
+            let attempts = self
+                .reconcile_attempts
+                .read()
+                .await
+                .get(&account.address)
+                .cloned()
+                .unwrap_or(0);
+            if attempts >= MAX_RECONCILE_ATTEMPTS {
+                error!(
+                    "Account {} still needs scan after {} resubmissions",
+                    account.address, attempts
+                );
+                stuck += 1;
+                continue;
+            }
+            let _ = self
+                .reconcile_attempts
+                .write()
+                .await
+                .insert(account.address.clone(), attempts + 1);
+            let head = match self.account_head(&account).await {
+                Ok(head) => head,
+                Err(e) => {
+                    warn!("Failed to get head of account {}, {}", account.address, e);
+                    continue;
+                }
+            };
+            info!(
+                "Resubmit lost scan of account {} from {}",
+                account.address, head.sequence
+            );
+            let scan_request = ScanRequest {
+                address: account.address.clone(),
+                in_vk: account.in_vk.clone(),
+                out_vk: account.out_vk.clone(),
+                head: Some(head),
+                options: account.scan_options.0.clone(),
+                dry_run: false,
+            };
+            let signature = self
+                .shared
+                .operator
+                .sign(&scan_request)
+                .unwrap_or("default_signature, should never happen".into());
+            let _ = self
+                .submit_scan_request(DecryptionMessage {
+                    message: scan_request,
+                    signature,
+                })
+                .await;
+        }
+        gauge!("scanner_stuck_accounts").set(stuck as f64);
+        Ok(())
+    }
+
+    pub async fn initialize_reconciler(server: Arc<Self>) -> Result<()> {
+        let (router, handler) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = router.send(());
+            loop {
+                if let Err(e) = server.reconcile_accounts().await {
+                    error!("Failed to reconcile need_scan accounts, {}", e);
+                }
+                sleep(RECONCILE_INTERVAL).await;
+            }
+        });
+        let _ = handler.await;
+        info!("Reconciler installed!");
+        Ok(())
+    }
+
     pub async fn initialize_status_updater(server: Arc<Self>) -> Result<()> {
         let (router, handler) = oneshot::channel();
         tokio::spawn(async move {
@@ -206,7 +1412,78 @@ impl Manager {
         Ok(())
     }
 
-    pub async fn initialize_networking(server: Arc<Self>, addr: SocketAddr) -> Result<()> {
+    pub async fn initialize_metrics_updater(server: Arc<Self>) -> Result<()> {
+        let (router, handler) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = router.send(());
+            loop {
+                {
+                    let workers = server.workers.read().await;
+                    let busy = workers
+                        .values()
+                        .filter(|worker| worker.credits() == 0)
+                        .count();
+                    let capacity: usize = workers.values().map(|worker| worker.capacity).sum();
+                    gauge!("scanner_workers").set(workers.len() as f64);
+                    gauge!("scanner_busy_workers").set(busy as f64);
+                    gauge!("scanner_worker_capacity").set(capacity as f64);
+                }
+                gauge!("scanner_task_queue_depth").set(server.task_queue.read().await.len() as f64);
+                {
+                    let tasks = server.task_mapping.read().await;
+                    let oldest = tasks
+                        .values()
+                        .map(|task| task.since.elapsed().as_secs_f64())
+                        .fold(0.0, f64::max);
+                    gauge!("scanner_inflight_tasks").set(tasks.len() as f64);
+                    gauge!("scanner_oldest_task_seconds").set(oldest);
+                }
+                gauge!("scanner_pending_accounts")
+                    .set(server.accounts_to_scan.read().await.len() as f64);
+                {
+                    let accounts = server.account_mappling.read().await;
+                    gauge!("scanner_scanning_accounts").set(accounts.len() as f64);
+                    // aggregates only, progress of single accounts is served by /scanStatus
+                    let mut remaining = 0;
+                    let mut progress = [0usize; PROGRESS_BUCKETS.len()];
+                    for account in accounts.values() {
+                        let total = account.end_block.sequence - account.start_block.sequence + 1;
+                        let done = 1.0 - account.remaining_task as f64 / total as f64;
+                        remaining += account.remaining_task;
+                        let bucket = PROGRESS_BUCKETS
+                            .iter()
+                            .position(|upper| done <= *upper)
+                            .unwrap_or(PROGRESS_BUCKETS.len() - 1);
+                        progress[bucket] += 1;
+                    }
+                    gauge!("scanner_remaining_blocks").set(remaining as f64);
+                    for (upper, count) in PROGRESS_BUCKETS.iter().zip(progress) {
+                        gauge!("scanner_scanning_accounts_by_progress", "le" => upper.to_string())
+                            .set(count as f64);
+                    }
+                }
+                sleep(Duration::from_secs(10)).await;
+            }
+        });
+        let _ = handler.await;
+        info!("Metrics updater installed!");
+        Ok(())
+    }
+
+    pub async fn initialize_networking(
+        server: Arc<Self>,
+        addr: SocketAddr,
+        transport: TransportConfig,
+    ) -> Result<()> {
+        match &transport.noise {
+            Some(noise) => info!(
+                "worker transport encrypted, scanner key {}, {} workers allowed",
+                noise.public_key,
+                noise.peers.len()
+            ),
+            None => warn!("worker transport is neither encrypted nor authenticated"),
+        }
+        let transport = Arc::new(transport);
         let (router, handler) = oneshot::channel();
         let listener = TcpListener::bind(&addr).await?;
         tokio::spawn(async move {
@@ -215,7 +1492,16 @@ impl Manager {
                 match listener.accept().await {
                     Ok((stream, ip)) => {
                         debug!("new connection from {}", ip);
-                        let _ = Self::handle_stream(stream, server.clone(), ip.to_string()).await;
+                        // the handshake waits on the peer, don't hold up the next accept
+                        let server = server.clone();
+                        let transport = transport.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) =
+                                Self::handle_stream(stream, server, ip.to_string(), transport).await
+                            {
+                                warn!("failed to set up connection from {}, {}", ip, e);
+                            }
+                        });
                     }
                     Err(e) => error!("failed to accept connection, {:?}", e),
                 }
@@ -225,34 +1511,103 @@ impl Manager {
         Ok(())
     }
 
-    pub async fn handle_stream(stream: TcpStream, server: Arc<Self>, worker: String) -> Result<()> {
+    pub async fn handle_stream(
+        mut stream: TcpStream,
+        server: Arc<Self>,
+        worker: String,
+        transport: Arc<TransportConfig>,
+    ) -> Result<()> {
+        // rejects unauthenticated workers before they are registered or sent any task
+        let (reader, writer, key) = transport.accept(&mut stream).await?;
+        if let Some(key) = &key {
+            info!("worker {} authenticated with key {}", worker, key);
+        }
+        // workers predating the handshake only take one account per request
+        let legacy = reader.encoding() == Encoding::Json;
+        let batches = Arc::new(RwLock::new(LegacyBatches::default()));
+        let (gathered_tx, mut gathered_rx) = mpsc::channel::<DBatchResponse>(1024);
         let (tx, mut rx) = mpsc::channel::<ServerMessage>(1024);
-        let mut worker_name = worker;
+        // credits and quarantine follow the authenticated key, or the connection without one,
+        // never the name a worker reports about itself
+        let identity = key.unwrap_or(worker);
+        // name the worker registered with on this connection
+        let mut worker_name: Option<String> = None;
         let (r, w) = split(stream);
-        let mut outbound_w = FramedWrite::new(w, DMessageCodec::default());
-        let mut outbound_r = FramedRead::new(r, DMessageCodec::default());
+        let mut outbound_w = FramedWrite::new(w, writer);
+        let mut outbound_r = FramedRead::new(r, reader);
 
-        let worker_server = server.clone();
         let (router, handler) = oneshot::channel();
+        let key_server = server.clone();
+        let legacy_batches = batches.clone();
         tokio::spawn(async move {
             let _ = router.send(());
+            // handles whose keys this worker holds
+            let mut registered = HashSet::new();
             while let Some(message) = rx.recv().await {
-                let ServerMessage { name, request } = message;
-                match name {
-                    Some(name) => {
-                        let _ = worker_server
-                            .workers
-                            .write()
-                            .await
-                            .get_mut(&name)
-                            .unwrap()
-                            .status = 2;
+                let mut messages = vec![];
+                let mut confirmation = None;
+                match message {
+                    ServerMessage::Task(request) if legacy => {
+                        let mut keys = vec![];
+                        for account in request.accounts.iter() {
+                            if let Some(account_keys) = key_server.registered_keys(account).await {
+                                keys.push(account_keys);
+                            }
+                        }
+                        let requests = legacy_batches.write().await.split(&request, &keys);
+                        debug!(
+                            "send task {} to legacy worker as {} requests",
+                            request.id,
+                            requests.len()
+                        );
+                        // keys of every session are gone, answer the task right away
+                        if requests.is_empty() {
+                            let _ = gathered_tx.send(DBatchResponse::new(request.id)).await;
+                        }
+                        messages.extend(requests.into_iter().map(DMessage::DRequest));
+                    }
+                    ServerMessage::Task(request) => {
+                        for account in request.accounts.iter() {
+                            if registered.contains(&account.handle) {
+                                continue;
+                            }
+                            // keys of an ended session are gone, the worker skips the account
+                            if let Some(keys) = key_server.registered_keys(account).await {
+                                let _ = registered.insert(account.handle);
+                                messages.push(DMessage::RegisterKeys(keys));
+                            }
+                        }
+                        debug!("send task {} to worker", request.id);
+                        messages.push(DMessage::DBatchRequest(request));
+                    }
+                    ServerMessage::ReleaseKeys(handles, confirm) => {
+                        let handles: Vec<u64> = handles
+                            .into_iter()
+                            .filter(|handle| registered.remove(handle))
+                            .collect();
+                        if !handles.is_empty() {
+                            messages.push(DMessage::ReleaseKeys(ReleaseKeys { handles }));
+                        }
+                        confirmation = Some(confirm);
                     }
-                    None => {}
                 }
-                let send_future = outbound_w.send(DMessage::DRequest(request));
-                if let Err(error) = timeout(Duration::from_secs(3), send_future).await {
-                    error!("send message to worker timeout: {}", error);
+                let mut written = true;
+                for message in messages {
+                    let send_future = outbound_w.send(message);
+                    match timeout(Duration::from_secs(3), send_future).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(error)) => {
+                            error!("send message to worker failed: {}", error);
+                            written = false;
+                        }
+                        Err(error) => {
+                            error!("send message to worker timeout: {}", error);
+                            written = false;
+                        }
+                    }
+                }
+                if let Some(confirm) = confirmation {
+                    let _ = confirm.send(written);
                 }
             }
         });
@@ -267,93 +1622,339 @@ impl Manager {
             loop {
                 tokio::select! {
                     _ = timer.tick() => {
-                        debug!("no message from worker {} for 5 mins, exit", worker_name);
-                        let _ = worker_server.workers.write().await.remove(&worker_name);
+                        debug!("no message from worker {} for 5 mins, exit", identity);
                         break;
                     },
+                    Some(response) = gathered_rx.recv() => {
+                        if worker_name.is_some() {
+                            worker_server.handle_response(&identity, response).await;
+                        }
+                    },
                     result = outbound_r.next() => {
-                        debug!("new message from outboud_reader {:?} of worker {}", result, worker_name);
+                        debug!("new message from outboud_reader {:?} of worker {}", result, identity);
                         match result {
                             Some(Ok(message)) => {
                                 timer.reset();
                                 match message {
                                     DMessage::RegisterWorker(register) => {
                                         debug!("heart beat info {:?}", register);
-                                        match worker_name == register.name {
-                                            true => {},
-                                            false => {
-                                                let worker = ServerWorker::new(tx.clone());
-                                                worker_name = register.name;
-                                                info!("new worker: {}", worker_name.clone());
-                                                let _ = worker_server.workers.write().await.insert(worker_name.clone(), worker);
-                                                let data = worker_server.task_queue.write().await.pop();
-                                                match data {
-                                                    Some((task, _)) => {
-                                                        let _ = tx.send(ServerMessage { name: Some(worker_name.clone()), request: task }).await.unwrap();
-                                                    },
-                                                    None => {},
+                                        match worker_name.clone() {
+                                            Some(name) if name == register.name => {},
+                                            Some(name) => {
+                                                error!("worker {} can't rename itself from {} to {}", identity, name, register.name);
+                                                break;
+                                            },
+                                            None => {
+                                                let mut workers = worker_server.workers.write().await;
+                                                if workers.contains_key(&identity) {
+                                                    error!("worker {} is connected already", identity);
+                                                    break;
                                                 }
+                                                let worker = ServerWorker::new(tx.clone(), register.name.clone(), register.capacity);
+                                                info!("new worker: {} {} with capacity {}", register.name, identity, worker.capacity);
+                                                let _ = workers.insert(identity.clone(), worker);
+                                                drop(workers);
+                                                worker_name = Some(register.name);
+                                                worker_server.dispatch().await;
                                             }
                                         }
                                     },
-                                    DMessage::DRequest(_) => {
+                                    DMessage::DResponse(response) if legacy => {
+                                        debug!("new response of {} from legacy worker {}", response.address, response.id);
+                                        let gathered = batches.write().await.gather(response);
+                                        if let (Some(response), Some(_)) = (gathered, &worker_name) {
+                                            worker_server.handle_response(&identity, response).await;
+                                        }
+                                    },
+                                    DMessage::DRequest(_) | DMessage::DResponse(_) | DMessage::DBatchRequest(_)
+                                    | DMessage::RegisterKeys(_) | DMessage::ReleaseKeys(_) => {
                                         error!("invalid message from worker, should never happen");
-                                        let _ = worker_server.workers.write().await.remove(&worker_name);
                                         break;
                                     },
-                                    DMessage::DResponse(response) => {
+                                    DMessage::DBatchResponse(response) => {
                                         debug!("new response from worker {}", response.id);
-                                        let data = worker_server.task_queue.write().await.pop();
-                                        match data {
-                                            Some((task, _)) => {
-                                                let _ = tx.send(ServerMessage { name: None, request: task }).await.unwrap();
-                                            },
-                                            None => worker_server.workers.write().await.get_mut(&worker_name).unwrap().status = 1,
+                                        if worker_name.is_some() {
+                                            worker_server.handle_response(&identity, response).await;
                                         }
-                                        let _ = worker_server.update_account(response).await;
                                     },
                                 }
                             },
                             _ => {
                                 warn!("unknown message");
-                                let _ = worker_server.workers.write().await.remove(&worker_name);
                                 break;
                             },
                         }
                     }
                 }
             }
-            error!("worker {} main loop exit", worker_name);
+            error!("worker {} main loop exit", identity);
+            // a connection turned down at registration leaves the registered one alone
+            if worker_name.is_some() {
+                let _ = worker_server.workers.write().await.remove(&identity);
+                worker_server.requeue_worker_tasks(&identity).await;
+            }
         });
         let _ = handler.await;
         Ok(())
     }
 
-    pub async fn update_account(&self, response: DResponse) -> Result<()> {
-        let address = response.address.clone();
-        let task_id = response.id.clone();
+    /// Free the credit of the task answered by `worker` and apply its response.
+    async fn handle_response(&self, worker: &str, response: DBatchResponse) {
+        counter!("scanner_task_responses_total").increment(1);
+        if let Some(worker) = self.workers.write().await.get_mut(worker) {
+            worker.settle(&response.id);
+        }
+        self.dispatch().await;
+        let _ = self.update_account(worker, response).await;
+    }
+
+    /// Workers which are quarantined at the moment.
+    pub async fn quarantined_workers(&self) -> HashSet<String> {
+        self.quarantined
+            .read()
+            .await
+            .iter()
+            .filter(|(_, since)| since.elapsed() < QUARANTINE_DURATION)
+            .map(|(worker, _)| worker.clone())
+            .collect()
+    }
+
+    /// Stop handing tasks to `worker` and rescan the accounts of the tasks it answered lately.
+    pub async fn quarantine(&self, worker: &str) {
+        error!(
+            "Quarantine worker {} which disagreed with other workers",
+            worker
+        );
+        counter!("scanner_workers_quarantined_total").increment(1);
+        let _ = self
+            .quarantined
+            .write()
+            .await
+            .insert(worker.to_string(), Instant::now());
+        let recent: HashSet<String> = self
+            .recent_answers
+            .write()
+            .await
+            .remove(worker)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(_, addresses)| addresses)
+            .collect();
+        self.requeue_worker_tasks(worker).await;
+        for address in recent {
+            let account_info = match self.account_mappling.write().await.remove(&address) {
+                Some(account_info) => account_info,
+                None => continue,
+            };
+            self.restart_session(&address, account_info).await;
+        }
+    }
+
+    async fn record_answer(&self, worker: &str, task_id: &str) {
+        let addresses: Vec<String> = match self.task_mapping.read().await.get(task_id) {
+            Some(task) => task.addresses().cloned().collect(),
+            None => return,
+        };
+        let mut recent_answers = self.recent_answers.write().await;
+        let answers = recent_answers.entry(worker.to_string()).or_default();
+        answers.push_back((Instant::now(), addresses));
+        while answers
+            .front()
+            .is_some_and(|(since, _)| since.elapsed() > RECENT_ANSWERS_WINDOW)
+        {
+            let _ = answers.pop_front();
+        }
+    }
+
+    /// Responses of verified tasks are held back until two workers agree on one, the task
+    /// goes to another worker meanwhile. Returns the response to use, if any, with the
+    /// workers which answered something else.
+    async fn check_response(
+        &self,
+        worker: &str,
+        response: DBatchResponse,
+    ) -> Option<(DBatchResponse, Vec<String>)> {
+        let quarantined = self.quarantined_workers().await;
+        let workers: Vec<String> = self.workers.read().await.keys().cloned().collect();
+        let (outcome, requeue) = {
+            let mut task_mapping = self.task_mapping.write().await;
+            let task = match task_mapping.get_mut(&response.id) {
+                Some(task) if task.verify => task,
+                _ => return Some((response, vec![])),
+            };
+            if task.answered_by(worker) {
+                return None;
+            }
+            task.responses.push((worker.to_string(), response));
+            let addresses: BTreeSet<String> = task.addresses().cloned().collect();
+            let others = workers
+                .iter()
+                .any(|other| !quarantined.contains(other) && !task.answered_by(other));
+            match agreed_response(&task.responses, &addresses) {
+                Some(outcome) => (Some(outcome), None),
+                // nobody to check with, the only answer is taken as is
+                None if task.responses.len() == 1 && !others => {
+                    warn!("No other worker to verify task {} with", task.request.id);
+                    counter!("scanner_tasks_unverified_total").increment(1);
+                    (
+                        task.responses.pop().map(|(_, response)| (response, vec![])),
+                        None,
+                    )
+                }
+                None => {
+                    if !others {
+                        warn!(
+                            "Workers disagree on task {}, waiting for another one",
+                            task.request.id
+                        );
+                    }
+                    task.worker = None;
+                    task.dispatched = None;
+                    let _ = self
+                        .answered
+                        .write()
+                        .await
+                        .entry(worker.to_string())
+                        .or_default()
+                        .insert(task.request.id.clone());
+                    (None, Some((task.request.clone(), task.sequence)))
+                }
+            }
+        };
+        if let Some((request, sequence)) = requeue {
+            self.queue_task(request, sequence).await;
+            self.dispatch().await;
+        }
+        outcome
+    }
+
+    pub async fn update_account(&self, worker: &str, response: DBatchResponse) -> Result<()> {
+        self.record_answer(worker, &response.id).await;
+        let (response, dissenters) = match self.check_response(worker, response).await {
+            Some(outcome) => outcome,
+            None => return Ok(()),
+        };
+        self.apply_response(response).await?;
+        for dissenter in dissenters {
+            self.quarantine(&dissenter).await;
+        }
+        Ok(())
+    }
+
+    async fn apply_response(&self, response: DBatchResponse) -> Result<()> {
+        let DBatchResponse {
+            id,
+            mut data,
+            mut notes,
+        } = response;
+        let maybe_task = self.task_mapping.write().await.remove(&id);
+        match maybe_task {
+            Some(task_info) => {
+                self.forget_answers(&task_info).await;
+                // drop a requeued or speculative copy still waiting for a worker
+                let _ = self.task_queue.write().await.remove(&task_info.request);
+                if let Some(dispatched) = task_info.dispatched {
+                    let mut durations = self.task_durations.write().await;
+                    durations.push_back(dispatched.elapsed().as_secs_f64());
+                    if durations.len() > TASK_DURATION_SAMPLES {
+                        let _ = durations.pop_front();
+                    }
+                }
+                for address in task_info.addresses() {
+                    let matched = data.remove(address).unwrap_or_default();
+                    if let Some(notes) = notes.remove(address) {
+                        self.save_notes(address, &task_info, notes).await;
+                    }
+                    self.update_account_block(address.clone(), &task_info, matched)
+                        .await;
+                }
+            }
+            None => debug!("response of unknown or rescheduled task {}", id),
+        }
+        Ok(())
+    }
+
+    /// Index the notes decrypted for `address` from the block of the task.
+    async fn save_notes(&self, address: &str, task_info: &TaskInfo, notes: Vec<DecryptedNote>) {
+        match self.account_mappling.read().await.get(address) {
+            Some(account) if !account.dry_run => {}
+            _ => return,
+        }
+        let notes = notes
+            .into_iter()
+            .filter_map(|note| {
+                // custom assets may mint values a BIGINT column can't hold
+                let value = match i64::try_from(note.value) {
+                    Ok(value) => value,
+                    Err(_) => {
+                        error!(
+                            "Note {} of tx {} for account {} has out of range value {}",
+                            note.index, note.tx_hash, address, note.value
+                        );
+                        return None;
+                    }
+                };
+                Some(ScanNote {
+                    address: address.to_string(),
+                    tx_hash: note.tx_hash,
+                    note_index: note.index as i32,
+                    direction: note.direction.as_str().to_string(),
+                    block_hash: task_info.hash.clone(),
+                    sequence: task_info.sequence,
+                    value,
+                    asset_id: note.asset_id,
+                    memo: note.memo,
+                    sender: note.sender,
+                })
+            })
+            .collect();
+        if let Err(e) = self.shared.db_handler.save_scan_notes(notes).await {
+            error!("Failed to save notes for account {}, {}", address, e);
+        }
+    }
+
+    async fn update_account_block(&self, address: String, task_info: &TaskInfo, data: Vec<String>) {
         let mut update_account = false;
+        let mut checkpoint = None;
+        let persisted = self
+            .account_mappling
+            .read()
+            .await
+            .get(&address)
+            .is_some_and(|account| !account.dry_run);
+        // Matched blocks must be persisted before the watermark can move past them
+        if !data.is_empty() && persisted {
+            let result = ScanResult {
+                address: address.clone(),
+                hash: task_info.hash.clone(),
+                sequence: task_info.sequence,
+                transactions: Json(data.clone()),
+            };
+            if let Err(e) = self.shared.db_handler.save_scan_result(result).await {
+                error!("Failed to save scan result for account {}, {}", address, e);
+            }
+        }
         match self.account_mappling.write().await.get_mut(&address) {
             Some(account) => {
-                let maybe_task = self.task_mapping.read().await.get(&task_id).cloned();
-                if let Some(task_info) = maybe_task {
-                    let block_hash = task_info.hash.clone();
-                    if !response.data.is_empty() {
-                        debug!("account info: {:?}", account);
-                        info!("new available block {} for account {}", block_hash, address);
-                        account.blocks.insert(
-                            block_hash.clone(),
-                            (
-                                task_info.sequence,
-                                response
-                                    .data
-                                    .into_iter()
-                                    .map(|hash| TransactionWithHash { hash })
-                                    .collect(),
-                            ),
-                        );
+                let block_hash = task_info.hash.clone();
+                if !data.is_empty() {
+                    debug!("account info: {:?}", account);
+                    info!("new available block {} for account {}", block_hash, address);
+                    account.blocks.insert(
+                        block_hash.clone(),
+                        (
+                            task_info.sequence,
+                            data.into_iter()
+                                .map(|hash| TransactionWithHash { hash })
+                                .collect(),
+                        ),
+                    );
+                }
+                if account.scan_block(task_info.sequence as u64) {
+                    if persisted && account.unsaved >= SCAN_CHECKPOINT_BLOCKS {
+                        account.unsaved = 0;
+                        checkpoint = Some((account.scanned, account.completed_ranges()));
                     }
-                    account.remaining_task -= 1;
                     if account.remaining_task == 0 {
                         update_account = true;
                     }
@@ -363,59 +1964,166 @@ impl Manager {
                 error!("bad response whose request account doesn't exist, should never happen")
             }
         }
+        if let Some((scanned, ranges)) = checkpoint {
+            if let Err(e) = self
+                .shared
+                .db_handler
+                .update_scan_watermark(address.clone(), scanned as i64, ranges)
+                .await
+            {
+                error!("Failed to checkpoint account {}, {}", address, e);
+            }
+        }
         if update_account {
-            let account_info = self
-                .account_mappling
-                .read()
+            self.finish_account(address).await;
+        }
+    }
+
+    /// Finish the accounts whose blocks are all scanned but which could not be finished yet.
+    pub async fn finish_scanned_accounts(&self) {
+        let scanned: Vec<String> = self
+            .account_mappling
+            .read()
+            .await
+            .iter()
+            .filter(|(_, account)| account.remaining_task == 0)
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in scanned {
+            self.finish_account(address).await;
+        }
+    }
+
+    /// Submit the scan result of an account whose blocks are all scanned, and drop its session.
+    pub async fn finish_account(&self, address: String) {
+        let account_info = match self.account_mappling.write().await.remove(&address) {
+            Some(account_info) => account_info,
+            None => return,
+        };
+        self.release_keys(&address).await;
+        // never hand out transactions of orphaned blocks
+        match self.is_canonical(&account_info).await {
+            Ok(true) => {}
+            Ok(false) => {
+                self.restart_session(&address, account_info).await;
+                return;
+            }
+            Err(e) => {
+                // nodes may be briefly unreachable, keep the scanned session to check it again
+                warn!(
+                    "Failed to check blocks of account {} against the chain, {}",
+                    address, e
+                );
+                if !account_info.dry_run {
+                    if let Err(e) = self
+                        .shared
+                        .db_handler
+                        .update_scan_watermark(
+                            address.clone(),
+                            account_info.end_block.sequence as i64,
+                            vec![],
+                        )
+                        .await
+                    {
+                        error!(
+                            "Failed to update scan session of account {}, {}",
+                            address, e
+                        );
+                    }
+                }
+                let _ = self
+                    .account_mappling
+                    .write()
+                    .await
+                    .insert(address, account_info);
+                return;
+            }
+        }
+        let set_account_head_request = RpcSetAccountHeadRequest {
+            account: address.clone(),
+            start: account_info.start_block.hash,
+            end: account_info.end_block.hash,
+            scan_complete: true,
+            blocks: account_info
+                .blocks
+                .iter()
+                .map(|(k, (sequence, v))| BlockWithHash {
+                    hash: k.to_string(),
+                    sequence: *sequence,
+                    transactions: v.clone(),
+                })
+                .collect(),
+        };
+        if account_info.dry_run {
+            info!("Dry run scan of account {} completed", address);
+            let _ = self
+                .dry_runs
+                .write()
                 .await
-                .get(&address)
-                .unwrap()
-                .clone();
-            let set_account_head_request = RpcSetAccountHeadRequest {
-                account: address.clone(),
-                start: account_info.start_block.hash,
-                end: account_info.end_block.hash,
-                scan_complete: true,
-                blocks: account_info
-                    .blocks
-                    .iter()
-                    .map(|(k, (sequence, v))| BlockWithHash {
-                        hash: k.to_string(),
-                        sequence: *sequence,
-                        transactions: v.clone(),
-                    })
-                    .collect(),
-            };
-            let signature = self
+                .insert(address, set_account_head_request);
+            return;
+        }
+        let signature = self
+            .shared
+            .operator
+            .sign(&set_account_head_request)
+            .unwrap_or("default_signature, should never happen".into());
+        let request = DecryptionMessage {
+            message: set_account_head_request,
+            signature,
+        };
+        info!("Scanning for account {} completed", address);
+        counter!("scanner_accounts_completed_total").increment(1);
+        let mut retry = 0;
+        let mut submitted = false;
+        loop {
+            if retry == 3 {
+                break;
+            }
+            if let Err(e) = self
                 .shared
-                .operator
-                .sign(&set_account_head_request)
-                .unwrap_or("default_signature, should never happen".into());
-            let request = DecryptionMessage {
-                message: set_account_head_request,
-                signature,
-            };
-            info!("Scanning for account {} completed", address);
-            let _ = self.account_mappling.write().await.remove(&address);
-            let mut retry = 0;
-            loop {
-                if retry == 3 {
-                    break;
-                }
-                if let Err(e) = self
-                    .shared
-                    .server_handler
-                    .submit_scan_response(request.clone())
-                {
-                    error!("Submit scan result failed {}", e);
-                } else {
-                    break;
-                }
-                retry += 1;
-                sleep(Duration::from_secs(3)).await;
+                .server_handler
+                .submit_scan_response(request.clone())
+                .await
+            {
+                error!("Submit scan result failed {}", e);
+            } else {
+                submitted = true;
+                break;
             }
+            retry += 1;
+            sleep(Duration::from_secs(3)).await;
+        }
+        // Keep the session of an unsubmitted result, it is submitted again on restart
+        let persisted = match submitted {
+            true => {
+                self.shared
+                    .db_handler
+                    .remove_scan_session(address.clone())
+                    .await
+            }
+            false => {
+                self.shared
+                    .db_handler
+                    .update_scan_watermark(
+                        address.clone(),
+                        account_info.end_block.sequence as i64,
+                        vec![],
+                    )
+                    .await
+            }
+        };
+        if let Err(e) = persisted {
+            error!(
+                "Failed to update scan session of account {}, {}",
+                address, e
+            );
         }
-        let _ = self.task_mapping.write().await.remove(&task_id);
-        Ok(())
     }
 }
+
+fn median(samples: &VecDeque<f64>) -> Option<f64> {
+    let mut samples: Vec<f64> = samples.iter().cloned().collect();
+    samples.sort_by(|a, b| a.total_cmp(b));
+    samples.get(samples.len() / 2).cloned()
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+use db_handler::load_db;
+use params::{mainnet::Mainnet, network::Network, testnet::Testnet};
+use server::rebalance::rebalance_accounts;
+use utils::{initialize_logger, initialize_logger_filter, EnvFilter, Parser, Rebalance};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Rebalance::parse();
+    let Rebalance {
+        dbconfig,
+        node,
+        scanner,
+        network,
+        operator,
+        dry_run,
+        verbosity,
+    } = args;
+    initialize_logger(verbosity);
+    let filter = EnvFilter::from_default_env().add_directive("rustls=off".parse().unwrap());
+    initialize_logger_filter(filter);
+    let db_handler = load_db(dbconfig).unwrap();
+    match network {
+        Mainnet::ID => {
+            rebalance_accounts::<Mainnet>(node, db_handler, scanner, operator, dry_run).await?;
+        }
+        Testnet::ID => {
+            rebalance_accounts::<Testnet>(node, db_handler, scanner, operator, dry_run).await?;
+        }
+        _ => panic!("Invalid network used"),
+    }
+    Ok(())
+}
@@ -1,12 +1,16 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use axum::{
     extract::{self, State},
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
+use db_handler::{Account, Session};
 use networking::{
-    decryption_message::{DecryptionMessage, ScanRequest, ScanResponse, SuccessResponse},
+    decryption_message::{
+        DecryptionMessage, DryRunResponse, ScanProgress, ScanRequest, ScanResponse, ScannerQuery,
+        SuccessResponse,
+    },
     rpc_abi::{
         BlockInfo, CreatedAt, OutPut, RpcAddTxRequest, RpcCreateTxRequest,
         RpcGetAccountStatusRequest, RpcGetAccountTransactionRequest, RpcGetBalancesRequest,
@@ -14,112 +18,147 @@ use networking::{
         RpcImportAccountResponse, RpcRemoveAccountRequest, RpcResetAccountRequest, RpcResponse,
         RpcSetScanningRequest,
     },
-    web_abi::{GetTransactionDetailResponse, ImportAccountRequest, RescanAccountResponse},
+    rpc_handler::RpcHandler,
+    web_abi::{
+        ChallengeRequest, GetMessagesRequest, GetMessagesResponse, GetTransactionDetailResponse,
+        ImportAccountRequest, LoginRequest, MessageDetail, RescanAccountRequest,
+        RescanAccountResponse, RevokeResponse, SendMessageRequest, SessionResponse, MEMO_LENGTH,
+    },
 };
 use oreo_errors::OreoError;
 use params::{mainnet::Mainnet, network::Network, testnet::Testnet};
 use serde_json::json;
+use tracing::error;
 
-use crate::SharedState;
+use crate::{
+    ratelimit::VerifiedAccount,
+    session::{verify_proof, Claims},
+    SharedState,
+};
+
+/// Import account into its owner node, and submit a quick scan request if it is far behind.
+pub async fn import_and_scan(shared: &SharedState, account: &Account) -> Result<(), OreoError> {
+    let genesis = shared.genesis().clone();
+    let node = shared.wallet(account)?;
+    let created_at = match (account.create_head, account.create_hash.clone()) {
+        (Some(sequence), Some(hash)) => Some(CreatedAt {
+            hash,
+            sequence: sequence as u64,
+            network_id: shared.network(),
+        }),
+        _ => None,
+    };
+    let rpc_data = RpcImportAccountRequest {
+        view_key: account.vk.clone(),
+        incoming_view_key: account.in_vk.clone(),
+        outgoing_view_key: account.out_vk.clone(),
+        public_address: account.address.clone(),
+        spending_key: None,
+        version: shared.account_version(),
+        name: account.name.clone(),
+        created_at,
+    };
+    node.import_account(rpc_data).await?;
+    let latest = shared.rpc_handler.get_latest_block().await?;
+    let latest_height = latest
+        .data
+        .current_block_identifier
+        .index
+        .parse::<u64>()
+        .map_err(|e| OreoError::ParseError(e.to_string()))?;
+    let status = node
+        .get_account_status(RpcGetAccountStatusRequest {
+            account: account.name.clone(),
+        })
+        .await?;
+    let head = status.data.account.head.unwrap_or(BlockInfo {
+        hash: genesis.hash.clone(),
+        sequence: genesis.sequence,
+    });
+    if latest_height.saturating_sub(head.sequence) > 1000 {
+        node.set_scanning(RpcSetScanningRequest {
+            account: account.name.clone(),
+            enabled: false,
+        })
+        .await?;
+        node.reset_account(RpcResetAccountRequest {
+            account: account.name.clone(),
+            reset_scanning_enabled: Some(false),
+            reset_created_at: Some(false),
+        })
+        .await?;
+        let scan_request = ScanRequest {
+            address: account.address.clone(),
+            in_vk: account.in_vk.clone(),
+            out_vk: account.out_vk.clone(),
+            head: Some(head),
+            options: account.scan_options.0.clone(),
+            dry_run: false,
+        };
+        let signature = shared
+            .operator
+            .sign(&scan_request)
+            .unwrap_or("default_but_bad_signature, should never happen".into());
+        shared
+            .scan_handler
+            .submit_scan_request(DecryptionMessage {
+                message: scan_request,
+                signature,
+            })
+            .await?;
+    }
+    Ok(())
+}
 
 pub async fn import_account_handler(
     State(shared): State<Arc<SharedState>>,
     extract::Json(import): extract::Json<ImportAccountRequest>,
 ) -> impl IntoResponse {
     let genesis = shared.genesis().clone();
+    let worker_id = match shared.assign_worker().await {
+        Ok(worker_id) => worker_id,
+        Err(e) => return e.into_response(),
+    };
+    let mut account = import.to_account(genesis);
+    account.worker_id = worker_id as i32;
     let account_name = shared
         .db_handler
-        .save_account(import.clone().to_account(genesis.clone()), 0)
+        .save_account(account.clone(), worker_id)
         .await;
     if let Err(e) = account_name {
         return e.into_response();
     }
     let account_name = account_name.unwrap();
-    let ImportAccountRequest {
-        view_key,
-        incoming_view_key,
-        outgoing_view_key,
-        public_address,
-        created_at,
-    } = import;
-
-    let created_at = created_at.map(|created_at| CreatedAt {
-        hash: created_at.hash,
-        sequence: created_at.sequence,
-        network_id: shared.network(),
-    });
-    let rpc_data = RpcImportAccountRequest {
-        view_key,
-        incoming_view_key: incoming_view_key.clone(),
-        outgoing_view_key: outgoing_view_key.clone(),
-        public_address: public_address.clone(),
-        spending_key: None,
-        version: shared.account_version(),
-        name: account_name.clone(),
-        created_at,
-    };
-    match shared.rpc_handler.import_account(rpc_data) {
-        Ok(_) => {
-            let latest = shared.rpc_handler.get_latest_block().unwrap();
-            let latest_height = latest
-                .data
-                .current_block_identifier
-                .index
-                .parse::<u64>()
-                .unwrap();
-            let result = shared
-                .rpc_handler
-                .get_account_status(RpcGetAccountStatusRequest {
-                    account: account_name.clone(),
-                })
-                .map(|x| {
-                    let head = x.data.account.head.unwrap_or(BlockInfo {
-                        hash: genesis.hash.clone(),
-                        sequence: genesis.sequence,
-                    });
-                    if latest_height - head.sequence > 1000 {
-                        shared.rpc_handler.set_scanning(RpcSetScanningRequest {
-                            account: account_name.clone(),
-                            enabled: false,
-                        })?;
-                        shared.rpc_handler.reset_account(RpcResetAccountRequest {
-                            account: account_name.clone(),
-                            reset_scanning_enabled: Some(false),
-                            reset_created_at: Some(false),
-                        })?;
-                        let scan_request = ScanRequest {
-                            address: public_address.clone(),
-                            in_vk: incoming_view_key.clone(),
-                            out_vk: outgoing_view_key.clone(),
-                            head: Some(head),
-                        };
-                        let signature = shared
-                            .operator
-                            .sign(&scan_request)
-                            .unwrap_or("default_but_bad_signature, should never happen".into());
-                        shared.scan_handler.submit_scan_request(DecryptionMessage {
-                            message: scan_request,
-                            signature,
-                        })?;
-                    }
-                    Ok::<RpcImportAccountResponse, OreoError>(RpcImportAccountResponse {
-                        name: account_name.clone(),
-                    })
-                });
-            match result {
-                Ok(response) => RpcResponse {
-                    status: 200,
-                    data: response,
-                }
-                .into_response(),
-
-                Err(e) => e.into_response(),
-            }
+    match import_and_scan(&shared, &account).await {
+        Ok(()) => RpcResponse {
+            status: 200,
+            data: RpcImportAccountResponse { name: account_name },
         }
+        .into_response(),
         Err(e) => e.into_response(),
     }
 }
 
+/// Have the scanner drop the tasks, partial results and keys of an account being removed.
+async fn forget_scans(shared: &SharedState, address: &str) -> Result<(), OreoError> {
+    let query = ScannerQuery::new(Some(address.to_string()));
+    let signature = shared
+        .operator
+        .sign(&query)
+        .unwrap_or("default_but_bad_signature, should never happen".into());
+    let response = shared
+        .scan_handler
+        .remove_account(DecryptionMessage {
+            message: query,
+            signature,
+        })
+        .await?;
+    match response.success {
+        true => Ok(()),
+        false => Err(OreoError::DServerError),
+    }
+}
+
 pub async fn remove_account_handler(
     State(shared): State<Arc<SharedState>>,
     extract::Json(remove_account): extract::Json<RpcRemoveAccountRequest>,
@@ -131,11 +170,21 @@ pub async fn remove_account_handler(
     if let Err(e) = db_account {
         return e.into_response();
     }
-    let result = shared.rpc_handler.remove_account(RpcRemoveAccountRequest {
-        account: db_account.unwrap().name,
-        confirm: Some(true),
-        wait: Some(true),
-    });
+    let db_account = db_account.unwrap();
+    if let Err(e) = forget_scans(&shared, &db_account.address).await {
+        return e.into_response();
+    }
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    let result = node
+        .remove_account(RpcRemoveAccountRequest {
+            account: db_account.name,
+            confirm: Some(true),
+            wait: Some(true),
+        })
+        .await;
     match result {
         Ok(response) => {
             if let Err(e) = shared
@@ -151,6 +200,21 @@ pub async fn remove_account_handler(
     }
 }
 
+/// Progress of the quick scan of an account, if the scanner runs one.
+async fn scan_progress(shared: &SharedState, address: &str) -> Option<ScanProgress> {
+    let query = ScannerQuery::new(Some(address.to_string()));
+    let signature = shared.operator.sign(&query).ok()?;
+    shared
+        .scan_handler
+        .get_scan_status(DecryptionMessage {
+            message: query,
+            signature,
+        })
+        .await
+        .ok()?
+        .progress
+}
+
 pub async fn account_status_handler(
     State(shared): State<Arc<SharedState>>,
     extract::Json(account): extract::Json<RpcGetAccountStatusRequest>,
@@ -159,11 +223,16 @@ pub async fn account_status_handler(
     if let Err(e) = db_account {
         return e.into_response();
     }
-    let result = shared
-        .rpc_handler
+    let db_account = db_account.unwrap();
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    let result = node
         .get_account_status(RpcGetAccountStatusRequest {
-            account: db_account.unwrap().name,
-        });
+            account: db_account.name,
+        })
+        .await;
     let genesis = shared.genesis().clone();
     match result {
         Ok(mut result) => {
@@ -176,6 +245,9 @@ pub async fn account_status_handler(
                     })
                 }
             }
+            if db_account.need_scan {
+                result.data.scan = scan_progress(&shared, &db_account.address).await;
+            }
             Ok(result)
         }
         Err(e) => Err(e),
@@ -183,59 +255,206 @@ pub async fn account_status_handler(
     .into_response()
 }
 
-async fn rescan_account(
-    shared: Arc<SharedState>,
-    account: RpcGetAccountStatusRequest,
-) -> Result<RescanAccountResponse, OreoError> {
-    let account = shared
-        .db_handler
-        .get_account(account.account.clone())
-        .await?;
-    shared.rpc_handler.set_scanning(RpcSetScanningRequest {
+/// Block a rescan starts from, given by sequence or hash and checked against the chain.
+async fn rescan_start(
+    rpc_handler: &RpcHandler,
+    genesis: &BlockInfo,
+    request: &RescanAccountRequest,
+) -> Result<Option<BlockInfo>, OreoError> {
+    let sequence = match (request.from_sequence, &request.from_hash) {
+        (None, None) => return Ok(None),
+        (Some(sequence), _) => sequence,
+        (None, Some(hash)) => {
+            rpc_handler
+                .get_block_by_hash(hash.clone())
+                .await
+                .map_err(|_| OreoError::BadRescanStart(hash.clone()))?
+                .data
+                .block
+                .sequence as u64
+        }
+    };
+    if sequence < genesis.sequence {
+        return Err(OreoError::BadRescanStart(sequence.to_string()));
+    }
+    let block = rpc_handler
+        .get_block(sequence as i64)
+        .await
+        .map_err(|_| OreoError::BadRescanStart(sequence.to_string()))?
+        .data
+        .block;
+    // a hash must name the canonical block at its sequence
+    if let Some(hash) = &request.from_hash {
+        if !hash.eq_ignore_ascii_case(&block.hash) {
+            return Err(OreoError::BadRescanStart(hash.clone()));
+        }
+    }
+    Ok(Some(BlockInfo {
+        hash: block.hash,
+        sequence,
+    }))
+}
+
+/// Block a reset wallet scans the account from, its creation block or genesis.
+fn created_head(shared: &SharedState, account: &Account) -> BlockInfo {
+    match (account.create_head, account.create_hash.clone()) {
+        (Some(sequence), Some(hash)) => BlockInfo {
+            hash,
+            sequence: sequence as u64,
+        },
+        _ => shared.genesis(),
+    }
+}
+
+/// Have the node wallet forget what it scanned for an account, the scanner fills it again from
+/// the head the reset leaves behind.
+async fn reset_wallet(shared: &SharedState, account: &Account) -> Result<BlockInfo, OreoError> {
+    let node = shared.wallet(account)?;
+    node.set_scanning(RpcSetScanningRequest {
         account: account.name.clone(),
         enabled: false,
-    })?;
-    shared.rpc_handler.reset_account(RpcResetAccountRequest {
+    })
+    .await?;
+    node.reset_account(RpcResetAccountRequest {
         account: account.name.clone(),
         reset_scanning_enabled: Some(false),
         reset_created_at: Some(false),
-    })?;
+    })
+    .await?;
     let _ = shared
         .db_handler
         .update_scan_status(account.address.clone(), true)
         .await?;
-    let status = shared
-        .rpc_handler
+    // setAccountHead only accepts results that continue from the head the node wallet holds
+    let status = node
         .get_account_status(RpcGetAccountStatusRequest {
             account: account.name.clone(),
-        })?;
-    let genesis = shared.genesis();
-    let head = status.data.account.head.unwrap_or(BlockInfo {
-        hash: genesis.hash.clone(),
-        sequence: genesis.sequence,
-    });
+        })
+        .await?;
+    Ok(status.data.account.head.unwrap_or(shared.genesis()))
+}
+
+async fn rescan_account(
+    shared: Arc<SharedState>,
+    request: RescanAccountRequest,
+) -> Result<RescanAccountResponse, OreoError> {
+    let account = shared
+        .db_handler
+        .get_account(request.account.clone())
+        .await?;
+    let start = rescan_start(&shared.rpc_handler, &shared.genesis(), &request).await?;
+    if let Some(start) = &start {
+        // the node wallet is reset to the account creation, so a real rescan must cover it all
+        if !request.dry_run && start.sequence > created_head(&shared, &account).sequence {
+            return Err(OreoError::BadRescanStart(start.sequence.to_string()));
+        }
+    }
+    let head = match request.dry_run {
+        true => start.unwrap_or_else(|| created_head(&shared, &account)),
+        // reset first, a short scan could otherwise post its results before the reset wipes them
+        false => match reset_wallet(&shared, &account).await {
+            Ok(head) => head,
+            Err(e) => {
+                resume_wallet(&shared, &account).await;
+                return Err(e);
+            }
+        },
+    };
     let scan_request = ScanRequest {
         address: account.address.clone(),
         in_vk: account.in_vk.clone(),
         out_vk: account.out_vk.clone(),
         head: Some(head),
+        options: account.scan_options.0.clone(),
+        dry_run: request.dry_run,
     };
     let signature = shared
         .operator
         .sign(&scan_request)
         .unwrap_or("default_but_bad_signature, should never happen".into());
-    shared.scan_handler.submit_scan_request(DecryptionMessage {
-        message: scan_request,
-        signature,
-    })?;
-    Ok(RescanAccountResponse { success: true })
+    let response = shared
+        .scan_handler
+        .submit_scan_request(DecryptionMessage {
+            message: scan_request,
+            signature,
+        })
+        .await;
+    let success = match response {
+        Ok(response) => response.success,
+        Err(e) if request.dry_run => return Err(e),
+        Err(e) => {
+            resume_wallet(&shared, &account).await;
+            return Err(e);
+        }
+    };
+    if !success && !request.dry_run {
+        resume_wallet(&shared, &account).await;
+    }
+    Ok(RescanAccountResponse { success })
+}
+
+/// Hand a reset account back to the node wallet when the scanner did not take its rescan.
+async fn resume_wallet(shared: &SharedState, account: &Account) {
+    if let Ok(node) = shared.wallet(account) {
+        if let Err(e) = node
+            .set_scanning(RpcSetScanningRequest {
+                account: account.name.clone(),
+                enabled: true,
+            })
+            .await
+        {
+            error!("Failed to resume scanning of {}, {}", account.address, e);
+        }
+    }
+    if let Err(e) = shared
+        .db_handler
+        .update_scan_status(account.address.clone(), false)
+        .await
+    {
+        error!("Failed to clear scan status of {}, {}", account.address, e);
+    }
 }
 
+/// A real rescan resets the node wallet and always scans from the head the reset leaves, so it
+/// rejects a start block past the creation block of the account; only dry runs start anywhere.
 pub async fn rescan_account_handler(
+    State(shared): State<Arc<SharedState>>,
+    extract::Json(request): extract::Json<RescanAccountRequest>,
+) -> impl IntoResponse {
+    match rescan_account(shared, request).await {
+        Ok(response) => RpcResponse {
+            status: 200,
+            data: response,
+        }
+        .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn rescan_result(
+    shared: Arc<SharedState>,
+    account: RpcGetAccountStatusRequest,
+) -> Result<DryRunResponse, OreoError> {
+    let account = shared.db_handler.get_account(account.account).await?;
+    let query = ScannerQuery::new(Some(account.address));
+    let signature = shared
+        .operator
+        .sign(&query)
+        .unwrap_or("default_but_bad_signature, should never happen".into());
+    shared
+        .scan_handler
+        .get_dry_run(DecryptionMessage {
+            message: query,
+            signature,
+        })
+        .await
+}
+
+pub async fn rescan_result_handler(
     State(shared): State<Arc<SharedState>>,
     extract::Json(account): extract::Json<RpcGetAccountStatusRequest>,
 ) -> impl IntoResponse {
-    match rescan_account(shared, account).await {
+    match rescan_result(shared, account).await {
         Ok(response) => RpcResponse {
             status: 200,
             data: response,
@@ -258,6 +477,7 @@ async fn update_scan_status(
             .db_handler
             .get_account(message.account.clone())
             .await?;
+        let node = shared.wallet(&account)?;
         let batch_size = shared.set_account_limit();
         let scan_complete = message.scan_complete;
         let mut first_request = true;
@@ -281,21 +501,26 @@ async fn update_scan_status(
             if !limited_blocks.is_empty() && !blocks.is_empty() {
                 let last_block = limited_blocks.last().unwrap();
                 message.end = last_block.hash.clone();
-                let q = shared.rpc_handler.get_blocks(last_block.sequence as u64, last_block.sequence as u64 + 1)?;
+                let q = shared
+                    .rpc_handler
+                    .get_blocks(last_block.sequence as u64, last_block.sequence as u64 + 1)
+                    .await?;
                 start_hash = q.data.blocks[q.data.blocks.len() - 1].block.hash.clone();
             }
 
             message.blocks = limited_blocks;
-            shared.rpc_handler.set_account_head(message)?;
+            node.set_account_head(message).await?;
             {
                 first_request = false;
             }
         }
         if scan_complete {
-            let _ = shared.rpc_handler.set_scanning(RpcSetScanningRequest {
-                account: account.name.clone(),
-                enabled: true,
-            })?;
+            let _ = node
+                .set_scanning(RpcSetScanningRequest {
+                    account: account.name.clone(),
+                    enabled: true,
+                })
+                .await?;
             shared
                 .db_handler
                 .update_scan_status(account.address, false)
@@ -328,14 +553,21 @@ pub async fn get_balances_handler(
     if let Err(e) = db_account {
         return e.into_response();
     }
-    let resp = shared.rpc_handler.get_balances(RpcGetBalancesRequest {
-        account: db_account.unwrap().name,
-        confirmations: Some(get_balance.confirmations.unwrap_or(10)),
-    });
+    let db_account = db_account.unwrap();
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    let resp = node
+        .get_balances(RpcGetBalancesRequest {
+            account: db_account.name,
+            confirmations: Some(get_balance.confirmations.unwrap_or(10)),
+        })
+        .await;
     match resp {
         Ok(mut res) => {
             for item in res.data.balances.iter_mut() {
-                if let Ok(asset) = shared.rpc_handler.get_asset(item.asset_id.clone()) {
+                if let Ok(asset) = shared.rpc_handler.get_asset(item.asset_id.clone()).await {
                     item.decimals = asset.data.verification.decimals;
                 }
             }
@@ -361,15 +593,24 @@ pub async fn get_ores_handler(
     if let Err(e) = db_account {
         return e.into_response();
     }
-    let resp = shared.rpc_handler.get_balances(RpcGetBalancesRequest {
-        account: db_account.unwrap().name,
-        confirmations: Some(get_balance.confirmations.unwrap_or(10)),
-    });
+    let db_account = db_account.unwrap();
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    let resp = node
+        .get_balances(RpcGetBalancesRequest {
+            account: db_account.name,
+            confirmations: Some(get_balance.confirmations.unwrap_or(10)),
+        })
+        .await;
     match resp {
         Ok(res) => {
             let data = match shared.network() {
-                Testnet::ID => RpcGetBalancesResponse::ores::<Testnet>(res.data).await,
-                _ => RpcGetBalancesResponse::ores::<Mainnet>(res.data).await,
+                Testnet::ID => {
+                    RpcGetBalancesResponse::ores::<Testnet>(&node.client, res.data).await
+                }
+                _ => RpcGetBalancesResponse::ores::<Mainnet>(&node.client, res.data).await,
             };
             let response = RpcResponse { status: 200, data };
             response.into_response()
@@ -386,14 +627,18 @@ pub async fn get_transaction_handler(
     if let Err(e) = db_account {
         return e.into_response();
     }
-    let rpc_transaction =
-        shared
-            .rpc_handler
-            .get_account_transaction(RpcGetAccountTransactionRequest {
-                account: db_account.unwrap().name,
-                hash: account.hash,
-                notes: Some(true),
-            });
+    let db_account = db_account.unwrap();
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    let rpc_transaction = node
+        .get_account_transaction(RpcGetAccountTransactionRequest {
+            account: db_account.name,
+            hash: account.hash,
+            notes: Some(true),
+        })
+        .await;
     match rpc_transaction {
         Ok(RpcResponse { data, status: _ }) => {
             let transaction_detail = GetTransactionDetailResponse::from_rpc_data(data);
@@ -421,15 +666,20 @@ pub async fn get_transactions_handler(
     if let Err(e) = db_account {
         return e.into_response();
     }
-    shared
-        .rpc_handler
-        .get_transactions(RpcGetTransactionsRequest {
-            account: db_account.unwrap().name,
-            limit: Some(get_transactions.limit.unwrap_or(6)),
-            offset: Some(get_transactions.offset.unwrap_or(0)),
-            reverse: Some(true),
-        })
-        .into_response()
+    let db_account = db_account.unwrap();
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    node.get_transactions(RpcGetTransactionsRequest {
+        account: db_account.name,
+        limit: Some(get_transactions.limit.unwrap_or(6)),
+        offset: Some(get_transactions.offset.unwrap_or(0)),
+        reverse: Some(true),
+        notes: None,
+    })
+    .await
+    .into_response()
 }
 
 pub async fn create_transaction_handler(
@@ -443,6 +693,11 @@ pub async fn create_transaction_handler(
     if let Err(e) = db_account {
         return e.into_response();
     }
+    let db_account = db_account.unwrap();
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
     let outputs: Vec<OutPut> = create_transaction
         .outputs
         .unwrap_or(vec![])
@@ -461,33 +716,401 @@ pub async fn create_transaction_handler(
         }
     }
     let burns = create_transaction.burns.unwrap_or(vec![]);
-    shared
-        .rpc_handler
-        .create_transaction(RpcCreateTxRequest {
-            account: db_account.unwrap().name,
-            outputs: Some(outputs),
-            fee: create_transaction.fee,
-            expiration_delta: Some(create_transaction.expiration_delta.unwrap_or(30)),
-            mints: Some(mints),
-            burns: Some(burns),
-        })
-        .into_response()
+    node.create_transaction(RpcCreateTxRequest {
+        account: db_account.name,
+        outputs: Some(outputs),
+        fee: create_transaction.fee,
+        expiration_delta: Some(create_transaction.expiration_delta.unwrap_or(30)),
+        mints: Some(mints),
+        burns: Some(burns),
+    })
+    .await
+    .into_response()
 }
 
 pub async fn add_transaction_handler(
     State(shared): State<Arc<SharedState>>,
+    Extension(VerifiedAccount(account)): Extension<VerifiedAccount>,
     extract::Json(broadcast_transaction): extract::Json<RpcAddTxRequest>,
 ) -> impl IntoResponse {
-    shared
-        .rpc_handler
-        .add_transaction(broadcast_transaction)
-        .into_response()
+    let db_account = match shared.db_handler.get_account(account).await {
+        Ok(account) => account,
+        Err(e) => return e.into_response(),
+    };
+    match shared.wallet(&db_account) {
+        Ok(node) => node
+            .add_transaction(broadcast_transaction)
+            .await
+            .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Messages decrypted by the scanner merged with the ones of the blocks the node wallet
+/// indexed itself, before an import scan or after it finished.
+pub async fn get_messages_handler(
+    State(shared): State<Arc<SharedState>>,
+    extract::Json(request): extract::Json<GetMessagesRequest>,
+) -> impl IntoResponse {
+    let db_account = match shared.db_handler.get_account(request.account.clone()).await {
+        Ok(account) => account,
+        Err(e) => return e.into_response(),
+    };
+    let start = request.start.unwrap_or(0);
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    // newest first, so the history below `start` is never read
+    let transactions = match node
+        .get_transactions_while(
+            RpcGetTransactionsRequest {
+                account: db_account.name.clone(),
+                limit: None,
+                offset: None,
+                reverse: Some(true),
+                notes: Some(true),
+            },
+            |transaction| {
+                transaction
+                    .block_sequence
+                    .map_or(true, |sequence| sequence as i64 >= start)
+            },
+        )
+        .await
+    {
+        Ok(response) => response.data.transactions,
+        Err(e) => return e.into_response(),
+    };
+    let notes = match shared
+        .db_handler
+        .get_scan_notes(db_account.address.clone(), start)
+        .await
+    {
+        Ok(notes) => notes,
+        Err(e) => return e.into_response(),
+    };
+    let mut messages: Vec<MessageDetail> = notes
+        .into_iter()
+        .filter_map(MessageDetail::from_note)
+        .collect();
+    let mut known: HashSet<(String, String, String)> = messages
+        .iter()
+        .map(|message| {
+            (
+                message.tx_hash.clone(),
+                message.direction.clone(),
+                message.memo.clone(),
+            )
+        })
+        .collect();
+    for transaction in transactions {
+        let sequence = match transaction.block_sequence {
+            Some(sequence) if sequence as i64 >= start => sequence as i64,
+            _ => continue,
+        };
+        for note in transaction.notes.unwrap_or_default() {
+            if let Some(message) =
+                MessageDetail::from_rpc_note(&db_account.address, &transaction.hash, sequence, note)
+            {
+                if known.insert((
+                    message.tx_hash.clone(),
+                    message.direction.clone(),
+                    message.memo.clone(),
+                )) {
+                    messages.push(message);
+                }
+            }
+        }
+    }
+    messages.sort_by_key(|message| message.block_sequence);
+    RpcResponse {
+        status: 200,
+        data: GetMessagesResponse {
+            account: request.account,
+            messages,
+        },
+    }
+    .into_response()
+}
+
+pub async fn send_message_handler(
+    State(shared): State<Arc<SharedState>>,
+    extract::Json(request): extract::Json<SendMessageRequest>,
+) -> impl IntoResponse {
+    if request.memo.len() > MEMO_LENGTH {
+        return OreoError::MemoTooLong(MEMO_LENGTH).into_response();
+    }
+    let db_account = match shared.db_handler.get_account(request.account.clone()).await {
+        Ok(account) => account,
+        Err(e) => return e.into_response(),
+    };
+    let node = match shared.wallet(&db_account) {
+        Ok(node) => node,
+        Err(e) => return e.into_response(),
+    };
+    let output = OutPut {
+        public_address: request.recipient,
+        amount: "0".into(),
+        memo: Some(request.memo),
+        memo_hex: None,
+        asset_id: None,
+    };
+    let output = match shared.network() {
+        Testnet::ID => OutPut::from::<Testnet>(output),
+        _ => OutPut::from::<Mainnet>(output),
+    };
+    node.create_transaction(RpcCreateTxRequest {
+        account: db_account.name,
+        outputs: Some(vec![output]),
+        fee: request.fee,
+        expiration_delta: Some(request.expiration_delta.unwrap_or(30)),
+        mints: Some(vec![]),
+        burns: Some(vec![]),
+    })
+    .await
+    .into_response()
 }
 
 pub async fn latest_block_handler(State(shared): State<Arc<SharedState>>) -> impl IntoResponse {
-    shared.rpc_handler.get_latest_block().into_response()
+    shared.rpc_handler.get_latest_block().await.into_response()
+}
+
+pub async fn challenge_handler(
+    State(shared): State<Arc<SharedState>>,
+    extract::Json(request): extract::Json<ChallengeRequest>,
+) -> impl IntoResponse {
+    match shared.db_handler.get_account(request.account.clone()).await {
+        Ok(account) => RpcResponse {
+            status: 200,
+            data: shared.sessions.issue_challenge(account.address),
+        }
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn new_session(shared: &SharedState, address: String) -> Result<SessionResponse, OreoError> {
+    let claims = Claims::new(address);
+    let token = claims.encode(&shared.operator)?;
+    shared
+        .db_handler
+        .save_session(Session {
+            sid: claims.sid.clone(),
+            address: claims.address.clone(),
+            expires_at: claims.exp as i64,
+            revoked: false,
+        })
+        .await?;
+    Ok(SessionResponse {
+        token,
+        expires_at: claims.exp,
+    })
+}
+
+async fn login(
+    shared: Arc<SharedState>,
+    request: LoginRequest,
+) -> Result<SessionResponse, OreoError> {
+    let account = shared
+        .db_handler
+        .get_account(request.account.clone())
+        .await?;
+    shared
+        .sessions
+        .take_challenge(&request.challenge, &account.address)?;
+    if !verify_proof(&account.vk, &request.challenge, &request.proof) {
+        return Err(OreoError::Unauthorized);
+    }
+    new_session(&shared, account.address).await
+}
+
+pub async fn login_handler(
+    State(shared): State<Arc<SharedState>>,
+    extract::Json(request): extract::Json<LoginRequest>,
+) -> impl IntoResponse {
+    match login(shared, request).await {
+        Ok(response) => RpcResponse {
+            status: 200,
+            data: response,
+        }
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn refresh_session(
+    shared: Arc<SharedState>,
+    claims: Option<Extension<Claims>>,
+) -> Result<SessionResponse, OreoError> {
+    let Extension(claims) = claims.ok_or(OreoError::Unauthorized)?;
+    shared.db_handler.revoke_session(claims.sid).await?;
+    new_session(&shared, claims.address).await
+}
+
+pub async fn refresh_session_handler(
+    State(shared): State<Arc<SharedState>>,
+    claims: Option<Extension<Claims>>,
+) -> impl IntoResponse {
+    match refresh_session(shared, claims).await {
+        Ok(response) => RpcResponse {
+            status: 200,
+            data: response,
+        }
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn revoke_session_handler(
+    State(shared): State<Arc<SharedState>>,
+    claims: Option<Extension<Claims>>,
+) -> impl IntoResponse {
+    let Some(Extension(claims)) = claims else {
+        return OreoError::Unauthorized.into_response();
+    };
+    match shared.db_handler.revoke_session(claims.sid).await {
+        Ok(()) => RpcResponse {
+            status: 200,
+            data: RevokeResponse { success: true },
+        }
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn revoke_all_sessions_handler(
+    State(shared): State<Arc<SharedState>>,
+    claims: Option<Extension<Claims>>,
+) -> impl IntoResponse {
+    let Some(Extension(claims)) = claims else {
+        return OreoError::Unauthorized.into_response();
+    };
+    match shared.db_handler.revoke_sessions(claims.address).await {
+        Ok(()) => RpcResponse {
+            status: 200,
+            data: RevokeResponse { success: true },
+        }
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
 }
 
 pub async fn health_check_handler() -> impl IntoResponse {
     Json(json!({"code": 200, "data": "Hello prover!"})).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+    use networking::{rpc_abi::BlockInfo, rpc_handler::RpcHandler, web_abi::RescanAccountRequest};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use super::rescan_start;
+
+    /// A node whose canonical block at `n` is hashed `canonical-n`, and which also knows a
+    /// forked block `fork-5`.
+    async fn mock_node() -> RpcHandler {
+        let block = |hash: &str, sequence: u64| {
+            Json(json!({"status": 200, "data": {"block": {
+                "hash": hash,
+                "sequence": sequence,
+                "previousBlockHash": "",
+                "transactions": [],
+            }}}))
+            .into_response()
+        };
+        let router = Router::new().route(
+            "/chain/getBlock",
+            post(move |Json(request): Json<Value>| async move {
+                if let Some(sequence) = request["sequence"].as_u64() {
+                    return block(&format!("canonical-{}", sequence), sequence);
+                }
+                match request["hash"].as_str().unwrap() {
+                    "fork-5" => block("fork-5", 5),
+                    hash => match hash.strip_prefix("canonical-") {
+                        Some(sequence) => block(hash, sequence.parse().unwrap()),
+                        None => (
+                            StatusCode::NOT_FOUND,
+                            Json(json!({"code": "not-found", "status": 404, "message": hash})),
+                        )
+                            .into_response(),
+                    },
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        RpcHandler::new(vec![endpoint])
+    }
+
+    fn genesis() -> BlockInfo {
+        BlockInfo {
+            hash: "canonical-1".to_string(),
+            sequence: 1,
+        }
+    }
+
+    fn request(from_sequence: Option<u64>, from_hash: Option<&str>) -> RescanAccountRequest {
+        RescanAccountRequest {
+            account: "test".to_string(),
+            from_sequence,
+            from_hash: from_hash.map(str::to_string),
+            dry_run: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn rescan_start_should_resolve_canonical_blocks() {
+        let rpc_handler = mock_node().await;
+        let genesis = genesis();
+        assert!(rescan_start(&rpc_handler, &genesis, &request(None, None))
+            .await
+            .unwrap()
+            .is_none());
+        let by_sequence = rescan_start(&rpc_handler, &genesis, &request(Some(5), None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(by_sequence.hash, "canonical-5");
+        let by_hash = rescan_start(&rpc_handler, &genesis, &request(None, Some("canonical-5")))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(by_hash.sequence, 5);
+    }
+
+    #[tokio::test]
+    async fn rescan_start_should_reject_bad_blocks() {
+        let rpc_handler = mock_node().await;
+        let genesis = genesis();
+        // known to the node but off the canonical chain
+        assert!(
+            rescan_start(&rpc_handler, &genesis, &request(None, Some("fork-5")))
+                .await
+                .is_err()
+        );
+        // unknown to the node
+        assert!(
+            rescan_start(&rpc_handler, &genesis, &request(None, Some("unknown")))
+                .await
+                .is_err()
+        );
+        // below genesis
+        assert!(
+            rescan_start(&rpc_handler, &genesis, &request(Some(0), None))
+                .await
+                .is_err()
+        );
+        // a hash naming another block than its sequence
+        assert!(rescan_start(
+            &rpc_handler,
+            &genesis,
+            &request(Some(6), Some("canonical-5"))
+        )
+        .await
+        .is_err());
+    }
+}
@@ -1,39 +1,62 @@
 use axum_extra::{
-    headers::{authorization::Basic, Authorization},
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization,
+    },
     TypedHeader,
 };
 use params::{mainnet::Mainnet, network::Network, testnet::Testnet};
 use sha2::{Digest, Sha256};
 use std::str::{self, FromStr};
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use utils::Signer;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use axum::{
-    body::Body,
+    body::{to_bytes, Body},
     error_handling::HandleErrorLayer,
-    extract::State,
+    extract::{MatchedPath, State},
     http::{Request, StatusCode},
-    middleware::{from_fn_with_state, Next},
+    middleware::{from_fn, from_fn_with_state, Next},
     response::IntoResponse,
     routing::{get, post},
     BoxError, Router,
 };
-use db_handler::DBHandler;
-use networking::{rpc_abi::BlockInfo, rpc_handler::RpcHandler, server_handler::ServerHandler};
+use db_handler::{Account, DBHandler};
+use metrics::histogram;
+use networking::{
+    rpc_abi::BlockInfo,
+    rpc_handler::{RpcHandler, RpcNode},
+    server_handler::ServerHandler,
+};
+use oreo_errors::OreoError;
 use tokio::net::TcpListener;
 use tower::{timeout::TimeoutLayer, ServiceBuilder};
 use tower_http::cors::{Any, CorsLayer};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::handlers::{
-    account_status_handler, add_transaction_handler, create_transaction_handler,
-    get_balances_handler, get_ores_handler, get_transaction_handler, get_transactions_handler,
-    health_check_handler, import_account_handler, latest_block_handler, remove_account_handler,
-    rescan_account_handler, update_scan_status_handler,
+use crate::{
+    handlers::{
+        account_status_handler, add_transaction_handler, challenge_handler,
+        create_transaction_handler, get_balances_handler, get_messages_handler, get_ores_handler,
+        get_transaction_handler, get_transactions_handler, health_check_handler,
+        import_account_handler, latest_block_handler, login_handler, refresh_session_handler,
+        remove_account_handler, rescan_account_handler, rescan_result_handler,
+        revoke_all_sessions_handler, revoke_session_handler, send_message_handler,
+        update_scan_status_handler,
+    },
+    ratelimit::{account_rate_limit, rate_limit, RateLimitConfig, RateLimiter, VerifiedAccount},
+    session::{targets_account, Claims, Sessions},
 };
 
 mod handlers;
+pub mod ratelimit;
+pub mod rebalance;
+pub mod session;
 
 pub struct SharedState {
     pub db_handler: Box<dyn Send + Sync + DBHandler>,
@@ -41,23 +64,29 @@ pub struct SharedState {
     pub scan_handler: ServerHandler,
     pub operator: Signer,
     pub network: u8,
+    pub sessions: Sessions,
+    pub limiter: Arc<RateLimiter>,
 }
 
 impl SharedState {
     pub fn new(
         db_handler: Box<dyn DBHandler + Send + Sync>,
-        endpoint: &str,
+        endpoints: Vec<String>,
         scan: &str,
         operator: String,
         network: u8,
+        legacy_auth_until: Option<u64>,
+        rate_limit: RateLimitConfig,
     ) -> Self {
         let operator = Signer::from_str(&operator).expect("Invalid secret key used");
         Self {
             db_handler: db_handler,
-            rpc_handler: RpcHandler::new(endpoint.into()),
+            rpc_handler: RpcHandler::new(endpoints),
             scan_handler: ServerHandler::new(scan.into()),
             operator,
             network,
+            sessions: Sessions::new(legacy_auth_until),
+            limiter: Arc::new(RateLimiter::new(rate_limit)),
         }
     }
 
@@ -91,18 +120,92 @@ impl SharedState {
             _ => Mainnet::SET_ACCOUNT_LIMIT,
         }
     }
+
+    /// The rpc node whose wallet holds this account.
+    pub fn wallet(&self, account: &Account) -> Result<&RpcNode, OreoError> {
+        self.rpc_handler.wallet(account.worker_id as u32)
+    }
+
+    /// Pick the healthy node wallet holding the fewest accounts for a new account.
+    pub async fn assign_worker(&self) -> Result<u32, OreoError> {
+        if self.rpc_handler.nodes.len() == 1 {
+            return Ok(0);
+        }
+        // backends which can't count accounts per node fill the first healthy one
+        let loads = match self.db_handler.get_worker_loads().await {
+            Ok(loads) => loads,
+            Err(e) => {
+                warn!("Failed to get node wallet loads, {}", e);
+                Default::default()
+            }
+        };
+        let health = self.rpc_handler.health.read().await;
+        (0..self.rpc_handler.nodes.len() as u32)
+            .filter(|id| health[*id as usize].healthy)
+            .min_by_key(|id| loads.get(id).cloned().unwrap_or(0))
+            .ok_or(OreoError::InternalRpcError(
+                "No healthy rpc node to import account".to_string(),
+            ))
+    }
 }
 
 unsafe impl Send for SharedState {}
 unsafe impl Sync for SharedState {}
 
-// Authentication middleware function
+/// Largest request body the auth middleware reads to find the account it targets.
+const AUTH_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+// Reject a request whose body targets another account than the authenticated one
+async fn ensure_account(
+    req: Request<Body>,
+    address: &str,
+) -> Result<Request<Body>, (StatusCode, &'static str)> {
+    let (parts, body) = req.into_parts();
+    let bytes = to_bytes(body, AUTH_BODY_LIMIT)
+        .await
+        .map_err(|_| (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"))?;
+    if !targets_account(&bytes, address) {
+        return Err((StatusCode::UNAUTHORIZED, "Token does not match account"));
+    }
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+// Authentication middleware function, accepts session tokens and legacy sha256(vk) passwords
 pub async fn auth(
     State(shared_state): State<Arc<SharedState>>,
-    TypedHeader(Authorization(basic)): TypedHeader<Authorization<Basic>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
     req: Request<Body>,
     next: Next,
 ) -> impl IntoResponse {
+    if let Some(TypedHeader(Authorization(bearer))) = bearer {
+        let claims = Claims::decode(bearer.token(), &shared_state.operator)
+            .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token"))?;
+        match shared_state
+            .db_handler
+            .get_session(claims.sid.clone())
+            .await
+        {
+            Ok(session) if !session.revoked => {
+                let mut req = ensure_account(req, &claims.address).await?;
+                req.extensions_mut()
+                    .insert(VerifiedAccount(claims.address.clone()));
+                req.extensions_mut().insert(claims);
+                return Ok(next.run(req).await);
+            }
+            _ => return Err((StatusCode::UNAUTHORIZED, "Invalid token")),
+        }
+    }
+    let basic = match basic {
+        Some(TypedHeader(Authorization(basic))) => basic,
+        None => return Err((StatusCode::UNAUTHORIZED, "Invalid token")),
+    };
+    if !shared_state.sessions.legacy_auth_allowed() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Legacy token expired, login required",
+        ));
+    }
     match shared_state
         .db_handler
         .get_account(basic.username().to_string())
@@ -116,6 +219,9 @@ pub async fn auth(
             if token_hex != basic.password() {
                 return Err((StatusCode::UNAUTHORIZED, "Invalid token"));
             }
+            let mut req = ensure_account(req, basic.username()).await?;
+            req.extensions_mut()
+                .insert(VerifiedAccount(basic.username().to_string()));
             return Ok(next.run(req).await);
         }
         Err(_) => {
@@ -125,40 +231,83 @@ pub async fn auth(
     }
 }
 
+// Metrics middleware function, records latency of each route
+pub async fn track_metrics(req: Request<Body>, next: Next) -> impl IntoResponse {
+    let start = Instant::now();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or("unknown".to_string());
+    let response = next.run(req).await;
+    histogram!(
+        "http_request_duration_seconds",
+        "route" => route,
+        "status" => response.status().as_u16().to_string(),
+    )
+    .record(start.elapsed().as_secs_f64());
+    response
+}
+
 pub async fn run_server<N: Network>(
     listen: SocketAddr,
-    rpc_server: String,
+    rpc_server: Vec<String>,
     db_handler: Box<dyn DBHandler + Send + Sync>,
     scan: String,
     operator: String,
+    legacy_auth_until: Option<u64>,
+    rate_limit_config: RateLimitConfig,
 ) -> Result<()> {
-    let genesis_hash;
     {
-        let temp_handler: RpcHandler = RpcHandler::new(rpc_server.clone().into());
-        let latest_block_response = temp_handler.get_latest_block()?.data;
-        genesis_hash = latest_block_response.genesis_block_identifier.hash;
-    }
-
-    info!("Genesis hash: {}", genesis_hash);
-    if N::GENESIS_BLOCK_HASH.to_lowercase() != genesis_hash.to_lowercase() {
-        error!("Network genesis hash doesnt match, exit!");
-        return Ok(());
+        let temp_handler: RpcHandler = RpcHandler::new(rpc_server.clone());
+        let mut reachable = 0;
+        for node in temp_handler.nodes.iter() {
+            // a node down at startup is left to the health checker, it is checked once back
+            let latest_block_response = match node.get_latest_block().await {
+                Ok(response) => response.data,
+                Err(e) => {
+                    warn!("Rpc node {} unreachable at startup, {}", node.endpoint, e);
+                    continue;
+                }
+            };
+            reachable += 1;
+            let genesis_hash = latest_block_response.genesis_block_identifier.hash;
+            info!("Genesis hash: {} of node {}", genesis_hash, node.endpoint);
+            if N::GENESIS_BLOCK_HASH.to_lowercase() != genesis_hash.to_lowercase() {
+                error!("Network genesis hash doesnt match, exit!");
+                return Ok(());
+            }
+        }
+        if reachable == 0 {
+            return Err(anyhow!("No rpc node reachable at startup"));
+        }
     }
 
     let shared_resource = Arc::new(SharedState::new(
         db_handler,
-        &rpc_server,
+        rpc_server,
         &scan,
         operator,
         N::ID,
+        legacy_auth_until,
+        rate_limit_config,
     ));
+    shared_resource
+        .rpc_handler
+        .initialize_health_checker()
+        .await;
+    RateLimiter::initialize_purger(shared_resource.limiter.clone()).await;
     let auth_middleware = from_fn_with_state(shared_resource.clone(), auth);
+    let rate_limit_middleware = from_fn_with_state(shared_resource.clone(), rate_limit);
+    let account_limit_middleware = from_fn_with_state(shared_resource.clone(), account_rate_limit);
 
     let no_auth_router = Router::new()
         .route("/import", post(import_account_handler))
         .route("/healthCheck", get(health_check_handler))
         .route("/latestBlock", get(latest_block_handler))
         .route("/updateScan", post(update_scan_status_handler))
+        .route("/challenge", post(challenge_handler))
+        .route("/login", post(login_handler))
         .with_state(shared_resource.clone());
 
     let mut auth_router = Router::new()
@@ -171,13 +320,24 @@ pub async fn run_server<N: Network>(
         .route("/addTx", post(add_transaction_handler))
         .route("/accountStatus", post(account_status_handler))
         .route("/ores", post(get_ores_handler))
+        .route("/messages", post(get_messages_handler))
+        .route("/messages/send", post(send_message_handler))
         .route("/rescan", post(rescan_account_handler))
+        .route("/rescan/result", post(rescan_result_handler))
+        .route("/refresh", post(refresh_session_handler))
+        .route("/revoke", post(revoke_session_handler))
+        .route("/revokeAll", post(revoke_all_sessions_handler))
         .with_state(shared_resource.clone());
 
-    auth_router = auth_router.layer(auth_middleware);
+    // auth is the outer layer so accounts are limited only once verified
+    auth_router = auth_router
+        .layer(account_limit_middleware)
+        .layer(auth_middleware);
 
     let router = no_auth_router
         .merge(auth_router)
+        .route_layer(from_fn(track_metrics))
+        .layer(rate_limit_middleware)
         .layer(
             ServiceBuilder::new()
                 .layer(HandleErrorLayer::new(|_: BoxError| async {
@@ -195,7 +355,11 @@ pub async fn run_server<N: Network>(
     let listener = TcpListener::bind(&listen).await?;
     let app = router.fallback(handler_404);
     info!("Server listening on {}", listen);
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
 
@@ -1,9 +1,10 @@
 use anyhow::Result;
 use db_handler::load_db;
 use params::{mainnet::Mainnet, network::Network, testnet::Testnet};
-use server::run_server;
+use server::{ratelimit::RateLimitConfig, run_server};
 use utils::{
-    handle_signals, initialize_logger, initialize_logger_filter, EnvFilter, Parser, Server,
+    handle_signals, initialize_logger, initialize_logger_filter, initialize_metrics, EnvFilter,
+    Parser, Server,
 };
 
 #[tokio::main]
@@ -16,21 +17,46 @@ async fn main() -> Result<()> {
         scanner,
         network,
         operator,
+        legacy_auth_until,
+        rate_limit,
+        expensive_rate_limit,
+        metrics,
         verbosity,
     } = args;
     initialize_logger(verbosity);
-    let filter = EnvFilter::from_default_env()
-        .add_directive("ureq=off".parse().unwrap())
-        .add_directive("rustls=off".parse().unwrap());
+    let filter = EnvFilter::from_default_env().add_directive("rustls=off".parse().unwrap());
     initialize_logger_filter(filter);
     handle_signals().await?;
+    initialize_metrics(metrics)?;
     let db_handler = load_db(dbconfig).unwrap();
+    let rate_limit_config = RateLimitConfig {
+        requests_per_minute: rate_limit,
+        expensive_per_minute: expensive_rate_limit,
+    };
     match network {
         Mainnet::ID => {
-            run_server::<Mainnet>(listen.into(), node, db_handler, scanner, operator).await?;
+            run_server::<Mainnet>(
+                listen.into(),
+                node,
+                db_handler,
+                scanner,
+                operator,
+                legacy_auth_until,
+                rate_limit_config,
+            )
+            .await?;
         }
         Testnet::ID => {
-            run_server::<Testnet>(listen.into(), node, db_handler, scanner, operator).await?;
+            run_server::<Testnet>(
+                listen.into(),
+                node,
+                db_handler,
+                scanner,
+                operator,
+                legacy_auth_until,
+                rate_limit_config,
+            )
+            .await?;
         }
         _ => panic!("Invalid network used"),
     }
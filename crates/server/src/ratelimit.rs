@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::Request,
+    middleware::Next,
+    response::IntoResponse,
+};
+use oreo_errors::OreoError;
+use tokio::{sync::oneshot, time::sleep};
+use tracing::info;
+
+use crate::SharedState;
+
+/// Routes which hit the node hard and get their own, smaller budget.
+pub const EXPENSIVE_ROUTES: [&str; 5] = [
+    "/rescan",
+    "/createTx",
+    "/import",
+    "/messages",
+    "/messages/send",
+];
+/// Routes called by the scanner and by probes, never limited.
+pub const EXEMPT_ROUTES: [&str; 2] = ["/updateScan", "/healthCheck"];
+/// Buckets untouched for this long are full again and can be dropped.
+pub const BUCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    /// Requests per minute for each account and each ip, 0 to disable.
+    pub requests_per_minute: u32,
+    /// Requests per minute to expensive routes for each account and each ip, 0 to disable.
+    pub expensive_per_minute: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 300,
+            expensive_per_minute: 20,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets refilled continuously, a full bucket allows a burst of one minute budget.
+#[derive(Debug)]
+pub struct RateLimiter {
+    pub config: RateLimitConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, key: &str, per_minute: u32) -> bool {
+        if per_minute == 0 {
+            return true;
+        }
+        let capacity = per_minute as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * capacity / 60.0;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn purge(&self) {
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| bucket.updated.elapsed() < BUCKET_IDLE_TIMEOUT);
+    }
+
+    pub async fn initialize_purger(limiter: Arc<RateLimiter>) {
+        let (router, waiter) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = router.send(());
+            loop {
+                sleep(BUCKET_IDLE_TIMEOUT).await;
+                limiter.purge();
+            }
+        });
+        let _ = waiter.await;
+        info!("Rate limiter purger installed!");
+    }
+}
+
+/// The account a request was authenticated for, set by the auth middleware.
+#[derive(Debug, Clone)]
+pub struct VerifiedAccount(pub String);
+
+fn tier(shared: &SharedState, req: &Request<Body>) -> (&'static str, u32) {
+    let config = shared.limiter.config;
+    match EXPENSIVE_ROUTES.contains(&req.uri().path()) {
+        true => ("expensive", config.expensive_per_minute),
+        false => ("default", config.requests_per_minute),
+    }
+}
+
+// Rate limit middleware function, keyed by client ip
+pub async fn rate_limit(
+    State(shared): State<Arc<SharedState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    if EXEMPT_ROUTES.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+    let (tier, budget) = tier(&shared, &req);
+    let key = format!("{}:ip:{}", tier, remote.ip());
+    if !shared.limiter.check(&key, budget) {
+        return OreoError::TooManyRequests.into_response();
+    }
+    next.run(req).await
+}
+
+// Rate limit middleware function, keyed by account, runs after auth
+pub async fn account_rate_limit(
+    State(shared): State<Arc<SharedState>>,
+    req: Request<Body>,
+    next: Next,
+) -> impl IntoResponse {
+    if let Some(VerifiedAccount(account)) = req.extensions().get::<VerifiedAccount>() {
+        let (tier, budget) = tier(&shared, &req);
+        let key = format!("{}:account:{}", tier, account);
+        if !shared.limiter.check(&key, budget) {
+            return OreoError::TooManyRequests.into_response();
+        }
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimitConfig, RateLimiter};
+
+    #[test]
+    fn bucket_should_limit_burst() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        for _ in 0..3 {
+            assert!(limiter.check("ip:127.0.0.1", 3));
+        }
+        assert!(!limiter.check("ip:127.0.0.1", 3));
+        assert!(limiter.check("ip:127.0.0.2", 3));
+        assert!(limiter.check("ip:127.0.0.1", 0));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use db_handler::{Account, DBHandler};
+use networking::rpc_abi::RpcRemoveAccountRequest;
+use params::network::Network;
+use tracing::{error, info, warn};
+
+use crate::{handlers::import_and_scan, ratelimit::RateLimitConfig, SharedState};
+
+/// Move accounts off node wallets which hold more than their share, or which are
+/// not configured anymore, into the least loaded configured node wallets.
+pub async fn rebalance_accounts<N: Network>(
+    rpc_server: Vec<String>,
+    db_handler: Box<dyn DBHandler + Send + Sync>,
+    scan: String,
+    operator: String,
+    dry_run: bool,
+) -> Result<()> {
+    let shared = SharedState::new(
+        db_handler,
+        rpc_server,
+        &scan,
+        operator,
+        N::ID,
+        None,
+        RateLimitConfig::default(),
+    );
+    shared.rpc_handler.check_health().await;
+    let nodes = shared.rpc_handler.nodes.len() as u32;
+    let mut loads: HashMap<u32, i64> = shared.db_handler.get_worker_loads().await?;
+    for worker_id in 0..nodes {
+        loads.entry(worker_id).or_insert(0);
+    }
+    let total: i64 = loads.values().sum();
+    let share = (total + nodes as i64 - 1) / nodes as i64;
+    info!(
+        "Rebalancing {} accounts over {} nodes, {:?}",
+        total, nodes, loads
+    );
+
+    let mut sources: Vec<(u32, i64)> = loads
+        .iter()
+        .filter_map(|(worker_id, load)| match *worker_id >= nodes {
+            true => Some((*worker_id, *load)),
+            false => (*load > share).then(|| (*worker_id, *load - share)),
+        })
+        .collect();
+    sources.sort();
+
+    for (source, surplus) in sources {
+        let accounts = shared.db_handler.get_worker_accounts(source).await?;
+        // accounts mid-scan get their results routed by worker id, they move on a later run
+        let accounts = accounts.into_iter().filter(|account| {
+            if account.need_scan {
+                info!("Skip account {} which is being scanned", account.address);
+            }
+            !account.need_scan
+        });
+        for account in accounts.take(surplus as usize) {
+            let target = {
+                let health = shared.rpc_handler.health.read().await;
+                (0..nodes)
+                    .filter(|id| health[*id as usize].healthy)
+                    .filter(|id| loads.get(id).cloned().unwrap_or(0) < share)
+                    .min_by_key(|id| loads.get(id).cloned().unwrap_or(0))
+            };
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    warn!("No healthy node wallet left to move accounts into");
+                    return Ok(());
+                }
+            };
+            info!(
+                "Move account {} from node {} to node {}",
+                account.address, source, target
+            );
+            if !dry_run {
+                if let Err(e) = move_account(&shared, account.clone(), target).await {
+                    error!("Failed to move account {}, {}", account.address, e);
+                    continue;
+                }
+            }
+            *loads.entry(source).or_insert(0) -= 1;
+            *loads.entry(target).or_insert(0) += 1;
+        }
+    }
+    info!("Rebalance done, {:?}", loads);
+    Ok(())
+}
+
+async fn move_account(shared: &SharedState, account: Account, target: u32) -> Result<()> {
+    let source = shared.wallet(&account).ok().cloned();
+    let mut moved = account.clone();
+    moved.worker_id = target as i32;
+    // route the account to its new node first, scan results posted from now on land there
+    shared
+        .db_handler
+        .update_account_worker(account.address.clone(), target)
+        .await?;
+    if let Err(e) = import_and_scan(shared, &moved).await {
+        if let Err(e) = shared
+            .db_handler
+            .update_account_worker(account.address.clone(), account.worker_id as u32)
+            .await
+        {
+            error!(
+                "Failed to route account {} back to node {}, {}",
+                account.address, account.worker_id, e
+            );
+        }
+        if let Ok(node) = shared.wallet(&moved) {
+            let _ = node
+                .remove_account(RpcRemoveAccountRequest {
+                    account: account.name.clone(),
+                    confirm: Some(true),
+                    wait: Some(true),
+                })
+                .await;
+        }
+        return Err(e.into());
+    }
+    // Node wallets which are not configured anymore are left untouched.
+    if let Some(source) = source {
+        if let Err(e) = source
+            .remove_account(RpcRemoveAccountRequest {
+                account: account.name.clone(),
+                confirm: Some(true),
+                wait: Some(true),
+            })
+            .await
+        {
+            warn!(
+                "Failed to remove account {} from old node {}, {}",
+                account.address, source.endpoint, e
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use networking::web_abi::ChallengeResponse;
+use oreo_errors::OreoError;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use utils::Signer;
+
+/// How long a login session token stays valid.
+pub const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long a login challenge can be answered.
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Claims carried by a session token, signed by the operator key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sid: String,
+    pub address: String,
+    pub exp: u64,
+}
+
+impl Claims {
+    pub fn new(address: String) -> Self {
+        Self {
+            sid: random_hex(16),
+            address,
+            exp: now() + SESSION_TTL.as_secs(),
+        }
+    }
+
+    /// Token format is `hex(json claims).signature`.
+    pub fn encode(&self, signer: &Signer) -> Result<String, OreoError> {
+        let payload =
+            serde_json::to_vec(self).map_err(|_| OreoError::SeralizeError(self.sid.clone()))?;
+        let signature = signer
+            .sign(self)
+            .map_err(|_| OreoError::SeralizeError(self.sid.clone()))?;
+        Ok(format!("{}.{}", hex::encode(payload), signature))
+    }
+
+    pub fn decode(token: &str, signer: &Signer) -> Result<Self, OreoError> {
+        let (payload, signature) = token.split_once('.').ok_or(OreoError::Unauthorized)?;
+        let payload = hex::decode(payload).map_err(|_| OreoError::Unauthorized)?;
+        let claims: Claims =
+            serde_json::from_slice(&payload).map_err(|_| OreoError::Unauthorized)?;
+        match signer.verify(&claims, signature.to_string()) {
+            Ok(true) if claims.exp > now() => Ok(claims),
+            _ => Err(OreoError::Unauthorized),
+        }
+    }
+}
+
+/// Outstanding login challenges and the legacy auth migration window.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    challenges: Mutex<HashMap<String, (String, u64)>>,
+    legacy_auth_until: Option<u64>,
+}
+
+impl Sessions {
+    pub fn new(legacy_auth_until: Option<u64>) -> Self {
+        Self {
+            challenges: Mutex::new(HashMap::new()),
+            legacy_auth_until,
+        }
+    }
+
+    /// Whether `Basic address:sha256(vk)` is still accepted.
+    pub fn legacy_auth_allowed(&self) -> bool {
+        match self.legacy_auth_until {
+            Some(until) => now() < until,
+            None => true,
+        }
+    }
+
+    pub fn issue_challenge(&self, address: String) -> ChallengeResponse {
+        let challenge = random_hex(32);
+        let expires_at = now() + CHALLENGE_TTL.as_secs();
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.retain(|_, (_, expires)| *expires > now());
+        challenges.insert(challenge.clone(), (address, expires_at));
+        ChallengeResponse {
+            challenge,
+            expires_at,
+        }
+    }
+
+    /// Challenges are single use, a failed answer burns it as well.
+    pub fn take_challenge(&self, challenge: &str, address: &str) -> Result<(), OreoError> {
+        match self.challenges.lock().unwrap().remove(challenge) {
+            Some((owner, expires_at)) if owner == address && expires_at > now() => Ok(()),
+            _ => Err(OreoError::Unauthorized),
+        }
+    }
+}
+
+/// Check `proof` is HMAC-SHA256(key = view key bytes, message = challenge bytes).
+pub fn verify_proof(vk: &str, challenge: &str, proof: &str) -> bool {
+    let (Ok(key), Ok(message), Ok(proof)) =
+        (hex::decode(vk), hex::decode(challenge), hex::decode(proof))
+    else {
+        return false;
+    };
+    let mut mac = match HmacSha256::new_from_slice(&key) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(&message);
+    mac.verify_slice(&proof).is_ok()
+}
+
+/// Whether a json request body targets no account or only `address`.
+pub fn targets_account(body: &[u8], address: &str) -> bool {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(fields)) => fields
+            .get("account")
+            .map_or(true, |account| account.as_str() == Some(address)),
+        _ => true,
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hmac::Mac;
+    use utils::Signer;
+
+    use super::{targets_account, verify_proof, Claims, HmacSha256, Sessions};
+
+    const VK: &str = "4ae4eb9606ba57b3b17a444100a9ac6453cd67e6fe4c860e63a2e18b1200978ab5ecce68e8639d5016cbe73b0ea9a3c8e906fc881af2e9ccfa7a7b63fb73d555";
+    const ADDRESS: &str = "d63ba13d7c35caf942c64d5139b948b885ec931977a3f248c13e7f3c1bd0aa64";
+    const OPERATOR: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+    #[test]
+    fn session_token_should_roundtrip() {
+        let signer = Signer::from_str(OPERATOR).unwrap();
+        let claims = Claims::new(ADDRESS.to_string());
+        let token = claims.encode(&signer).unwrap();
+        assert_eq!(Claims::decode(&token, &signer).unwrap(), claims);
+
+        let mut forged = claims.clone();
+        forged.exp += 1;
+        let signature = token.split_once('.').unwrap().1;
+        let forged = format!(
+            "{}.{}",
+            hex::encode(serde_json::to_vec(&forged).unwrap()),
+            signature
+        );
+        assert!(Claims::decode(&forged, &signer).is_err());
+    }
+
+    #[test]
+    fn token_should_only_reach_own_account() {
+        let body = |account: &str| format!(r#"{{"account":"{}","limit":10}}"#, account);
+        assert!(targets_account(body(ADDRESS).as_bytes(), ADDRESS));
+        assert!(!targets_account(body("other").as_bytes(), ADDRESS));
+        assert!(!targets_account(br#"{"account":1}"#, ADDRESS));
+        assert!(targets_account(br#"{"sid":"x"}"#, ADDRESS));
+        assert!(targets_account(b"", ADDRESS));
+    }
+
+    #[test]
+    fn challenge_should_be_single_use() {
+        let sessions = Sessions::new(None);
+        let challenge = sessions.issue_challenge(ADDRESS.to_string()).challenge;
+        let mut mac = HmacSha256::new_from_slice(&hex::decode(VK).unwrap()).unwrap();
+        mac.update(&hex::decode(&challenge).unwrap());
+        let proof = hex::encode(mac.finalize().into_bytes());
+        assert!(verify_proof(VK, &challenge, &proof));
+        assert!(sessions.take_challenge(&challenge, ADDRESS).is_ok());
+        assert!(sessions.take_challenge(&challenge, ADDRESS).is_err());
+    }
+}
@@ -10,9 +10,9 @@ pub struct Server {
     /// Specify the path to the db config file.
     #[clap(long)]
     pub dbconfig: String,
-    /// The Ironfish rpc node to connect to.
-    #[clap(short, long, default_value = "127.0.0.1:9092")]
-    pub node: String,
+    /// The Ironfish rpc nodes to connect to, separated by comma.
+    #[clap(short, long, default_value = "127.0.0.1:9092", value_delimiter = ',')]
+    pub node: Vec<String>,
     /// The scanner service to connect to.
     #[clap(long, default_value = "127.0.0.1:9093")]
     pub scanner: String,
@@ -22,6 +22,44 @@ pub struct Server {
     /// The operator secret key for signing messages.
     #[clap(long)]
     pub operator: String,
+    /// Unix timestamp until which legacy Basic auth is accepted, forever if unset.
+    #[clap(long)]
+    pub legacy_auth_until: Option<u64>,
+    /// Requests per minute allowed for each account and each client ip, 0 to disable.
+    #[clap(long, default_value = "300")]
+    pub rate_limit: u32,
+    /// Requests per minute to /rescan, /createTx, /import, /messages and /messages/send for each
+    /// account and each client ip, 0 to disable.
+    #[clap(long, default_value = "20")]
+    pub expensive_rate_limit: u32,
+    /// The ip:port to expose prometheus metrics on, disabled if unset.
+    #[clap(long)]
+    pub metrics: Option<SocketAddr>,
+    /// Specify the verbosity of the server [options: 0, 1, 2].
+    #[clap(short, long, default_value = "0")]
+    pub verbosity: u8,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct Rebalance {
+    /// Specify the path to the db config file.
+    #[clap(long)]
+    pub dbconfig: String,
+    /// The Ironfish rpc nodes to spread accounts over, separated by comma.
+    #[clap(short, long, default_value = "127.0.0.1:9092", value_delimiter = ',')]
+    pub node: Vec<String>,
+    /// The scanner service to connect to.
+    #[clap(long, default_value = "127.0.0.1:9093")]
+    pub scanner: String,
+    /// The network to work on, 0 for testnet, 1 for mainnet.
+    #[clap(long, default_value = "0")]
+    pub network: u8,
+    /// The operator secret key for signing messages.
+    #[clap(long)]
+    pub operator: String,
+    /// Only print the moves without touching any node wallet.
+    #[clap(long)]
+    pub dry_run: bool,
     /// Specify the verbosity of the server [options: 0, 1, 2].
     #[clap(short, long, default_value = "0")]
     pub verbosity: u8,
@@ -32,6 +70,9 @@ pub struct Prover {
     /// The ip:port prover will listen for incoming proof requests.
     #[clap(short, long, default_value = "0.0.0.0:10002")]
     pub listen: SocketAddr,
+    /// The ip:port to expose prometheus metrics on, disabled if unset.
+    #[clap(long)]
+    pub metrics: Option<SocketAddr>,
     /// Specify the verbosity of the prover [options: 0, 1, 2].
     #[clap(short, long, default_value = "0")]
     pub verbosity: u8,
@@ -48,9 +89,9 @@ pub struct Scanner {
     /// Specify the path to the db config file.
     #[clap(long)]
     pub dbconfig: String,
-    /// The Ironfish rpc node to connect to.
-    #[clap(short, long, default_value = "127.0.0.1:9092")]
-    pub node: String,
+    /// The Ironfish rpc nodes to connect to, separated by comma, in the same order as the server.
+    #[clap(short, long, default_value = "127.0.0.1:9092", value_delimiter = ',')]
+    pub node: Vec<String>,
     /// The oreowallet server to contribute to.
     #[clap(short, long, default_value = "127.0.0.1:9093")]
     pub server: String,
@@ -60,19 +101,90 @@ pub struct Scanner {
     /// The operator secret key for signing messages.
     #[clap(long)]
     pub operator: String,
+    /// Largest frame in bytes accepted from or sent to a worker.
+    #[clap(long, default_value = "16777216")]
+    pub max_frame_size: usize,
+    /// Hex encoded x25519 private key, worker connections are encrypted and authenticated once set.
+    /// Prefer `NOISE_KEY` to keep it out of the process list.
+    #[clap(long, env = "NOISE_KEY", hide_env_values = true)]
+    pub noise_key: Option<String>,
+    /// Hex encoded public keys of the workers allowed to connect, separated by comma.
+    #[clap(long, value_delimiter = ',')]
+    pub worker_keys: Vec<String>,
+    /// Accept plaintext, unauthenticated workers when no noise key is set, trusted networks only.
+    #[clap(long)]
+    pub insecure_transport: bool,
+    /// Have workers return the decrypted notes and index them per account.
+    #[clap(long)]
+    pub note_details: bool,
+    /// Fraction of tasks scanned by two workers whose results must agree, workers which
+    /// disagree are quarantined.
+    #[clap(long, default_value = "0")]
+    pub verify_fraction: f64,
+    /// Unique id of this scanner, set to share scan sessions with other scanners on the same db.
+    /// Sessions are leased to one scanner at a time and taken over once its lease expires.
+    #[clap(long)]
+    pub instance_id: Option<String>,
+    /// The ip:port to expose prometheus metrics on, disabled if unset.
+    #[clap(long)]
+    pub metrics: Option<SocketAddr>,
     /// Specify the verbosity of the server [options: 0, 1, 2].
     #[clap(short, long, default_value = "0")]
     pub verbosity: u8,
 }
 
+#[derive(Parser, Debug, Clone)]
+pub struct ChainLoader {
+    /// Specify the path to the db config file.
+    #[clap(long)]
+    pub dbconfig: String,
+    /// The Ironfish rpc nodes to load blocks from, separated by comma.
+    #[clap(short, long, default_value = "127.0.0.1:9092", value_delimiter = ',')]
+    pub node: Vec<String>,
+    /// The network to work on, 0 for testnet, 1 for mainnet.
+    #[clap(long, default_value = "0")]
+    pub network: u8,
+    /// The number of block batches fetched concurrently while backfilling.
+    #[clap(long, default_value = "8")]
+    pub parallelism: usize,
+    /// The ip:port to expose prometheus metrics on, disabled if unset.
+    #[clap(long)]
+    pub metrics: Option<SocketAddr>,
+    /// Specify the verbosity of the chain loader [options: 0, 1, 2].
+    #[clap(short, long, default_value = "0")]
+    pub verbosity: u8,
+}
+
 #[derive(Parser, Debug)]
 pub struct Worker {
-    /// Specify the scanner to contribute to.
+    /// Specify the scanner to contribute to, as host:port resolved again on each reconnect.
     #[clap(long)]
-    pub address: SocketAddr,
+    pub address: String,
     /// Specify worker name to identify this worker.
     #[clap(long)]
     pub name: Option<String>,
+    /// Tasks to take at once, defaults to the number of cpus.
+    #[clap(long)]
+    pub capacity: Option<usize>,
+    /// Largest frame in bytes accepted from or sent to the scanner.
+    #[clap(long, default_value = "16777216")]
+    pub max_frame_size: usize,
+    /// Talk newline delimited json, for scanners predating the binary protocol.
+    #[clap(long)]
+    pub legacy_protocol: bool,
+    /// Hex encoded x25519 private key, the connection to the scanner is encrypted once set.
+    /// Prefer `NOISE_KEY` to keep it out of the process list.
+    #[clap(long, env = "NOISE_KEY", hide_env_values = true)]
+    pub noise_key: Option<String>,
+    /// Hex encoded public key the scanner must prove to hold.
+    #[clap(long)]
+    pub scanner_key: Option<String>,
+    /// Talk plaintext to the scanner when no noise key is set, trusted networks only.
+    #[clap(long)]
+    pub insecure_transport: bool,
+    /// The ip:port to expose prometheus metrics on, disabled if unset.
+    #[clap(long)]
+    pub metrics: Option<SocketAddr>,
     /// Specify the verbosity of the server [options: 0, 1, 2].
     #[clap(short, long, default_value = "0")]
     pub verbosity: u8,
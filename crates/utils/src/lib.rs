@@ -1,4 +1,4 @@
-use std::{cmp, ops::Range, time::Duration};
+use std::{cmp, net::SocketAddr, ops::Range, time::Duration};
 
 mod cli;
 mod signer;
@@ -6,6 +6,8 @@ pub use clap::Parser;
 pub use cli::*;
 pub use signer::*;
 
+use metrics_exporter_prometheus::PrometheusBuilder;
+use metrics_util::MetricKindMask;
 use tokio::sync::oneshot;
 use tracing::{info, warn};
 pub use tracing_subscriber::EnvFilter;
@@ -42,6 +44,20 @@ pub fn initialize_logger_filter(filter: EnvFilter) {
     tracing_subscriber::fmt().with_env_filter(filter).init();
 }
 
+/// Gauges not updated for this long are dropped from the metrics endpoint.
+pub const METRICS_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub fn initialize_metrics(listen: Option<SocketAddr>) -> anyhow::Result<()> {
+    if let Some(listen) = listen {
+        PrometheusBuilder::new()
+            .with_http_listener(listen)
+            .idle_timeout(MetricKindMask::GAUGE, Some(METRICS_IDLE_TIMEOUT))
+            .install()?;
+        info!("Metrics exporter listening on {}", listen);
+    }
+    Ok(())
+}
+
 pub fn blocks_range(blocks: Range<u64>, batch: u64) -> Vec<Range<u64>> {
     let end = blocks.end;
     let mut result = vec![];
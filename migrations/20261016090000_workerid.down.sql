@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE
+    wallet.account DROP COLUMN worker_id CASCADE;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE
+    wallet.account
+ADD
+    COLUMN worker_id INTEGER NOT NULL DEFAULT 0;
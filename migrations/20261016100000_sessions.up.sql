@@ -0,0 +1,10 @@
+-- Add up migration script here
+CREATE TABLE wallet.sessions (
+    sid CHAR(32) NOT NULL,
+    address CHAR(64) NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked boolean NOT NULL DEFAULT false,
+    CONSTRAINT session_pkey PRIMARY KEY (sid)
+);
+
+CREATE INDEX session_address_idx ON wallet.sessions (address);
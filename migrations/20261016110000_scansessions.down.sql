@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE wallet.scan_results;
+DROP TABLE wallet.scan_sessions;
@@ -0,0 +1,20 @@
+-- Add up migration script here
+CREATE TABLE wallet.scan_sessions (
+    address CHAR(64) NOT NULL,
+    in_vk CHAR(64) NOT NULL,
+    out_vk CHAR(64) NOT NULL,
+    start_hash CHAR(64) NOT NULL,
+    start_sequence BIGINT NOT NULL,
+    end_hash CHAR(64),
+    end_sequence BIGINT,
+    scanned_sequence BIGINT NOT NULL,
+    CONSTRAINT scan_session_pkey PRIMARY KEY (address)
+);
+
+CREATE TABLE wallet.scan_results (
+    address CHAR(64) NOT NULL,
+    hash CHAR(64) NOT NULL,
+    sequence BIGINT NOT NULL,
+    transactions JSON NOT NULL,
+    CONSTRAINT scan_result_pkey PRIMARY KEY (address, hash)
+);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE
+    wallet.blocks DROP COLUMN previous_block_hash CASCADE;
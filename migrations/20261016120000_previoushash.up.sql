@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE
+    wallet.blocks
+ADD
+    COLUMN previous_block_hash CHAR(64);
@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE wallet.chain_progress (
+    id SMALLINT NOT NULL DEFAULT 0,
+    hash CHAR(64) NOT NULL,
+    sequence BIGINT NOT NULL,
+    CONSTRAINT chain_progress_pkey PRIMARY KEY (id),
+    CONSTRAINT chain_progress_single_row CHECK (id = 0)
+);
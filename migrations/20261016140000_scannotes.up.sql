@@ -0,0 +1,16 @@
+-- Add up migration script here
+CREATE TABLE wallet.scan_notes (
+    address CHAR(64) NOT NULL,
+    tx_hash CHAR(64) NOT NULL,
+    note_index INTEGER NOT NULL,
+    direction VARCHAR(8) NOT NULL,
+    block_hash CHAR(64) NOT NULL,
+    sequence BIGINT NOT NULL,
+    value BIGINT NOT NULL,
+    asset_id CHAR(64) NOT NULL,
+    memo CHAR(64) NOT NULL,
+    sender CHAR(64) NOT NULL,
+    CONSTRAINT scan_note_pkey PRIMARY KEY (address, tx_hash, note_index, direction)
+);
+
+CREATE INDEX scan_notes_address_sequence ON wallet.scan_notes (address, sequence);
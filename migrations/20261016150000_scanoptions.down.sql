@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE wallet.scan_sessions DROP COLUMN scan_options;
+ALTER TABLE wallet.account DROP COLUMN scan_options;
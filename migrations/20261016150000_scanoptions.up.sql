@@ -0,0 +1,10 @@
+-- Add up migration script here
+ALTER TABLE
+    wallet.account
+ADD
+    COLUMN scan_options JSON NOT NULL DEFAULT '{}';
+
+ALTER TABLE
+    wallet.scan_sessions
+ADD
+    COLUMN scan_options JSON NOT NULL DEFAULT '{}';
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE wallet.scan_sessions DROP COLUMN lease_expires;
+ALTER TABLE wallet.scan_sessions DROP COLUMN owner;
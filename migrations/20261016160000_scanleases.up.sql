@@ -0,0 +1,7 @@
+-- Add up migration script here
+ALTER TABLE
+    wallet.scan_sessions
+ADD
+    COLUMN owner VARCHAR(64),
+ADD
+    COLUMN lease_expires BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE wallet.scan_sessions DROP COLUMN completed_ranges;
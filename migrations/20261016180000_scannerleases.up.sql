@@ -0,0 +1,6 @@
+-- Add up migration script here
+CREATE TABLE wallet.scanner_leases (
+    name VARCHAR(64) PRIMARY KEY,
+    owner VARCHAR(64) NOT NULL,
+    lease_expires BIGINT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE
+    wallet.scan_sessions
+ADD
+    COLUMN revoked BOOLEAN NOT NULL DEFAULT false;